@@ -0,0 +1,59 @@
+use async_ucx::ucp::*;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use std::net::SocketAddr;
+use tokio::task::LocalSet;
+
+const PAYLOAD: usize = 64;
+
+fn am_small(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let local = LocalSet::new();
+
+    let context1 = Context::new().unwrap();
+    let worker1 = context1.create_worker().unwrap();
+    let context2 = Context::new().unwrap();
+    let worker2 = context2.create_worker().unwrap();
+    local.spawn_local(worker1.clone().polling());
+    local.spawn_local(worker2.clone().polling());
+
+    let (_endpoint1, endpoint2) = local.block_on(&rt, async {
+        let mut listener = worker1
+            .create_listener("0.0.0.0:0".parse().unwrap())
+            .unwrap();
+        let mut addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        addr.set_port(listener.socket_addr().unwrap().port());
+        tokio::join!(
+            async {
+                let conn = listener.next().await;
+                worker1.accept(conn).await.unwrap()
+            },
+            async { worker2.connect_socket(addr).await.unwrap() },
+        )
+    });
+
+    let stream = worker1.am_stream(16).unwrap();
+    let header = [0_u8; 8];
+    let data = [0_u8; PAYLOAD];
+
+    let mut group = c.benchmark_group("am");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("send_recv_64B", |b| {
+        b.iter(|| {
+            local.block_on(&rt, async {
+                endpoint2
+                    .am_send_flags(16, &header, &data, AmSendFlags::EAGER)
+                    .await
+                    .unwrap();
+                let msg = stream.wait_msg().await.unwrap();
+                black_box(msg.get_data());
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, am_small);
+criterion_main!(benches);
@@ -0,0 +1,91 @@
+//! Compares the queue behind an active message stream, `SegQueue` + `Notify`,
+//! with a `Mutex<VecDeque>` + `Notify` under a single producer and a single consumer.
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use crossbeam::queue::SegQueue;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+const MSGS: u64 = 100_000;
+
+// stand-in for a queued message, header and data inline
+type Msg = [u8; 64];
+
+trait Queue: Send + Sync + 'static {
+    fn new() -> Self;
+    fn push(&self, msg: Msg);
+    fn pop(&self) -> Option<Msg>;
+}
+
+impl Queue for SegQueue<Msg> {
+    fn new() -> Self {
+        SegQueue::new()
+    }
+
+    fn push(&self, msg: Msg) {
+        SegQueue::push(self, msg)
+    }
+
+    fn pop(&self) -> Option<Msg> {
+        SegQueue::pop(self)
+    }
+}
+
+impl Queue for Mutex<VecDeque<Msg>> {
+    fn new() -> Self {
+        Mutex::new(VecDeque::new())
+    }
+
+    fn push(&self, msg: Msg) {
+        self.lock().unwrap().push_back(msg)
+    }
+
+    fn pop(&self) -> Option<Msg> {
+        self.lock().unwrap().pop_front()
+    }
+}
+
+fn spsc<Q: Queue>(rt: &tokio::runtime::Runtime) {
+    let queue = Arc::new((Q::new(), Notify::new()));
+    let producer = {
+        let queue = queue.clone();
+        std::thread::spawn(move || {
+            for i in 0..MSGS {
+                queue.0.push([i as u8; 64]);
+                queue.1.notify_one();
+            }
+        })
+    };
+    rt.block_on(async {
+        let mut received = 0;
+        while received < MSGS {
+            let notified = queue.1.notified();
+            match queue.0.pop() {
+                Some(msg) => {
+                    black_box(msg);
+                    received += 1;
+                }
+                None => notified.await,
+            }
+        }
+    });
+    producer.join().unwrap();
+}
+
+fn am_queue(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("am_queue");
+    group.throughput(Throughput::Elements(MSGS));
+    group.bench_function(BenchmarkId::new("spsc", "seg_queue"), |b| {
+        b.iter(|| spsc::<SegQueue<Msg>>(&rt))
+    });
+    group.bench_function(BenchmarkId::new("spsc", "mutex_vec_deque"), |b| {
+        b.iter(|| spsc::<Mutex<VecDeque<Msg>>>(&rt))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, am_queue);
+criterion_main!(benches);
@@ -0,0 +1,106 @@
+use async_ucx::ucp::*;
+use serde::{Deserialize, Serialize};
+use std::io::Result;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Request {
+    id: u64,
+    user: User,
+    tags: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct User {
+    name: String,
+    age: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Response {
+    id: u64,
+    greeting: String,
+}
+
+const REQUEST_ID: u16 = 1;
+const RESPONSE_ID: u16 = 2;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let local = tokio::task::LocalSet::new();
+    if let Some(server_addr) = std::env::args().nth(1) {
+        local.run_until(client(server_addr)).await?;
+    } else {
+        local.run_until(server()).await?;
+    }
+    Ok(())
+}
+
+async fn client(server_addr: String) -> Result<()> {
+    println!("client: connect to {:?}", server_addr);
+    let context = Context::new().unwrap();
+    let worker = context.create_worker().unwrap();
+
+    #[cfg(not(feature = "event"))]
+    tokio::task::spawn_local(worker.clone().polling());
+    #[cfg(feature = "event")]
+    tokio::task::spawn_local(worker.clone().event_poll());
+
+    let endpoint = worker
+        .connect_socket(server_addr.parse().unwrap())
+        .await
+        .unwrap();
+    let responses = worker.am_stream(RESPONSE_ID).unwrap();
+
+    let request = Request {
+        id: 1,
+        user: User {
+            name: "alice".into(),
+            age: 30,
+        },
+        tags: vec!["admin".into(), "ops".into()],
+    };
+    println!("request: {:?}", request);
+    endpoint
+        .am_send_serde(REQUEST_ID as u32, &[], &request)
+        .await
+        .unwrap();
+
+    let mut msg = responses.wait_msg().await.unwrap();
+    let response: Response = msg.recv_serde().await.unwrap();
+    println!("response: {:?}", response);
+    Ok(())
+}
+
+async fn server() -> Result<()> {
+    println!("server");
+    let context = Context::new().unwrap();
+    let worker = context.create_worker().unwrap();
+
+    #[cfg(not(feature = "event"))]
+    tokio::task::spawn_local(worker.clone().polling());
+    #[cfg(feature = "event")]
+    tokio::task::spawn_local(worker.clone().event_poll());
+
+    let mut listener = worker
+        .create_listener("0.0.0.0:10000".parse().unwrap())
+        .unwrap();
+    println!("listening on {}", listener.socket_addr().unwrap());
+    let connection = listener.next().await;
+    let endpoint = worker.accept(connection).await.unwrap();
+    println!("accept");
+    let requests = worker.am_stream(REQUEST_ID).unwrap();
+
+    let mut msg = requests.wait_msg().await.unwrap();
+    let request: Request = msg.recv_serde().await.unwrap();
+    println!("request: {:?}", request);
+    let response = Response {
+        id: request.id,
+        greeting: format!("hello {} ({})", request.user.name, request.tags.join(", ")),
+    };
+    endpoint
+        .am_send_serde(RESPONSE_ID as u32, &[], &response)
+        .await
+        .unwrap();
+    Ok(())
+}
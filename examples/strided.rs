@@ -0,0 +1,92 @@
+use async_ucx::ucp::*;
+use std::io::Result;
+
+/// One column of a row-major `ROWS`x`COLS` matrix.
+struct Column {
+    matrix: Vec<f64>,
+    col: usize,
+}
+
+const ROWS: usize = 512;
+const COLS: usize = 64;
+const ELEM: usize = std::mem::size_of::<f64>();
+
+impl Datatype for Column {
+    fn packed_size(&self) -> usize {
+        ROWS * ELEM
+    }
+
+    fn pack(&self, offset: usize, dest: &mut [u8]) -> usize {
+        let mut len = 0;
+        while len < dest.len() && offset + len < self.packed_size() {
+            let pos = offset + len;
+            let elem = self.matrix[pos / ELEM * COLS + self.col].to_ne_bytes();
+            let n = (ELEM - pos % ELEM).min(dest.len() - len);
+            dest[len..len + n].copy_from_slice(&elem[pos % ELEM..pos % ELEM + n]);
+            len += n;
+        }
+        len
+    }
+
+    fn unpack(&mut self, offset: usize, src: &[u8]) {
+        let mut len = 0;
+        while len < src.len() {
+            let pos = offset + len;
+            let elem = &mut self.matrix[pos / ELEM * COLS + self.col];
+            let mut bytes = elem.to_ne_bytes();
+            let n = (ELEM - pos % ELEM).min(src.len() - len);
+            bytes[pos % ELEM..pos % ELEM + n].copy_from_slice(&src[len..len + n]);
+            *elem = f64::from_ne_bytes(bytes);
+            len += n;
+        }
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let local = tokio::task::LocalSet::new();
+    local.run_until(run()).await
+}
+
+async fn run() -> Result<()> {
+    let context1 = Context::new().unwrap();
+    let worker1 = context1.create_worker().unwrap();
+    let context2 = Context::new().unwrap();
+    let worker2 = context2.create_worker().unwrap();
+    tokio::task::spawn_local(worker1.clone().polling());
+    tokio::task::spawn_local(worker2.clone().polling());
+
+    let mut listener = worker1
+        .create_listener("0.0.0.0:0".parse().unwrap())
+        .unwrap();
+    let mut addr = listener.socket_addr().unwrap();
+    addr.set_ip("127.0.0.1".parse().unwrap());
+    let (_endpoint1, endpoint2) = tokio::join!(
+        async {
+            let conn = listener.next().await;
+            worker1.accept(conn).await.unwrap()
+        },
+        async { worker2.connect_socket(addr).await.unwrap() },
+    );
+
+    // send column 3 of one matrix into column 5 of another
+    let send = Column {
+        matrix: (0..ROWS * COLS).map(|x| x as f64).collect(),
+        col: 3,
+    };
+    let mut recv = Column {
+        matrix: vec![0.0; ROWS * COLS],
+        col: 5,
+    };
+    let (sent, received) = tokio::join!(
+        async { endpoint2.tag_send_generic(1, &send).await.unwrap() },
+        async { worker1.tag_recv_generic(1, &mut recv).await.unwrap() },
+    );
+    println!("sent {} bytes, received {} bytes", sent, received);
+    for row in 0..ROWS {
+        assert_eq!(recv.matrix[row * COLS + 5], send.matrix[row * COLS + 3]);
+    }
+    println!("column transferred");
+    Ok(())
+}
@@ -9,7 +9,7 @@ use ucx1_sys::UCS_PTR_IS_ERR;
 use ucx1_sys::UCS_PTR_RAW_STATUS;
 
 #[macro_use]
-extern crate log;
+extern crate tracing;
 
 #[cfg(test)]
 macro_rules! spawn_thread {
@@ -26,6 +26,8 @@ macro_rules! spawn_thread {
     };
 }
 
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 pub mod ucp;
 
 /// UCX error code.
@@ -97,11 +99,81 @@ pub enum Error {
     #[error("Endpoint timeout")]
     EndpointTimeout,
 
-    #[error("Unknown error")]
-    Unknown,
+    #[error("Active message header is too large")]
+    HeaderTooLarge,
+    #[error("Failed to serialize the message")]
+    Serialize,
+    #[error("Failed to deserialize the message")]
+    Deserialize,
+
+    #[error("Unknown error: {0:?}")]
+    Unknown(ucs_status_t),
+}
+
+/// Classification of an [`Error`], see [`Error::kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Out of resources or timed out for now, the operation may succeed if retried.
+    Transient,
+    /// The peer is gone, the endpoint should be closed.
+    PeerGone,
+    /// Any other error, retrying won't help.
+    Fatal,
 }
 
 impl Error {
+    /// Classify the error, e.g. to decide whether to retry the operation.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            // `Timeout` comes from deadlines such as those of
+            // `Worker::connect_timeout`, the operation may succeed with more time
+            Self::NoReource | Self::Busy | Self::Timeout => ErrorKind::Transient,
+            Self::ConnectionReset | Self::EndpointTimeout => ErrorKind::PeerGone,
+            _ => ErrorKind::Fatal,
+        }
+    }
+
+    /// The UCX status the error was created from, `None` for the errors
+    /// raised by this crate itself.
+    pub fn status(&self) -> Option<ucs_status_t> {
+        let status = match self {
+            Self::Inprogress => ucs_status_t::UCS_INPROGRESS,
+            Self::NoMessage => ucs_status_t::UCS_ERR_NO_MESSAGE,
+            Self::NoReource => ucs_status_t::UCS_ERR_NO_RESOURCE,
+            Self::IoError => ucs_status_t::UCS_ERR_IO_ERROR,
+            Self::NoMemory => ucs_status_t::UCS_ERR_NO_MEMORY,
+            Self::InvalidParam => ucs_status_t::UCS_ERR_INVALID_PARAM,
+            Self::Unreachable => ucs_status_t::UCS_ERR_UNREACHABLE,
+            Self::InvalidAddr => ucs_status_t::UCS_ERR_INVALID_ADDR,
+            Self::NotImplemented => ucs_status_t::UCS_ERR_NOT_IMPLEMENTED,
+            Self::MessageTruncated => ucs_status_t::UCS_ERR_MESSAGE_TRUNCATED,
+            Self::NoProgress => ucs_status_t::UCS_ERR_NO_PROGRESS,
+            Self::BufferTooSmall => ucs_status_t::UCS_ERR_BUFFER_TOO_SMALL,
+            Self::NoElem => ucs_status_t::UCS_ERR_NO_ELEM,
+            Self::SomeConnectsFailed => ucs_status_t::UCS_ERR_SOME_CONNECTS_FAILED,
+            Self::NoDevice => ucs_status_t::UCS_ERR_NO_DEVICE,
+            Self::Busy => ucs_status_t::UCS_ERR_BUSY,
+            Self::Canceled => ucs_status_t::UCS_ERR_CANCELED,
+            Self::ShmemSegment => ucs_status_t::UCS_ERR_SHMEM_SEGMENT,
+            Self::AlreadyExists => ucs_status_t::UCS_ERR_ALREADY_EXISTS,
+            Self::OutOfRange => ucs_status_t::UCS_ERR_OUT_OF_RANGE,
+            Self::Timeout => ucs_status_t::UCS_ERR_TIMED_OUT,
+            Self::ExceedsLimit => ucs_status_t::UCS_ERR_EXCEEDS_LIMIT,
+            Self::Unsupported => ucs_status_t::UCS_ERR_UNSUPPORTED,
+            Self::Rejected => ucs_status_t::UCS_ERR_REJECTED,
+            Self::NotConnected => ucs_status_t::UCS_ERR_NOT_CONNECTED,
+            Self::ConnectionReset => ucs_status_t::UCS_ERR_CONNECTION_RESET,
+            Self::FirstLinkFailure => ucs_status_t::UCS_ERR_FIRST_LINK_FAILURE,
+            Self::LastLinkFailure => ucs_status_t::UCS_ERR_LAST_LINK_FAILURE,
+            Self::FirstEndpointFailure => ucs_status_t::UCS_ERR_FIRST_ENDPOINT_FAILURE,
+            Self::EndpointTimeout => ucs_status_t::UCS_ERR_ENDPOINT_TIMEOUT,
+            Self::LastEndpointFailure => ucs_status_t::UCS_ERR_LAST_ENDPOINT_FAILURE,
+            Self::Unknown(status) => *status,
+            Self::HeaderTooLarge | Self::Serialize | Self::Deserialize => return None,
+        };
+        Some(status)
+    }
+
     // status != UCS_OK
     fn from_error(status: ucs_status_t) -> Self {
         debug_assert_ne!(status, ucs_status_t::UCS_OK);
@@ -140,7 +212,7 @@ impl Error {
             ucs_status_t::UCS_ERR_ENDPOINT_TIMEOUT => Self::EndpointTimeout,
             ucs_status_t::UCS_ERR_LAST_ENDPOINT_FAILURE => Self::LastEndpointFailure,
 
-            _ => Self::Unknown,
+            _ => Self::Unknown(status),
         }
     }
 
@@ -163,3 +235,47 @@ impl Error {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kind() {
+        let kind = |status| Error::from_error(status).kind();
+        assert_eq!(
+            kind(ucs_status_t::UCS_ERR_NO_RESOURCE),
+            ErrorKind::Transient
+        );
+        assert_eq!(kind(ucs_status_t::UCS_ERR_BUSY), ErrorKind::Transient);
+        assert_eq!(
+            kind(ucs_status_t::UCS_ERR_CONNECTION_RESET),
+            ErrorKind::PeerGone
+        );
+        assert_eq!(
+            kind(ucs_status_t::UCS_ERR_ENDPOINT_TIMEOUT),
+            ErrorKind::PeerGone
+        );
+        assert_eq!(kind(ucs_status_t::UCS_ERR_TIMED_OUT), ErrorKind::Transient);
+        assert_eq!(kind(ucs_status_t::UCS_ERR_UNREACHABLE), ErrorKind::Fatal);
+        assert_eq!(kind(ucs_status_t::UCS_ERR_INVALID_PARAM), ErrorKind::Fatal);
+        assert_eq!(kind(ucs_status_t::UCS_ERR_NO_MEMORY), ErrorKind::Fatal);
+        assert_eq!(Error::Deserialize.kind(), ErrorKind::Fatal);
+    }
+
+    #[test]
+    fn status() {
+        for status in [
+            ucs_status_t::UCS_ERR_NO_RESOURCE,
+            ucs_status_t::UCS_ERR_CONNECTION_RESET,
+            ucs_status_t::UCS_ERR_LAST,
+        ] {
+            assert_eq!(Error::from_error(status).status(), Some(status));
+        }
+        assert_eq!(
+            Error::from_error(ucs_status_t::UCS_ERR_LAST),
+            Error::Unknown(ucs_status_t::UCS_ERR_LAST)
+        );
+        assert_eq!(Error::HeaderTooLarge.status(), None);
+    }
+}
@@ -0,0 +1,234 @@
+//! Loopback connections, and fault injection for exercising failure handling
+//! without real network faults.
+
+#[cfg(feature = "test-util")]
+use crate::ucp::AmSendFlags;
+use crate::ucp::{Config, Context, Endpoint, Worker};
+use crate::Error;
+#[cfg(feature = "test-util")]
+use std::cell::Cell;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::rc::Rc;
+#[cfg(feature = "test-util")]
+use std::time::Duration;
+
+#[cfg(feature = "test-util")]
+thread_local! {
+    static ACCEPT_FAILURES: Cell<usize> = Cell::new(0);
+}
+
+/// Make the next `count` calls of [`Worker::accept`](crate::ucp::Worker::accept)
+/// on this thread fail with [`Error::NoReource`], rejecting the connection.
+///
+/// # Safety
+/// The listener that received a failed connection must still be alive when
+/// it is accepted.
+#[cfg(feature = "test-util")]
+pub unsafe fn fail_next_accepts(count: usize) {
+    ACCEPT_FAILURES.with(|failures| failures.set(count));
+}
+
+// whether to fail the accept being called
+#[cfg(feature = "test-util")]
+pub(crate) fn take_accept_failure() -> bool {
+    ACCEPT_FAILURES.with(|failures| {
+        let count = failures.get();
+        failures.set(count.saturating_sub(1));
+        count > 0
+    })
+}
+
+/// Connect two new workers, each of its own context, through a listener on
+/// the loopback interface.
+///
+/// Returns `(endpoint1, worker1, endpoint2, worker2)` where `endpoint1` on
+/// `worker1` is connected to `endpoint2` on `worker2`. Both workers are polled
+/// by tasks spawned with [`tokio::task::spawn_local`], so this must be called
+/// within a [`LocalSet`](tokio::task::LocalSet).
+pub async fn loopback() -> Result<(Endpoint, Rc<Worker>, Endpoint, Rc<Worker>), Error> {
+    let (endpoint1, worker1, endpoint2, worker2, _) = loopback_with_config(&Config::new()?).await?;
+    Ok((endpoint1, worker1, endpoint2, worker2))
+}
+
+/// Like [`loopback`], except that both contexts are created with `config`.
+///
+/// Also returns the address of the listener `endpoint2` connected to.
+pub async fn loopback_with_config(
+    config: &Config,
+) -> Result<(Endpoint, Rc<Worker>, Endpoint, Rc<Worker>, SocketAddr), Error> {
+    loopback_connect(config, |worker2, addr| async move {
+        worker2.connect_socket(addr).await
+    })
+    .await
+}
+
+// Like `loopback_with_config`, with `endpoint2` connected by `connect`,
+// e.g. through an `EndpointBuilder`.
+pub(crate) async fn loopback_connect<F, Fut>(
+    config: &Config,
+    connect: F,
+) -> Result<(Endpoint, Rc<Worker>, Endpoint, Rc<Worker>, SocketAddr), Error>
+where
+    F: FnOnce(Rc<Worker>, SocketAddr) -> Fut,
+    Fut: Future<Output = Result<Endpoint, Error>>,
+{
+    let worker1 = spawn_worker(config)?;
+    let worker2 = spawn_worker(config)?;
+
+    let mut listener = worker1.create_listener("0.0.0.0:0".parse().unwrap())?;
+    let mut addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    addr.set_port(listener.socket_addr()?.port());
+    let (endpoint1, endpoint2) = futures::join!(
+        async {
+            let conn1 = listener.next().await;
+            worker1.accept(conn1).await
+        },
+        connect(worker2.clone(), addr),
+    );
+    Ok((endpoint1?, worker1, endpoint2?, worker2, addr))
+}
+
+/// Create a worker of a new context with `config`, polled by a task spawned
+/// with [`tokio::task::spawn_local`].
+pub fn spawn_worker(config: &Config) -> Result<Rc<Worker>, Error> {
+    let worker = Context::new_with_config(config)?.create_worker()?;
+    tokio::task::spawn_local(worker.clone().polling());
+    Ok(worker)
+}
+
+/// An [`Endpoint`] wrapper that delays or drops active messages before they
+/// are handed to UCX.
+#[cfg(feature = "test-util")]
+#[derive(Debug, Clone)]
+pub struct FaultyEndpoint {
+    endpoint: Endpoint,
+    delay: Cell<Duration>,
+    drops: Cell<usize>,
+}
+
+#[cfg(feature = "test-util")]
+impl FaultyEndpoint {
+    /// Wrap `endpoint` without injecting any fault.
+    pub fn new(endpoint: Endpoint) -> Self {
+        FaultyEndpoint {
+            endpoint,
+            delay: Cell::new(Duration::ZERO),
+            drops: Cell::new(0),
+        }
+    }
+
+    /// Delay every following send by `delay`.
+    pub fn set_delay(&self, delay: Duration) {
+        self.delay.set(delay);
+    }
+
+    /// Silently drop the next `count` sends.
+    pub fn set_drop_next(&self, count: usize) {
+        self.drops.set(count);
+    }
+
+    /// Get the wrapped endpoint.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Like [`Endpoint::am_send_flags`], but subject to the injected faults.
+    pub async fn am_send(
+        &self,
+        id: u32,
+        header: &[u8],
+        data: &[u8],
+        flags: AmSendFlags,
+    ) -> Result<usize, Error> {
+        let delay = self.delay.get();
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        let drops = self.drops.get();
+        if drops > 0 {
+            trace!("am_send: drop injected, id={}", id);
+            self.drops.set(drops - 1);
+            return Ok(data.len());
+        }
+        self.endpoint.am_send_flags(id, header, data, flags).await
+    }
+
+    /// Like [`FaultyEndpoint::am_send`], but fails with [`Error::Timeout`]
+    /// if the send doesn't complete within `deadline`.
+    ///
+    /// Note that a timed out send is not canceled in UCX once it was submitted.
+    pub async fn am_send_deadline(
+        &self,
+        deadline: Duration,
+        id: u32,
+        header: &[u8],
+        data: &[u8],
+        flags: AmSendFlags,
+    ) -> Result<usize, Error> {
+        tokio::time::timeout(deadline, self.am_send(id, header, data, flags))
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "test-util")]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn deadline() {
+        spawn_thread!(_deadline()).join().unwrap();
+    }
+
+    async fn _deadline() {
+        let (_endpoint1, worker1, endpoint2, _) = loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let endpoint2 = FaultyEndpoint::new(endpoint2);
+        let header = vec![1, 2, 3, 4];
+        let data = vec![1_u8; 64];
+
+        // latency exceeds the deadline
+        endpoint2.set_delay(Duration::from_millis(200));
+        let result = endpoint2
+            .am_send_deadline(
+                Duration::from_millis(100),
+                16,
+                &header,
+                &data,
+                AmSendFlags::empty(),
+            )
+            .await;
+        assert_eq!(result, Err(Error::Timeout));
+
+        // dropped message never arrives, the next one does
+        endpoint2.set_delay(Duration::ZERO);
+        endpoint2.set_drop_next(1);
+        endpoint2
+            .am_send(16, &[0], &data, AmSendFlags::empty())
+            .await
+            .unwrap();
+        endpoint2
+            .am_send(16, &header, &data, AmSendFlags::empty())
+            .await
+            .unwrap();
+        let msg = stream1.wait_msg().await.expect("no msg");
+        assert_eq!(msg.header(), &header);
+    }
+
+    #[test_log::test]
+    fn accept_failure() {
+        spawn_thread!(_accept_failure()).join().unwrap();
+    }
+
+    async fn _accept_failure() {
+        // the client is refused instead of waiting forever
+        unsafe { fail_next_accepts(1) };
+        assert_eq!(loopback().await.err(), Some(Error::NoReource));
+
+        // only the next accept fails
+        assert!(loopback().await.is_ok());
+    }
+}
@@ -1,11 +1,22 @@
+#[cfg(feature = "serde")]
+use bincode::Options;
+#[cfg(feature = "bytes")]
+use bytes::{Buf, Bytes, BytesMut};
 use crossbeam::queue::SegQueue;
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+use smallvec::SmallVec;
 use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::instrument;
 
 use super::*;
 use std::{
+    collections::HashMap,
     io::{IoSlice, IoSliceMut},
+    ops::Deref,
     slice,
-    sync::atomic::AtomicBool,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
 };
 
 #[derive(Debug, PartialEq, Eq)]
@@ -15,8 +26,16 @@ pub enum AmDataType {
     Rndv,
 }
 
+// headers and eager data up to these sizes are stored inline in `RawMsg`,
+// so small messages don't touch the heap in the receive callback
+const INLINE_HEADER_LEN: usize = 32;
+const INLINE_DATA_LEN: usize = 64;
+
+type InlineHeader = SmallVec<[u8; INLINE_HEADER_LEN]>;
+type InlineData = SmallVec<[u8; INLINE_DATA_LEN]>;
+
 enum AmData {
-    Eager(Vec<u8>),
+    Eager(InlineData),
     Data(&'static [u8]),
     Rndv(&'static [u8]),
 }
@@ -30,7 +49,7 @@ impl AmData {
         } else if attr & ucp_am_recv_attr_t::UCP_AM_RECV_ATTR_FLAG_RNDV as u64 != 0 {
             Some(AmData::Rndv(data))
         } else {
-            Some(AmData::Eager(data.to_owned()))
+            Some(AmData::Eager(InlineData::from_slice(data)))
         }
     }
 
@@ -64,7 +83,7 @@ impl AmData {
 
 struct RawMsg {
     id: u16,
-    header: Vec<u8>,
+    header: InlineHeader,
     data: Option<AmData>,
     reply_ep: ucp_ep_h,
     attr: u64,
@@ -80,7 +99,7 @@ impl RawMsg {
     ) -> Self {
         RawMsg {
             id,
-            header: header.to_owned(),
+            header: InlineHeader::from_slice(header),
             data: AmData::from_raw(data, attr),
             reply_ep,
             attr,
@@ -89,13 +108,19 @@ impl RawMsg {
 }
 
 pub struct AmMsg<'a> {
-    worker: &'a Worker,
+    worker: &'a Rc<Worker>,
     msg: RawMsg,
+    // bytes of data already taken by `recv_data_chunk`
+    consumed: usize,
 }
 
 impl<'a> AmMsg<'a> {
-    fn from_raw(worker: &'a Worker, msg: RawMsg) -> Self {
-        AmMsg { worker, msg }
+    fn from_raw(worker: &'a Rc<Worker>, msg: RawMsg) -> Self {
+        AmMsg {
+            worker,
+            msg,
+            consumed: 0,
+        }
     }
 
     #[inline]
@@ -113,10 +138,19 @@ impl<'a> AmMsg<'a> {
         self.data_type().is_some()
     }
 
+    /// Where the data not received yet is held, `None` once it is all received.
     pub fn data_type(&self) -> Option<AmDataType> {
         self.msg.data.as_ref().map(|data| data.data_type())
     }
 
+    /// Attributes UCX delivered the message with.
+    ///
+    /// Unlike `data_type`, they don't change once the data is received.
+    #[inline]
+    pub fn recv_attrs(&self) -> AmRecvAttr {
+        AmRecvAttr::from_bits_truncate(self.msg.attr)
+    }
+
     #[inline]
     pub fn get_data(&self) -> Option<&[u8]> {
         self.msg.data.as_ref().and_then(|data| data.data())
@@ -128,22 +162,197 @@ impl<'a> AmMsg<'a> {
     }
 
     pub async fn recv_data(&mut self) -> Result<Vec<u8>, Error> {
+        self.check_unconsumed()?;
         match self.msg.data.take() {
             None => Ok(Vec::new()),
-            Some(AmData::Eager(vec)) => Ok(vec),
+            Some(AmData::Eager(data)) => Ok(data.into_vec()),
             Some(data) => {
                 self.msg.data = Some(data);
                 let mut buf = Vec::with_capacity(self.data_len());
+                let recv_size = self.recv_data_uninit(buf.spare_capacity_mut()).await?.len();
+                // SAFETY: `recv_data_uninit` initialized the first `recv_size` bytes
+                unsafe { buf.set_len(recv_size) };
+                Ok(buf)
+            }
+        }
+    }
+
+    /// Receive the data into `buf`, which needs not be initialized,
+    /// returning the initialized prefix holding the data.
+    ///
+    /// Fails with [`Error::MessageTruncated`] if `buf` is shorter than the data.
+    pub async fn recv_data_uninit<'b>(
+        &mut self,
+        buf: &'b mut [MaybeUninit<u8>],
+    ) -> Result<&'b mut [u8], Error> {
+        self.check_unconsumed()?;
+        // the data is kept, so the caller can retry with a larger buffer
+        if self.data_len() > buf.len() {
+            return Err(Error::MessageTruncated);
+        }
+        let len = match self.msg.data.take() {
+            None => 0,
+            Some(AmData::Eager(data)) => {
                 unsafe {
-                    buf.set_len(self.data_len());
+                    std::ptr::copy_nonoverlapping(data.as_ptr(), buf.as_mut_ptr() as _, data.len())
+                };
+                data.len()
+            }
+            Some(data) => {
+                let datatype = ucp_dt_make_contig(1);
+                self.recv_data_desc(data, buf.as_mut_ptr() as _, buf.len(), datatype)
+                    .await?
+            }
+        };
+        // SAFETY: the first `len` bytes were written above
+        Ok(unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, len) })
+    }
+
+    /// Take the data of a [`AmDataType::Data`] message without copying it.
+    ///
+    /// The data is held by UCX until the returned handle is dropped,
+    /// so it can outlive the message.
+    /// Returns `None` for other data types, which are left in the message,
+    /// and once part of the data was received by `recv_data_chunk`.
+    pub fn take_data_handle(&mut self) -> Option<AmDataHandle<'a>> {
+        if self.consumed > 0 {
+            return None;
+        }
+        match self.msg.data.take() {
+            Some(AmData::Data(data)) => Some(AmDataHandle {
+                worker: self.worker,
+                data,
+            }),
+            data => {
+                self.msg.data = data;
+                None
+            }
+        }
+    }
+
+    /// Length of the data not received by `recv_data_chunk` yet.
+    #[inline]
+    pub fn remaining_data_len(&self) -> usize {
+        self.msg
+            .data
+            .as_ref()
+            .map_or(0, |data| data.len() - self.consumed)
+    }
+
+    /// Receive the next part of the data into `buf`, returning its length.
+    ///
+    /// Calling it in a loop until it returns 0 copies the data out through a
+    /// fixed-size buffer. Fails with [`Error::InvalidParam`] if `buf` is
+    /// empty, so that 0 always means the end of the data.
+    ///
+    /// This doesn't receive rendezvous data in parts: `ucp_am_recv_data_nbx`
+    /// receives a descriptor in one operation and takes no offset, so
+    /// rendezvous data is fetched whole into memory on the first call and
+    /// handed out from there, after which `data_type` reports
+    /// [`AmDataType::Eager`].
+    ///
+    /// Once part of the data was received, the other `recv_data*` methods fail
+    /// with [`Error::InvalidParam`]; continue with `recv_data_chunk` or
+    /// `recv_data_fill`, or call `discard_data`.
+    pub async fn recv_data_chunk(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Err(Error::InvalidParam);
+        }
+        if let Some(AmData::Rndv(_)) = self.msg.data {
+            let data = self.recv_data().await?;
+            self.msg.data = Some(AmData::Eager(InlineData::from_vec(data)));
+        }
+        let data = match self.msg.data.as_ref().and_then(|data| data.data()) {
+            Some(data) => &data[self.consumed..],
+            None => return Ok(0),
+        };
+        let len = std::cmp::min(data.len(), buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        self.consumed += len;
+        if self.remaining_data_len() == 0 {
+            self.release_data();
+            self.consumed = 0;
+        }
+        Ok(len)
+    }
+
+    // Fails once `recv_data_chunk` handed out part of the data
+    #[inline]
+    fn check_unconsumed(&self) -> Result<(), Error> {
+        if self.consumed > 0 {
+            return Err(Error::InvalidParam);
+        }
+        Ok(())
+    }
+
+    /// Drop the data without receiving it, e.g. after inspecting the header.
+    ///
+    /// Descriptors held by UCX are released right away instead of when the
+    /// message is dropped, and a rendezvous sender completes without its data
+    /// being transferred.
+    pub fn discard_data(&mut self) {
+        self.release_data();
+        self.consumed = 0;
+    }
+
+    /// Receive the data into `iov` until the buffers are full or the data is
+    /// exhausted, returning the total length received.
+    ///
+    /// Unlike `recv_data_vectored`, data beyond the capacity of `iov` is not an
+    /// error, it is left for further `recv_data_fill` or `recv_data_chunk` calls.
+    pub async fn recv_data_fill(&mut self, iov: &mut [IoSliceMut<'_>]) -> Result<usize, Error> {
+        // rendezvous data that fits is received in place
+        let cap = iov.iter().map(|buf| buf.len()).sum::<usize>();
+        if let Some(AmData::Rndv(data)) = &self.msg.data {
+            if data.len() <= cap {
+                return self.recv_data_vectored(iov).await;
+            }
+        }
+        let mut total = 0;
+        for buf in iov.iter_mut() {
+            let mut filled = 0;
+            while filled < buf.len() {
+                let len = self.recv_data_chunk(&mut buf[filled..]).await?;
+                if len == 0 {
+                    return Ok(total);
                 }
-                let recv_size = self.recv_data_single(&mut buf).await?;
-                buf.truncate(recv_size);
-                Ok(buf)
+                filled += len;
+                total += len;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Like `recv_data`, except that the data is returned as [`Bytes`].
+    #[cfg(feature = "bytes")]
+    pub async fn recv_data_bytes(&mut self) -> Result<Bytes, Error> {
+        self.check_unconsumed()?;
+        match self.msg.data.take() {
+            None => Ok(Bytes::new()),
+            Some(AmData::Eager(data)) => Ok(Bytes::from(data.into_vec())),
+            Some(data) => {
+                self.msg.data = Some(data);
+                let mut buf = BytesMut::with_capacity(self.data_len());
+                let recv_size = self.recv_data_uninit(buf.spare_capacity_mut()).await?.len();
+                // SAFETY: `recv_data_uninit` initialized the first `recv_size` bytes
+                unsafe { buf.set_len(recv_size) };
+                Ok(buf.freeze())
             }
         }
     }
 
+    /// Receive the data and deserialize it, see [`Endpoint::am_send_serde`].
+    ///
+    /// Fails with [`Error::Deserialize`] if the data isn't exactly a `T`.
+    #[cfg(feature = "serde")]
+    pub async fn recv_serde<T: DeserializeOwned>(&mut self) -> Result<T, Error> {
+        let data = self.recv_data().await?;
+        bincode_options().deserialize(&data).map_err(|err| {
+            warn!("recv_serde: {}", err);
+            Error::Deserialize
+        })
+    }
+
     pub async fn recv_data_single(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
         if !self.contains_data() {
             Ok(0)
@@ -153,7 +362,31 @@ impl<'a> AmMsg<'a> {
         }
     }
 
+    /// Like `recv_data_vectored`, except that it returns the number of bytes
+    /// received into each buffer of `iov`.
+    ///
+    /// Buffers are filled in order, so only the last non-empty one may be partially filled.
+    pub async fn recv_data_scatter(&mut self, iov: &[IoSliceMut<'_>]) -> Result<Vec<usize>, Error> {
+        let mut remaining = self.recv_data_vectored(iov).await?;
+        Ok(iov
+            .iter()
+            .map(|buf| {
+                let len = std::cmp::min(remaining, buf.len());
+                remaining -= len;
+                len
+            })
+            .collect())
+    }
+
+    #[instrument(
+        level = "trace",
+        skip_all,
+        fields(worker = ?self.worker.handle, id = self.msg.id, request = next_request_id()),
+        ret,
+        err(level = "debug")
+    )]
     pub async fn recv_data_vectored(&mut self, iov: &[IoSliceMut<'_>]) -> Result<usize, Error> {
+        self.check_unconsumed()?;
         let data = self.msg.data.take();
         if let Some(data) = data {
             if let AmData::Eager(data) = data {
@@ -183,82 +416,243 @@ impl<'a> AmMsg<'a> {
                 return Ok(copied);
             }
 
-            let (data_desc, data_len) = match data {
-                AmData::Data(data) => (data.as_ptr(), data.len()),
-                AmData::Rndv(data) => (data.as_ptr(), data.len()),
-                _ => unreachable!(),
-            };
-
-            unsafe extern "C" fn callback(
-                request: *mut c_void,
-                status: ucs_status_t,
-                _length: usize,
-                _data: *mut c_void,
-            ) {
-                // todo: handle error & fix real data length
-                trace!(
-                    "recv_data_vectored: complete, req={:?}, status={:?}",
-                    request,
-                    status
-                );
-                let request = &mut *(request as *mut Request);
-                request.waker.wake();
-            }
             trace!(
                 "recv_data_vectored: worker={:?} iov.len={}",
                 self.worker.handle,
                 iov.len()
             );
-            let mut param = MaybeUninit::<ucp_request_param_t>::uninit();
-            let (buffer, count) = unsafe {
-                let param = &mut *param.as_mut_ptr();
-                param.op_attr_mask = ucp_op_attr_t::UCP_OP_ATTR_FIELD_CALLBACK as u32
-                    | ucp_op_attr_t::UCP_OP_ATTR_FIELD_DATATYPE as u32;
-                param.cb = ucp_request_param_t__bindgen_ty_1 {
-                    recv_am: Some(callback),
-                };
+            let (buffer, count, datatype) = if iov.len() == 1 {
+                (iov[0].as_ptr(), iov[0].len(), ucp_dt_make_contig(1))
+            } else {
+                let datatype = ucp_dt_type::UCP_DATATYPE_IOV as _;
+                (iov.as_ptr() as _, iov.len(), datatype)
+            };
+            self.recv_data_desc(data, buffer as _, count, datatype)
+                .await
+        } else {
+            // no data
+            Ok(0)
+        }
+    }
 
-                if iov.len() == 1 {
-                    param.datatype = ucp_dt_make_contig(1);
-                    (iov[0].as_ptr(), iov[0].len())
-                } else {
-                    param.datatype = ucp_dt_type::UCP_DATATYPE_IOV as _;
-                    (iov.as_ptr() as _, iov.len())
-                }
+    // Receive the data of a `Data` or `Rndv` message into `count` elements
+    // of `datatype` at `buffer`, returning the data length.
+    async fn recv_data_desc(
+        &self,
+        data: AmData,
+        buffer: *mut c_void,
+        count: usize,
+        datatype: ucp_datatype_t,
+    ) -> Result<usize, Error> {
+        let (data_desc, data_len) = match data {
+            AmData::Data(data) => (data.as_ptr(), data.len()),
+            AmData::Rndv(data) => (data.as_ptr(), data.len()),
+            _ => unreachable!(),
+        };
+
+        unsafe extern "C" fn callback(
+            request: *mut c_void,
+            status: ucs_status_t,
+            _length: usize,
+            _data: *mut c_void,
+        ) {
+            // todo: handle error & fix real data length
+            trace!(
+                "recv_data_vectored: complete, req={:?}, status={:?}",
+                request,
+                status
+            );
+            let request = &mut *(request as *mut Request);
+            request.waker.wake();
+        }
+        let mut param = MaybeUninit::<ucp_request_param_t>::uninit();
+        unsafe {
+            let param = &mut *param.as_mut_ptr();
+            param.op_attr_mask = ucp_op_attr_t::UCP_OP_ATTR_FIELD_CALLBACK as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_DATATYPE as u32;
+            param.cb = ucp_request_param_t__bindgen_ty_1 {
+                recv_am: Some(callback),
             };
+            param.datatype = datatype;
+        }
 
-            let status = unsafe {
-                ucp_am_recv_data_nbx(
-                    self.worker.handle,
-                    data_desc as _,
-                    buffer as _,
-                    count as _,
-                    param.as_ptr(),
-                )
+        let status = unsafe {
+            ucp_am_recv_data_nbx(
+                self.worker.handle,
+                data_desc as _,
+                buffer,
+                count as _,
+                param.as_ptr(),
+            )
+        };
+        if status.is_null() {
+            trace!("recv_data_vectored: complete");
+            Ok(data_len)
+        } else if UCS_PTR_IS_PTR(status) {
+            RequestHandle::new(self.worker, status, poll_recv).await;
+            Ok(data_len)
+        } else {
+            Err(Error::from_ptr(status).unwrap_err())
+        }
+    }
+
+    /// Receive the data unpacking it into `data` through its [`Datatype`] implementation.
+    ///
+    /// Returns the length of the packed data. Fails with [`Error::MessageTruncated`]
+    /// if it exceeds `data.packed_size()`.
+    pub async fn recv_data_dt<T: Datatype>(&mut self, data: &mut T) -> Result<usize, Error> {
+        self.check_unconsumed()?;
+        if self.data_len() > data.packed_size() {
+            return Err(Error::MessageTruncated);
+        }
+        let (data_desc, data_len) = match self.msg.data.take() {
+            None => return Ok(0),
+            Some(AmData::Eager(eager)) => {
+                data.unpack(0, &eager);
+                return Ok(eager.len());
+            }
+            Some(AmData::Data(desc)) | Some(AmData::Rndv(desc)) => (desc.as_ptr(), desc.len()),
+        };
+
+        unsafe extern "C" fn callback(
+            request: *mut c_void,
+            status: ucs_status_t,
+            _length: usize,
+            _data: *mut c_void,
+        ) {
+            trace!(
+                "recv_data_dt: complete, req={:?}, status={:?}",
+                request,
+                status
+            );
+            let request = &mut *(request as *mut Request);
+            request.waker.wake();
+        }
+        trace!(
+            "recv_data_dt: worker={:?} len={}",
+            self.worker.handle,
+            data_len
+        );
+        let datatype = generic_datatype::<T>()?;
+        let mut param = MaybeUninit::<ucp_request_param_t>::uninit();
+        unsafe {
+            let param = &mut *param.as_mut_ptr();
+            param.op_attr_mask = ucp_op_attr_t::UCP_OP_ATTR_FIELD_CALLBACK as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_DATATYPE as u32;
+            param.cb = ucp_request_param_t__bindgen_ty_1 {
+                recv_am: Some(callback),
             };
-            if status.is_null() {
-                trace!("recv_data_vectored: complete");
-                Ok(data_len)
-            } else if UCS_PTR_IS_PTR(status) {
-                RequestHandle {
-                    ptr: status,
-                    poll_fn: poll_recv,
+            param.datatype = datatype;
+        }
+        let status = unsafe {
+            ucp_am_recv_data_nbx(
+                self.worker.handle,
+                data_desc as _,
+                data as *mut T as _,
+                1,
+                param.as_ptr(),
+            )
+        };
+        if status.is_null() {
+            trace!("recv_data_dt: complete");
+            Ok(data_len)
+        } else if UCS_PTR_IS_PTR(status) {
+            RequestHandle::new(self.worker, status, poll_normal).await?;
+            Ok(data_len)
+        } else {
+            Err(Error::from_ptr(status).unwrap_err())
+        }
+    }
+
+    /// Receive the data into registered memory `mem` starting at `offset`.
+    ///
+    /// Passing the memory handle and its type lets UCX write rendezvous data
+    /// straight into it, which is the zero-copy path for device memory.
+    /// Eager data has already arrived in host memory, so it is only accepted
+    /// for host `mem` and fails with [`Error::Unsupported`] otherwise;
+    /// send with [`AmSendFlags::RNDV`] to receive into device memory.
+    /// Fails with [`Error::OutOfRange`] if the data doesn't fit.
+    ///
+    /// # Safety
+    /// The region registered by `mem` must stay valid, and must not be accessed
+    /// otherwise, until the receive completes.
+    pub async unsafe fn recv_data_into_mem(
+        &mut self,
+        mem: &MemoryHandle,
+        offset: usize,
+    ) -> Result<usize, Error> {
+        self.check_unconsumed()?;
+        let buf = mem.ptr_at(offset, self.data_len())?;
+        let (data_desc, data_len) = match self.msg.data.take() {
+            None => return Ok(0),
+            Some(AmData::Eager(data)) => {
+                if mem.mem_type != ucs_memory_type::UCS_MEMORY_TYPE_HOST {
+                    self.msg.data = Some(AmData::Eager(data));
+                    return Err(Error::Unsupported);
                 }
-                .await;
-                Ok(data_len)
-            } else {
-                Err(Error::from_ptr(status).unwrap_err())
+                unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len()) };
+                return Ok(data.len());
             }
+            Some(AmData::Data(data)) | Some(AmData::Rndv(data)) => (data.as_ptr(), data.len()),
+        };
+
+        unsafe extern "C" fn callback(
+            request: *mut c_void,
+            status: ucs_status_t,
+            _length: usize,
+            _data: *mut c_void,
+        ) {
+            trace!(
+                "recv_data_into_mem: complete, req={:?}, status={:?}",
+                request,
+                status
+            );
+            let request = &mut *(request as *mut Request);
+            request.waker.wake();
+        }
+        trace!(
+            "recv_data_into_mem: worker={:?} offset={} len={}",
+            self.worker.handle,
+            offset,
+            data_len
+        );
+        let mut param = MaybeUninit::<ucp_request_param_t>::uninit();
+        unsafe {
+            let param = &mut *param.as_mut_ptr();
+            param.op_attr_mask = ucp_op_attr_t::UCP_OP_ATTR_FIELD_CALLBACK as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_DATATYPE as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_MEMH as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_MEMORY_TYPE as u32;
+            param.cb = ucp_request_param_t__bindgen_ty_1 {
+                recv_am: Some(callback),
+            };
+            param.datatype = ucp_dt_make_contig(1);
+            param.memh = mem.handle;
+            param.memory_type = mem.mem_type;
+        }
+        let status = unsafe {
+            ucp_am_recv_data_nbx(
+                self.worker.handle,
+                data_desc as _,
+                buf as _,
+                data_len as _,
+                param.as_ptr(),
+            )
+        };
+        if status.is_null() {
+            trace!("recv_data_into_mem: complete");
+            Ok(data_len)
+        } else if UCS_PTR_IS_PTR(status) {
+            RequestHandle::new(self.worker, status, poll_normal).await?;
+            Ok(data_len)
         } else {
-            // no data
-            Ok(0)
+            Err(Error::from_ptr(status).unwrap_err())
         }
     }
 
     #[inline]
     pub fn need_reply(&self) -> bool {
-        self.msg.attr & (ucp_am_recv_attr_t::UCP_AM_RECV_ATTR_FIELD_REPLY_EP as u64) != 0
-            && !self.msg.reply_ep.is_null()
+        self.recv_attrs().contains(AmRecvAttr::REPLY_EP) && !self.msg.reply_ep.is_null()
     }
 
     /// Send reply
@@ -269,13 +663,12 @@ impl<'a> AmMsg<'a> {
         id: u32,
         header: &[u8],
         data: &[u8],
-        need_reply: bool,
-        proto: Option<AmProto>,
-    ) -> Result<(), Error> {
+        flags: AmSendFlags,
+    ) -> Result<usize, Error> {
         // todo: we should prevent endpoint from being freed
         //       currently, ucx doesn't provide such function.
         assert!(self.need_reply());
-        self.reply_vectorized(id, header, &[IoSlice::new(data)], need_reply, proto)
+        self.reply_vectorized(id, header, &[IoSlice::new(data)], flags)
             .await
     }
 
@@ -287,16 +680,241 @@ impl<'a> AmMsg<'a> {
         id: u32,
         header: &[u8],
         data: &[IoSlice<'_>],
-        need_reply: bool,
-        proto: Option<AmProto>,
-    ) -> Result<(), Error> {
+        flags: AmSendFlags,
+    ) -> Result<usize, Error> {
+        assert!(self.need_reply());
+        self.worker
+            .send_reply(self.msg.reply_ep, id, header, data, flags)
+            .await
+    }
+
+    /// Get the endpoint of the peer which sent this message.
+    ///
+    /// Unlike [`AmMsg::reply`], the endpoint can be used for any number of
+    /// replies, RMA or tag operations back to the peer. It is owned by UCX and
+    /// borrowed by the returned reference, which can't outlive the message.
+    /// Returns `None` if the sender didn't ask for a reply.
+    /// # Safety
+    /// User needs to ensure that the endpoint isn't closed,
+    /// and must not close it through the returned reference.
+    pub unsafe fn reply_endpoint(&self) -> Option<EndpointRef<'_>> {
+        if self.need_reply() {
+            Some(EndpointRef::new(self.worker, self.msg.reply_ep))
+        } else {
+            None
+        }
+    }
+
+    /// Send reply with the `id` and header of this message.
+    ///
+    /// This suits RPCs which carry a correlation token in the header.
+    /// # Safety
+    /// User needs to ensure that the endpoint isn't closed.
+    pub async unsafe fn reply_echo(&self, data: &[u8]) -> Result<usize, Error> {
+        self.reply(self.id() as u32, self.header(), data, AmSendFlags::empty())
+            .await
+    }
+
+    /// Send reply serializing `value` as the data, see [`AmMsg::recv_serde`].
+    ///
+    /// Fails with [`Error::Serialize`] if `value` can't be serialized.
+    /// # Safety
+    /// User needs to ensure that the endpoint isn't closed.
+    #[cfg(feature = "serde")]
+    pub async unsafe fn reply_serde<T: Serialize>(
+        &self,
+        id: u32,
+        header: &[u8],
+        value: &T,
+    ) -> Result<usize, Error> {
+        let data = bincode_options().serialize(value).map_err(|err| {
+            warn!("reply_serde: {}", err);
+            Error::Serialize
+        })?;
+        self.reply(id, header, &data, AmSendFlags::empty()).await
+    }
+
+    /// Strip the reply target advertised by [`Endpoint::am_send_reply_to`] from the header.
+    ///
+    /// After this call `header()` returns the header given by the sender.
+    /// Connect to the returned address with [`Worker::connect_addr`] to reply.
+    /// # Safety
+    /// User needs to ensure that the sender advertised a valid worker address.
+    pub unsafe fn take_reply_addr(&mut self) -> Result<ExternalWorkerAddress, Error> {
+        let header = &self.msg.header;
+        if header.len() < REPLY_ADDR_LEN_SIZE {
+            return Err(Error::InvalidParam);
+        }
+        let mut len = [0_u8; REPLY_ADDR_LEN_SIZE];
+        len.copy_from_slice(&header[..REPLY_ADDR_LEN_SIZE]);
+        let end = REPLY_ADDR_LEN_SIZE + u32::from_le_bytes(len) as usize;
+        if header.len() < end {
+            return Err(Error::InvalidParam);
+        }
+        let addr = header[REPLY_ADDR_LEN_SIZE..end].to_owned();
+        self.msg.header.drain(..end);
+        Ok(ExternalWorkerAddress::new(addr))
+    }
+
+    /// Receive the data and detach the message from the worker borrow,
+    /// e.g. to move it into a spawned task.
+    pub async fn into_owned(mut self) -> Result<OwnedAmMsg, Error> {
+        let data = self.recv_data().await?;
+        Ok(OwnedAmMsg {
+            worker: self.worker.clone(),
+            id: self.msg.id,
+            header: self.msg.header.to_vec(),
+            data,
+            reply_ep: self.msg.reply_ep,
+            attr: self.msg.attr,
+        })
+    }
+}
+
+/// An active message with its data received, see [`AmMsg::into_owned`].
+pub struct OwnedAmMsg {
+    worker: Rc<Worker>,
+    id: u16,
+    header: Vec<u8>,
+    data: Vec<u8>,
+    reply_ep: ucp_ep_h,
+    attr: u64,
+}
+
+impl OwnedAmMsg {
+    #[inline]
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    #[inline]
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Take the data, leaving it empty.
+    pub fn take_data(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.data)
+    }
+
+    /// The worker the message was received on.
+    pub fn worker(&self) -> &Rc<Worker> {
+        &self.worker
+    }
+
+    #[inline]
+    pub fn need_reply(&self) -> bool {
+        let attr = AmRecvAttr::from_bits_truncate(self.attr);
+        attr.contains(AmRecvAttr::REPLY_EP) && !self.reply_ep.is_null()
+    }
+
+    /// Send reply, like [`AmMsg::reply`].
+    /// # Safety
+    /// User needs to ensure that the endpoint isn't closed.
+    pub async unsafe fn reply(
+        &self,
+        id: u32,
+        header: &[u8],
+        data: &[u8],
+        flags: AmSendFlags,
+    ) -> Result<usize, Error> {
         assert!(self.need_reply());
-        am_send(self.msg.reply_ep, id, header, data, need_reply, proto).await
+        let data = [IoSlice::new(data)];
+        self.worker
+            .send_reply(self.reply_ep, id, header, &data, flags)
+            .await
     }
 }
 
-impl<'a> Drop for AmMsg<'a> {
-    fn drop(&mut self) {
+/// Endpoint borrowed from UCX, see [`AmMsg::reply_endpoint`].
+///
+/// It offers the sends of [`Endpoint`] on the raw UCX handle. Unlike an
+/// `Endpoint` it takes no send permit, so `max_inflight_sends` doesn't
+/// apply, and errors are not recorded on the `Endpoint` the connection was
+/// accepted as.
+pub struct EndpointRef<'a> {
+    worker: &'a Worker,
+    handle: ucp_ep_h,
+}
+
+impl<'a> EndpointRef<'a> {
+    fn new(worker: &'a Worker, handle: ucp_ep_h) -> Self {
+        EndpointRef { worker, handle }
+    }
+
+    /// Like [`Endpoint::am_send`].
+    pub async fn am_send(&self, id: u32, header: &[u8], data: &[u8]) -> Result<usize, Error> {
+        self.am_send_flags(id, header, data, AmSendFlags::empty())
+            .await
+    }
+
+    /// Like [`Endpoint::am_send_flags`].
+    pub async fn am_send_flags(
+        &self,
+        id: u32,
+        header: &[u8],
+        data: &[u8],
+        flags: AmSendFlags,
+    ) -> Result<usize, Error> {
+        let data = [IoSlice::new(data)];
+        self.am_send_vectorized(id, header, &data, flags).await
+    }
+
+    /// Like [`Endpoint::am_send_vectorized`].
+    pub async fn am_send_vectorized(
+        &self,
+        id: u32,
+        header: &[u8],
+        data: &[IoSlice<'_>],
+        flags: AmSendFlags,
+    ) -> Result<usize, Error> {
+        am_send(self.worker, self.handle, id, header, data, flags).await
+    }
+
+    /// Like [`Endpoint::tag_send`].
+    pub async fn tag_send(&self, tag: u64, buf: &[u8]) -> Result<usize, Error> {
+        let iov = [IoSlice::new(buf)];
+        super::tag::tag_send(self.worker, self.handle, tag, &iov).await
+    }
+
+    /// Like [`Endpoint::tag_send_vectored`].
+    pub async fn tag_send_vectored(&self, tag: u64, iov: &[IoSlice<'_>]) -> Result<usize, Error> {
+        super::tag::tag_send(self.worker, self.handle, tag, iov).await
+    }
+
+    /// Like [`Endpoint::stream_send`].
+    pub async fn stream_send(&self, buf: &[u8]) -> Result<usize, Error> {
+        super::stream::stream_send(self.worker, self.handle, buf).await
+    }
+
+    /// Like [`Endpoint::put`].
+    pub async fn put(&self, buf: &[u8], remote_addr: u64, rkey: &RKey) -> Result<(), Error> {
+        super::rma::put(self.worker, self.handle, buf, remote_addr, rkey).await
+    }
+
+    /// Like [`Endpoint::get`].
+    pub async fn get(&self, buf: &mut [u8], remote_addr: u64, rkey: &RKey) -> Result<(), Error> {
+        super::rma::get(self.worker, self.handle, buf, remote_addr, rkey).await
+    }
+}
+
+// encoding of `am_send_serde`, trailing bytes mean a different type was sent
+#[cfg(feature = "serde")]
+fn bincode_options() -> impl bincode::Options {
+    bincode::DefaultOptions::new().reject_trailing_bytes()
+}
+
+// length prefix of the reply address carried by `am_send_reply_to`
+const REPLY_ADDR_LEN_SIZE: usize = std::mem::size_of::<u32>();
+
+impl<'a> AmMsg<'a> {
+    // drop the data, releasing descriptors held by UCX
+    fn release_data(&mut self) {
         match self.msg.data.take() {
             Some(AmData::Data(desc)) => unsafe {
                 ucp_am_data_release(self.worker.handle, desc.as_ptr() as _);
@@ -309,73 +927,185 @@ impl<'a> Drop for AmMsg<'a> {
     }
 }
 
+impl<'a> Drop for AmMsg<'a> {
+    fn drop(&mut self) {
+        self.release_data();
+    }
+}
+
+/// Data of an active message held by UCX, see [`AmMsg::take_data_handle`].
+///
+/// The data is released back to UCX on drop.
+pub struct AmDataHandle<'a> {
+    worker: &'a Worker,
+    data: &'static [u8],
+}
+
+impl<'a> Deref for AmDataHandle<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl<'a> Drop for AmDataHandle<'a> {
+    fn drop(&mut self) {
+        unsafe { ucp_am_data_release(self.worker.handle, self.data.as_ptr() as _) };
+    }
+}
+
 #[derive(Clone)]
 pub struct AmStream<'a> {
-    worker: &'a Worker,
+    worker: &'a Rc<Worker>,
     inner: Rc<AmStreamInner>,
 }
 
 impl<'a> AmStream<'a> {
-    fn new(worker: &'a Worker, inner: Rc<AmStreamInner>) -> Self {
+    fn new(worker: &'a Rc<Worker>, inner: Rc<AmStreamInner>) -> Self {
         AmStream { worker, inner }
     }
 
     /// Wait active message.
-    pub async fn wait_msg(&self) -> Option<AmMsg<'_>> {
+    pub async fn wait_msg(&self) -> Option<AmMsg<'a>> {
         self.inner.wait_msg(self.worker).await
     }
 }
 
-pub(crate) struct AmStreamInner {
-    id: u16,
-    msgs: SegQueue<RawMsg>,
-    notify: Notify,
-    unregistered: AtomicBool,
+/// Counters of active messages, see [`Worker::stats`].
+#[derive(Debug, Clone, Default)]
+pub struct WorkerStats {
+    /// Number of messages sent.
+    pub am_sent: u64,
+    /// Number of header and data bytes sent.
+    pub am_sent_bytes: u64,
+    /// Number of messages received.
+    pub am_received: u64,
+    /// Number of header and data bytes received.
+    pub am_received_bytes: u64,
+    /// Number of sends waiting for completion.
+    pub am_pending_sends: u64,
+    /// Number of messages waiting in the queue of each registered stream.
+    pub am_queue_depths: HashMap<u16, usize>,
+    /// Number of times a reply waited for earlier replies to be flushed,
+    /// see [`Worker::set_am_reply_window`].
+    pub am_reply_flushes: u64,
+    /// Number of replies sent since their endpoint was last flushed.
+    pub am_unflushed_replies: usize,
 }
 
-impl AmStreamInner {
-    // new active message handler
-    fn new(id: u16) -> Self {
-        Self {
-            id,
-            msgs: SegQueue::new(),
+// Counters shared by a worker and its streams
+#[derive(Debug, Default)]
+pub(crate) struct AmCounters {
+    sent: AtomicU64,
+    sent_bytes: AtomicU64,
+    received: AtomicU64,
+    received_bytes: AtomicU64,
+    pending_sends: AtomicU64,
+    reply_flushes: AtomicU64,
+}
+
+impl AmCounters {
+    fn add_sent(&self, len: usize) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        self.sent_bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    fn add_received(&self, len: usize) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.received_bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+}
+
+pub(crate) struct AmInlineHandler {
+    counters: Rc<AmCounters>,
+    handler: Box<dyn Fn(&[u8], Option<&[u8]>)>,
+}
+
+pub(crate) struct AmStreamInner {
+    id: u16,
+    counters: Rc<AmCounters>,
+    msgs: SegQueue<RawMsg>,
+    notify: Notify,
+    unregistered: AtomicBool,
+}
+
+impl AmStreamInner {
+    // new active message handler
+    fn new(id: u16, counters: Rc<AmCounters>) -> Self {
+        Self {
+            id,
+            counters,
+            msgs: SegQueue::new(),
             notify: Notify::new(),
             unregistered: AtomicBool::new(false),
         }
     }
 
-    // unregister
+    // unregister and wake up parked receivers
     fn unregister(&self) {
         self.unregistered
             .store(true, std::sync::atomic::Ordering::SeqCst);
+        self.notify.notify_waiters();
     }
 
     // callback function
     fn callback(&self, header: &[u8], data: &'static [u8], reply: ucp_ep_h, attr: u64) {
+        self.counters.add_received(header.len() + data.len());
         let msg = RawMsg::from_raw(self.id, header, data, reply, attr);
         self.msgs.push(msg);
         self.notify.notify_one();
     }
 
     /// Wait active message.
-    async fn wait_msg<'a>(&self, worker: &'a Worker) -> Option<AmMsg<'a>> {
-        // todo: how to make this thread safe?
-        while !self.unregistered.load(std::sync::atomic::Ordering::Relaxed) {
+    async fn wait_msg<'a>(&self, worker: &'a Rc<Worker>) -> Option<AmMsg<'a>> {
+        loop {
+            // register interest before checking, so `notify_waiters` in `unregister`
+            // can't slip in between the check and the await
+            let notified = self.notify.notified();
             if let Some(msg) = self.msgs.pop() {
                 return Some(AmMsg::from_raw(worker, msg));
             }
+            if self.unregistered.load(std::sync::atomic::Ordering::SeqCst) {
+                return None;
+            }
 
-            self.notify.notified().await;
+            notified.await;
         }
+    }
+
+    // like `wait_msg`, except that it gives up once `token` is cancelled
+    async fn wait_msg_cancelable<'a>(
+        &self,
+        worker: &'a Rc<Worker>,
+        token: &CancellationToken,
+    ) -> Option<AmMsg<'a>> {
+        loop {
+            let notified = self.notify.notified();
+            // a queued message wins over a concurrent cancellation
+            if let Some(msg) = self.msgs.pop() {
+                return Some(AmMsg::from_raw(worker, msg));
+            }
+            if self.unregistered.load(std::sync::atomic::Ordering::SeqCst) || token.is_cancelled() {
+                return None;
+            }
 
-        self.msgs.pop().map(|msg| AmMsg::from_raw(worker, msg))
+            futures::future::select(Box::pin(notified), Box::pin(token.cancelled())).await;
+        }
     }
 }
 
 impl Worker {
     /// Register active message stream for `id`.
     /// Message of this `id` can be received with `am_recv`.
-    pub fn am_stream(&self, id: u16) -> Result<AmStream<'_>, Error> {
+    ///
+    /// Messages are queued without a bound until taken. The queue allocates
+    /// small blocks on demand and frees them as it drains, so an idle stream
+    /// holds no queue memory and needs no capacity tuning.
+    ///
+    /// Messages are always queued whole: UCX reassembles eager messages the
+    /// transport splits into fragments before handing them over.
+    pub fn am_stream(self: &Rc<Self>, id: u16) -> Result<AmStream<'_>, Error> {
         if let Some(inner) = self.am_streams.read().unwrap().get(&id) {
             return Ok(AmStream::new(self, inner.clone()));
         }
@@ -404,7 +1134,7 @@ impl Worker {
             }
         }
 
-        let stream = Rc::new(AmStreamInner::new(id));
+        let stream = Rc::new(AmStreamInner::new(id, self.am_counters.clone()));
         unsafe {
             self.am_register(id, Some(callback), Rc::as_ptr(&stream) as _)?;
         }
@@ -413,6 +1143,197 @@ impl Worker {
         return Ok(AmStream::new(self, stream));
     }
 
+    /// Wait active message of any registered stream.
+    ///
+    /// The `id()` of the returned message tells which stream it came from.
+    /// Streams are served round-robin, so a busy `id` doesn't starve others.
+    /// Returns `None` if no stream is registered.
+    pub async fn am_recv_any(self: &Rc<Self>) -> Option<AmMsg<'_>> {
+        loop {
+            let mut streams: Vec<_> = self.am_streams.read().unwrap().values().cloned().collect();
+            if streams.is_empty() {
+                return None;
+            }
+            streams.sort_unstable_by_key(|stream| stream.id);
+
+            // start after the stream served last time
+            let start = match self.am_any_last.get() {
+                Some(last) => streams.partition_point(|stream| stream.id <= last),
+                None => 0,
+            };
+            for i in 0..streams.len() {
+                let stream = &streams[(start + i) % streams.len()];
+                if let Some(msg) = stream.msgs.pop() {
+                    self.am_any_last.set(Some(stream.id));
+                    return Some(AmMsg::from_raw(self, msg));
+                }
+            }
+
+            // `notify_one` stores a permit if nobody is waiting,
+            // so a message pushed after the check above still wakes us
+            let notified = streams
+                .iter()
+                .map(|stream| Box::pin(stream.notify.notified()));
+            futures::future::select_all(notified).await;
+        }
+    }
+
+    /// Take a queued active message of `id` without waiting.
+    ///
+    /// Returns `None` if no message is queued or no stream is registered
+    /// for `id`. Each message is taken exactly once, also when `wait_msg`
+    /// is called concurrently on the same stream.
+    pub fn am_try_recv(self: &Rc<Self>, id: u16) -> Option<AmMsg<'_>> {
+        let stream = self.am_streams.read().unwrap().get(&id)?.clone();
+        let msg = stream.msgs.pop()?;
+        Some(AmMsg::from_raw(self, msg))
+    }
+
+    /// Like `AmStream::wait_msg` on the stream of `id`, except that it
+    /// returns `None` once `token` is cancelled.
+    ///
+    /// A message already queued is still returned after cancellation.
+    /// Returns `None` if no stream is registered for `id`.
+    pub async fn am_recv_cancelable(
+        self: &Rc<Self>,
+        id: u16,
+        token: CancellationToken,
+    ) -> Option<AmMsg<'_>> {
+        let stream = self.am_streams.read().unwrap().get(&id)?.clone();
+        stream.wait_msg_cancelable(self, &token).await
+    }
+
+    // fail early instead of letting UCX reject the send
+    fn check_am_header(&self, header: &[u8]) -> Result<(), Error> {
+        if header.len() > self.max_am_header {
+            return Err(Error::HeaderTooLarge);
+        }
+        Ok(())
+    }
+
+    /// Counters of active messages on this worker.
+    ///
+    /// They are maintained with relaxed atomics, so reading them is cheap.
+    pub fn stats(&self) -> WorkerStats {
+        let counters = &self.am_counters;
+        WorkerStats {
+            am_sent: counters.sent.load(Ordering::Relaxed),
+            am_sent_bytes: counters.sent_bytes.load(Ordering::Relaxed),
+            am_received: counters.received.load(Ordering::Relaxed),
+            am_received_bytes: counters.received_bytes.load(Ordering::Relaxed),
+            am_pending_sends: counters.pending_sends.load(Ordering::Relaxed),
+            am_queue_depths: (self.am_streams.read().unwrap().iter())
+                .map(|(id, stream)| (*id, stream.msgs.len()))
+                .collect(),
+            am_reply_flushes: counters.reply_flushes.load(Ordering::Relaxed),
+            am_unflushed_replies: self.am_unflushed_replies.borrow().values().sum(),
+        }
+    }
+
+    /// Limit the replies sent to one endpoint without a flush to `window`.
+    ///
+    /// Once `window` replies were sent to an endpoint, the next reply
+    /// flushes the endpoint first, so a slow peer holds back its replies
+    /// instead of letting them pile up. Unlimited (`None`) by default.
+    pub fn set_am_reply_window(&self, window: Option<usize>) {
+        self.am_reply_window.set(window);
+    }
+
+    // send a reply to `ep` within the reply window
+    async fn send_reply(
+        &self,
+        ep: ucp_ep_h,
+        id: u32,
+        header: &[u8],
+        data: &[IoSlice<'_>],
+        flags: AmSendFlags,
+    ) -> Result<usize, Error> {
+        self.take_reply_credit(ep).await?;
+        let result = am_send(self, ep, id, header, data, flags).await;
+        if result.is_err() {
+            // the peer is likely gone, stop counting replies to it
+            self.am_unflushed_replies
+                .borrow_mut()
+                .remove(&(ep as usize));
+        }
+        result
+    }
+
+    // count a reply to `ep`, waiting for a flush if the window is used up
+    async fn take_reply_credit(&self, ep: ucp_ep_h) -> Result<(), Error> {
+        let window = match self.am_reply_window.get() {
+            Some(window) => window,
+            None => return Ok(()),
+        };
+        let key = ep as usize;
+        let sent = self.am_unflushed_replies.borrow().get(&key).copied();
+        if sent.unwrap_or(0) >= window {
+            self.am_counters
+                .reply_flushes
+                .fetch_add(1, Ordering::Relaxed);
+            let flushed = flush_ep(self, ep).await;
+            self.am_unflushed_replies.borrow_mut().remove(&key);
+            flushed?;
+        }
+        *self
+            .am_unflushed_replies
+            .borrow_mut()
+            .entry(key)
+            .or_default() += 1;
+        Ok(())
+    }
+
+    /// Handle active messages of `id` with `handler`, without queuing them.
+    ///
+    /// `handler` is called synchronously inside worker progress with the
+    /// header and data, borrowed only for the call, so nothing is copied.
+    /// It must not block or await anything; hand work off to a task instead.
+    /// Rendezvous data can't be read inline, so it is dropped and
+    /// `handler` gets `None` for it.
+    ///
+    /// Replaces the stream or handler registered for `id`.
+    pub fn am_register_inline<F>(&self, id: u16, handler: F) -> Result<(), Error>
+    where
+        F: Fn(&[u8], Option<&[u8]>) + 'static,
+    {
+        unsafe extern "C" fn callback(
+            arg: *mut c_void,
+            header: *const c_void,
+            header_len: usize,
+            data: *mut c_void,
+            data_len: usize,
+            param: *const ucp_am_recv_param_t,
+        ) -> ucs_status_t {
+            // `handler` may replace or unregister itself while it runs,
+            // so hold a reference of our own until it returns
+            let handler = arg as *const AmInlineHandler;
+            Rc::increment_strong_count(handler);
+            let handler = Rc::from_raw(handler);
+            let header = slice::from_raw_parts(header as *const u8, header_len as usize);
+            let data = slice::from_raw_parts(data as *const u8, data_len as usize);
+            handler.counters.add_received(header.len() + data.len());
+
+            let rndv = (*param).recv_attr & ucp_am_recv_attr_t::UCP_AM_RECV_ATTR_FLAG_RNDV as u64;
+            if rndv != 0 {
+                (handler.handler)(header, None);
+            } else {
+                (handler.handler)(header, Some(data));
+            }
+            // UCX releases data descriptors itself on `UCS_OK`
+            ucs_status_t::UCS_OK
+        }
+
+        let handler = Rc::new(AmInlineHandler {
+            counters: self.am_counters.clone(),
+            handler: Box::new(handler),
+        });
+        unsafe {
+            self.am_register(id, Some(callback), Rc::as_ptr(&handler) as _)?;
+        }
+        self.am_inline_handlers.write().unwrap().insert(id, handler);
+        Ok(())
+    }
+
     /// Register active message handler for `id`.
     /// # Safety
     /// This method is not concurrent safe with `Worker::polling` or `Worker::event_poll`
@@ -421,6 +1342,117 @@ impl Worker {
         id: u16,
         cb: ucp_am_recv_callback_t,
         arg: *mut c_void,
+    ) -> Result<(), Error> {
+        self.set_am_recv_handler(id, cb, arg)?;
+        if let Some(stream) = self.am_streams.write().unwrap().remove(&id) {
+            stream.unregister();
+        }
+        self.am_inline_handlers.write().unwrap().remove(&id);
+
+        Ok(())
+    }
+
+    /// Unregister all active message streams.
+    ///
+    /// Pending `wait_msg` and `am_recv_any` calls return `None`
+    /// once queued messages are drained.
+    pub fn shutdown(&self) {
+        self.am_unregister_all();
+    }
+
+    /// Detach all active message streams from UCX and release them.
+    pub(crate) fn am_unregister_all(&self) {
+        let streams = std::mem::take(&mut *self.am_streams.write().unwrap());
+        for (id, stream) in streams {
+            // incoming messages of `id` are dropped by UCX from now on
+            let status = unsafe { self.set_am_recv_handler(id, None, null_mut()) };
+            let _ = status.map_err(|err| error!("Failed to unregister am {}, {}", id, err));
+            stream.unregister();
+        }
+        let handlers = std::mem::take(&mut *self.am_inline_handlers.write().unwrap());
+        for id in handlers.keys() {
+            let status = unsafe { self.set_am_recv_handler(*id, None, null_mut()) };
+            let _ = status.map_err(|err| error!("Failed to unregister am {}, {}", id, err));
+        }
+    }
+
+    /// Send the same active message to each of `endpoints`, which belong to
+    /// this worker.
+    ///
+    /// All sends are submitted before awaiting any of them, so the fan-out
+    /// overlaps, subject to the [`max_inflight`](EndpointBuilder::max_inflight)
+    /// of each endpoint. A failed send doesn't stop the others, the first
+    /// error is returned once all of them complete.
+    /// Returns [`Error::InvalidParam`] without sending if an endpoint belongs
+    /// to another worker.
+    pub async fn am_broadcast(
+        &self,
+        endpoints: &[Endpoint],
+        id: u32,
+        header: &[u8],
+        data: &[u8],
+    ) -> Result<(), Error> {
+        self.check_am_header(header)?;
+        if !endpoints
+            .iter()
+            .all(|endpoint| std::ptr::eq(&**endpoint.worker(), self))
+        {
+            return Err(Error::InvalidParam);
+        }
+        let counters = &self.am_counters;
+        let data = [IoSlice::new(data)];
+        let len = header.len() + data[0].len();
+        let mut requests = MultiRequest::new();
+        let mut result = Ok(());
+        for endpoint in endpoints {
+            let handle = match endpoint.get_handle() {
+                Ok(handle) => handle,
+                Err(err) => {
+                    result = result.and(Err(err));
+                    continue;
+                }
+            };
+            let permit = requests.send_permit(endpoint).await;
+            let status = unsafe {
+                am_send_nbx(
+                    handle,
+                    id,
+                    header,
+                    &data,
+                    AmSendFlags::empty(),
+                    null_mut(),
+                    null_mut(),
+                )
+            };
+            if status.is_null() {
+                counters.add_sent(len);
+            } else if UCS_PTR_IS_PTR(status) {
+                requests.push(RequestHandle::new(self, status, poll_normal), permit);
+            } else {
+                result = result.and(Error::from_ptr(status));
+            }
+        }
+        trace!("am_broadcast: submitted, pending={}", requests.len());
+
+        // buffers are borrowed until all submitted requests complete
+        let pending = requests.len() as u64;
+        counters.pending_sends.fetch_add(pending, Ordering::Relaxed);
+        let statuses = requests.await;
+        counters.pending_sends.fetch_sub(pending, Ordering::Relaxed);
+        for status in statuses {
+            if status.is_ok() {
+                counters.add_sent(len);
+            }
+            result = result.and(status);
+        }
+        result
+    }
+
+    unsafe fn set_am_recv_handler(
+        &self,
+        id: u16,
+        cb: ucp_am_recv_callback_t,
+        arg: *mut c_void,
     ) -> Result<(), Error> {
         let param = ucp_am_handler_param_t {
             id: id as _,
@@ -433,159 +1465,747 @@ impl Worker {
             flags: 0,
         };
         let status = ucp_worker_set_am_recv_handler(self.handle, &param as _);
-        Error::from_status(status)?;
-        if let Some(stream) = self.am_streams.write().unwrap().remove(&id) {
-            stream.unregister();
-        }
-
-        Ok(())
+        Error::from_status(status)
     }
 }
 
 impl Endpoint {
-    pub async fn am_send(
+    /// Send an active message.
+    ///
+    /// Returns the number of data bytes sent, which is always `data.len()` on success.
+    ///
+    /// Messages sent on the same endpoint reach the receiver's handler in the
+    /// order they were submitted, whether they go eager or rendezvous.
+    /// A send is submitted when its future is first polled, so sends driven
+    /// concurrently are ordered by their first poll, not by completion.
+    ///
+    /// If the transport is out of send resources, the send waits for them
+    /// instead of failing with [`Error::NoReource`].
+    pub async fn am_send(&self, id: u32, header: &[u8], data: &[u8]) -> Result<usize, Error> {
+        self.am_send_flags(id, header, data, AmSendFlags::empty())
+            .await
+    }
+
+    /// Like `am_send`, except that the send behavior is controlled by `flags`.
+    pub async fn am_send_flags(
         &self,
         id: u32,
         header: &[u8],
         data: &[u8],
-        need_reply: bool,
-        proto: Option<AmProto>,
-    ) -> Result<(), Error> {
+        flags: AmSendFlags,
+    ) -> Result<usize, Error> {
         let data = [IoSlice::new(data)];
-        self.am_send_vectorized(id, header, &data, need_reply, proto)
-            .await
+        self.am_send_vectorized(id, header, &data, flags).await
     }
 
-    pub async fn am_send_vectorized(
+    /// Like `am_send`, except that the first `header_len` bytes of `combined`
+    /// are the header and the rest is the data, for small messages kept in a
+    /// single buffer.
+    ///
+    /// Fails with [`Error::InvalidParam`] if `header_len` exceeds `combined`.
+    pub async fn am_send_inline(
         &self,
         id: u32,
-        header: &[u8],
-        data: &[IoSlice<'_>],
-        need_reply: bool,
-        proto: Option<AmProto>,
-    ) -> Result<(), Error> {
-        let endpoint = self.get_handle()?;
-        am_send(endpoint, id, header, data, need_reply, proto).await
+        combined: &[u8],
+        header_len: usize,
+    ) -> Result<usize, Error> {
+        if header_len > combined.len() {
+            return Err(Error::InvalidParam);
+        }
+        let (header, data) = combined.split_at(header_len);
+        self.am_send(id, header, data).await
     }
-}
 
-pub enum AmProto {
-    Eager,
-    Rndv,
-}
+    /// Like `am_send_flags`, except that it reads from the chunks of `data`.
+    ///
+    /// `data` is copied into one buffer if it doesn't expose all its chunks
+    /// at once through `Buf::chunks_vectored`.
+    #[cfg(feature = "bytes")]
+    pub async fn am_send_buf(
+        &self,
+        id: u32,
+        header: &[u8],
+        mut data: impl Buf,
+        flags: AmSendFlags,
+    ) -> Result<usize, Error> {
+        let remaining = data.remaining();
+        let mut iov = Vec::new();
+        let mut count = 16;
+        loop {
+            iov.resize(count, IoSlice::new(&[]));
+            let filled = data.chunks_vectored(&mut iov);
+            if iov[..filled].iter().map(|v| v.len()).sum::<usize>() == remaining {
+                iov.truncate(filled);
+                return self.am_send_vectorized(id, header, &iov, flags).await;
+            }
+            if filled < iov.len() {
+                // more room won't help, e.g. the default `chunks_vectored`
+                break;
+            }
+            count *= 2;
+        }
+        let data = data.copy_to_bytes(remaining);
+        self.am_send_flags(id, header, &data, flags).await
+    }
 
-async fn am_send(
-    endpoint: ucp_ep_h,
-    id: u32,
-    header: &[u8],
-    data: &[IoSlice<'_>],
-    need_reply: bool,
-    proto: Option<AmProto>,
-) -> Result<(), Error> {
-    unsafe extern "C" fn callback(request: *mut c_void, _status: ucs_status_t, _data: *mut c_void) {
-        trace!("am_send: complete");
-        let request = &mut *(request as *mut Request);
-        request.waker.wake();
+    /// Send an active message carrying `value` serialized with bincode.
+    ///
+    /// Receive it with [`AmMsg::recv_serde`].
+    #[cfg(feature = "serde")]
+    pub async fn am_send_serde<T: Serialize>(
+        &self,
+        id: u32,
+        header: &[u8],
+        value: &T,
+    ) -> Result<usize, Error> {
+        let data = bincode_options().serialize(value).map_err(|err| {
+            warn!("am_send_serde: {}", err);
+            Error::Serialize
+        })?;
+        self.am_send(id, header, &data).await
     }
 
-    let mut param = MaybeUninit::<ucp_request_param_t>::uninit();
-    let (buffer, count) = unsafe {
-        let param = &mut *param.as_mut_ptr();
-        param.op_attr_mask = ucp_op_attr_t::UCP_OP_ATTR_FIELD_CALLBACK as u32
-            | ucp_op_attr_t::UCP_OP_ATTR_FIELD_DATATYPE as u32
-            | ucp_op_attr_t::UCP_OP_ATTR_FIELD_FLAGS as u32;
-        param.flags = 0;
-        param.cb = ucp_request_param_t__bindgen_ty_1 {
-            send: Some(callback),
-        };
+    /// Like `am_send_flags`, except that the returned future owns a clone of the
+    /// endpoint and the buffers, so it is `'static`.
+    ///
+    /// This allows driving many sends together, e.g. in a `FuturesUnordered`.
+    pub fn am_send_owned<H, D>(
+        &self,
+        id: u32,
+        header: H,
+        data: D,
+        flags: AmSendFlags,
+    ) -> impl Future<Output = Result<usize, Error>> + 'static
+    where
+        H: AsRef<[u8]> + 'static,
+        D: AsRef<[u8]> + 'static,
+    {
+        let endpoint = self.clone();
+        async move {
+            endpoint
+                .am_send_flags(id, header.as_ref(), data.as_ref(), flags)
+                .await
+        }
+    }
 
-        match proto {
-            Some(AmProto::Eager) => param.flags |= ucp_send_am_flags::UCP_AM_SEND_FLAG_EAGER.0,
-            Some(AmProto::Rndv) => param.flags |= ucp_send_am_flags::UCP_AM_SEND_FLAG_RNDV.0,
-            _ => (),
+    /// Send an active message without waiting for its completion.
+    ///
+    /// `data` is owned by the send and freed once UCX is done with it,
+    /// so it can't be dropped under a rendezvous send in flight.
+    /// `header` is copied before returning.
+    /// Messages are still delivered in the order they are sent on this endpoint.
+    /// Only errors detected when posting the send are returned,
+    /// later failures are logged and show up in the endpoint status.
+    /// Fails with [`Error::NoReource`] if
+    /// [`max_inflight`](EndpointBuilder::max_inflight) sends are in flight.
+    pub fn am_send_owned_buf(
+        &self,
+        id: u32,
+        header: &[u8],
+        data: Vec<u8>,
+        flags: AmSendFlags,
+    ) -> Result<(), Error> {
+        struct OwnedSend {
+            data: Vec<u8>,
+            counters: Rc<AmCounters>,
+            _inflight: InflightGuard,
+            _permit: Option<SendPermit>,
         }
 
-        if need_reply {
-            param.flags |= ucp_send_am_flags::UCP_AM_SEND_FLAG_REPLY.0;
+        unsafe extern "C" fn callback(
+            request: *mut c_void,
+            status: ucs_status_t,
+            arg: *mut c_void,
+        ) {
+            let send = Box::from_raw(arg as *mut OwnedSend);
+            trace!(
+                "am_send_owned_buf: complete. req={:?}, status={:?}, len={}",
+                request,
+                status,
+                send.data.len()
+            );
+            if let Err(err) = Error::from_status(status) {
+                warn!("am_send_owned_buf: {}", err);
+            }
+            send.counters.pending_sends.fetch_sub(1, Ordering::Relaxed);
+            // nobody else holds the request, and UCX gives no callback once it is freed
+            ucp_request_free(request);
         }
 
-        if data.len() == 1 {
+        let endpoint = self.get_handle()?;
+        let worker = self.worker();
+        worker.check_am_header(header)?;
+        let len = data.len();
+        let send = Box::into_raw(Box::new(OwnedSend {
+            data,
+            counters: worker.am_counters.clone(),
+            _inflight: worker.inflight.track(),
+            _permit: self.try_send_permit()?,
+        }));
+
+        let mut param = MaybeUninit::<ucp_request_param_t>::uninit();
+        unsafe {
+            let param = &mut *param.as_mut_ptr();
+            param.op_attr_mask = ucp_op_attr_t::UCP_OP_ATTR_FIELD_CALLBACK as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_USER_DATA as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_DATATYPE as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_FLAGS as u32;
+            param.cb = ucp_request_param_t__bindgen_ty_1 {
+                send: Some(callback),
+            };
+            param.user_data = send as _;
             param.datatype = ucp_dt_make_contig(1);
-            (data[0].as_ptr(), data[0].len())
+            param.flags = (flags | AmSendFlags::COPY_HEADER).bits();
+        }
+        let status = unsafe {
+            ucp_am_send_nbx(
+                endpoint,
+                id,
+                header.as_ptr() as _,
+                header.len() as _,
+                (*send).data.as_ptr() as _,
+                len as _,
+                param.as_mut_ptr(),
+            )
+        };
+        if status.is_null() {
+            trace!("am_send_owned_buf: complete");
+            drop(unsafe { Box::from_raw(send) });
+        } else if UCS_PTR_IS_PTR(status) {
+            // the callback frees `data` and the request
+            worker
+                .am_counters
+                .pending_sends
+                .fetch_add(1, Ordering::Relaxed);
         } else {
-            param.datatype = ucp_dt_type::UCP_DATATYPE_IOV as _;
-            (data.as_ptr() as _, data.len())
+            drop(unsafe { Box::from_raw(send) });
+            return Err(Error::from_ptr(status).unwrap_err());
         }
-    };
-
-    let status = unsafe {
-        ucp_am_send_nbx(
-            endpoint,
-            id,
-            header.as_ptr() as _,
-            header.len() as _,
-            buffer as _,
-            count as _,
-            param.as_mut_ptr(),
-        )
-    };
-    if status.is_null() {
-        trace!("am_send: complete");
+        worker.am_counters.add_sent(header.len() + len);
         Ok(())
-    } else if UCS_PTR_IS_PTR(status) {
-        RequestHandle {
-            ptr: status,
-            poll_fn: poll_normal,
-        }
-        .await
-    } else {
-        Err(Error::from_ptr(status).unwrap_err())
-    }
-}
-
-unsafe fn poll_recv(ptr: ucs_status_ptr_t) -> Poll<()> {
-    let status = ucp_request_check_status(ptr as _);
-    if status == ucs_status_t::UCS_INPROGRESS {
-        Poll::Pending
-    } else {
-        Poll::Ready(())
     }
-}
 
-#[cfg(test)]
-#[cfg(feature = "am")]
-mod tests {
-    use super::*;
+    /// Submit an active message and return a handle to its completion.
+    ///
+    /// Unlike `am_send_flags`, the send is posted before returning, so many
+    /// sends can be submitted and then completed in any order.
+    /// `header` is copied before returning and `data` is owned by the send
+    /// until UCX is done with it, so the handle can be dropped at any time;
+    /// the send then completes in the background.
+    /// Fails with [`Error::NoReource`] if
+    /// [`max_inflight`](EndpointBuilder::max_inflight) sends are in flight.
+    pub fn am_send_start(
+        &self,
+        id: u32,
+        header: &[u8],
+        data: Vec<u8>,
+        flags: AmSendFlags,
+    ) -> Result<AmSendHandle, Error> {
+        unsafe extern "C" fn callback(
+            request: *mut c_void,
+            status: ucs_status_t,
+            arg: *mut c_void,
+        ) {
+            trace!(
+                "am_send_start: complete. req={:?}, status={:?}",
+                request,
+                status
+            );
+            let send = &*(arg as *const StartedSend);
+            send.counters.pending_sends.fetch_sub(1, Ordering::Relaxed);
+            if send.detached.get() {
+                // the handle is gone, so nobody else holds the request
+                let send = Box::from_raw(arg as *mut StartedSend);
+                match Error::from_status(status) {
+                    Ok(()) => send.counters.add_sent(send.header_len + send.data.len()),
+                    Err(err) => warn!("am_send_start: {}", err),
+                }
+                ucp_request_free(request);
+                return;
+            }
+            send.status.set(Some(status));
+            if let Some(waker) = send.waker.take() {
+                waker.wake();
+            }
+        }
 
-    #[test_log::test]
-    fn am() {
-        for i in 0..20_usize {
-            spawn_thread!(send_recv(4 << i)).join().unwrap();
+        let worker = self.worker();
+        worker.check_am_header(header)?;
+        let endpoint = self.get_handle()?;
+        let send = Box::into_raw(Box::new(StartedSend {
+            data,
+            header_len: header.len(),
+            status: Cell::new(None),
+            waker: RefCell::new(None),
+            detached: Cell::new(false),
+            counters: worker.am_counters.clone(),
+            _inflight: worker.inflight.track(),
+            _permit: self.try_send_permit()?,
+        }));
+
+        let mut param = MaybeUninit::<ucp_request_param_t>::uninit();
+        unsafe {
+            let param = &mut *param.as_mut_ptr();
+            param.op_attr_mask = ucp_op_attr_t::UCP_OP_ATTR_FIELD_CALLBACK as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_USER_DATA as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_DATATYPE as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_FLAGS as u32;
+            param.cb = ucp_request_param_t__bindgen_ty_1 {
+                send: Some(callback),
+            };
+            param.user_data = send as _;
+            param.datatype = ucp_dt_make_contig(1);
+            param.flags = (flags | AmSendFlags::COPY_HEADER).bits();
         }
+        let status = unsafe {
+            ucp_am_send_nbx(
+                endpoint,
+                id,
+                header.as_ptr() as _,
+                header.len() as _,
+                (*send).data.as_ptr() as _,
+                (*send).data.len() as _,
+                param.as_mut_ptr(),
+            )
+        };
+        let (request, send) = if status.is_null() {
+            trace!("am_send_start: complete");
+            let send = unsafe { Box::from_raw(send) };
+            worker.am_counters.add_sent(header.len() + send.data.len());
+            return Ok(AmSendHandle {
+                worker: worker.clone(),
+                inflight: None,
+                data_len: send.data.len(),
+            });
+        } else if UCS_PTR_IS_PTR(status) {
+            worker
+                .am_counters
+                .pending_sends
+                .fetch_add(1, Ordering::Relaxed);
+            (status, send)
+        } else {
+            drop(unsafe { Box::from_raw(send) });
+            return Err(Error::from_ptr(status).unwrap_err());
+        };
+        Ok(AmSendHandle {
+            worker: worker.clone(),
+            inflight: Some((request, send)),
+            data_len: unsafe { (*send).data.len() },
+        })
     }
 
-    async fn send_recv(data_size: usize) {
-        let context1 = Context::new().unwrap();
-        let worker1 = context1.create_worker().unwrap();
-        let context2 = Context::new().unwrap();
-        let worker2 = context2.create_worker().unwrap();
-        tokio::task::spawn_local(worker1.clone().polling());
-        tokio::task::spawn_local(worker2.clone().polling());
+    /// Like `am_send`, except that it reads from a slice of buffers.
+    ///
+    /// UCX either queues the whole message or fails, so the returned number of
+    /// bytes is always the total length of `data` on success.
+    pub async fn am_send_vectorized(
+        &self,
+        id: u32,
+        header: &[u8],
+        data: &[IoSlice<'_>],
+        flags: AmSendFlags,
+    ) -> Result<usize, Error> {
+        let endpoint = self.get_handle()?;
+        let _permit = self.send_permit().await;
+        am_send(self.worker(), endpoint, id, header, data, flags).await
+    }
 
-        // connect with each other
-        let mut listener = worker1
-            .create_listener("0.0.0.0:0".parse().unwrap())
-            .unwrap();
-        let listen_port = listener.socket_addr().unwrap().port();
-        let mut addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
-        addr.set_port(listen_port);
-        let (endpoint1, endpoint2) = tokio::join!(
-            async {
-                let conn1 = listener.next().await;
-                worker1.accept(conn1).await.unwrap()
-            },
-            async { worker2.connect_socket(addr).await.unwrap() },
-        );
+    /// Send a batch of active messages of `(id, header, data)`.
+    ///
+    /// All messages are submitted to UCX before awaiting any of them,
+    /// so submission overlaps with completion, at most
+    /// [`max_inflight`](EndpointBuilder::max_inflight) at once.
+    /// On error the messages submitted so far are still awaited.
+    pub async fn am_send_batch(&self, msgs: &[(u32, &[u8], &[IoSlice<'_>])]) -> Result<(), Error> {
+        let endpoint = self.get_handle()?;
+        let counters = &self.worker().am_counters;
+        let mut requests = MultiRequest::new();
+        let mut lens = Vec::new();
+        let mut result = Ok(());
+        for (id, header, data) in msgs {
+            if let Err(err) = self.worker().check_am_header(header) {
+                result = Err(err);
+                break;
+            }
+            let len = header.len() + data.iter().map(|v| v.len()).sum::<usize>();
+            let permit = requests.send_permit(self).await;
+            let status = unsafe {
+                am_send_nbx(
+                    endpoint,
+                    *id,
+                    header,
+                    data,
+                    AmSendFlags::empty(),
+                    null_mut(),
+                    null_mut(),
+                )
+            };
+            if status.is_null() {
+                counters.add_sent(len);
+            } else if UCS_PTR_IS_PTR(status) {
+                requests.push(
+                    RequestHandle::new(self.worker(), status, poll_normal),
+                    permit,
+                );
+                lens.push(len);
+            } else {
+                result = Error::from_ptr(status);
+                break;
+            }
+        }
+        trace!("am_send_batch: submitted, pending={}", requests.len());
+
+        // buffers are borrowed until all submitted requests complete
+        let pending = requests.len() as u64;
+        counters.pending_sends.fetch_add(pending, Ordering::Relaxed);
+        let statuses = requests.await;
+        counters.pending_sends.fetch_sub(pending, Ordering::Relaxed);
+        for (status, len) in statuses.into_iter().zip(lens) {
+            if status.is_ok() {
+                counters.add_sent(len);
+            }
+            result = result.and(status);
+        }
+        result
+    }
+
+    /// Like `am_send`, except that the request is allocated from `pool`.
+    ///
+    /// Fails with [`Error::NoReource`] if the pool is exhausted.
+    pub async fn am_send_pooled(
+        &self,
+        pool: &RequestPool,
+        id: u32,
+        header: &[u8],
+        data: &[u8],
+        flags: AmSendFlags,
+    ) -> Result<usize, Error> {
+        let endpoint = self.get_handle()?;
+        self.worker().check_am_header(header)?;
+        let _permit = self.send_permit().await;
+        let slot = pool.alloc()?;
+        let data = [IoSlice::new(data)];
+        let status = unsafe {
+            am_send_nbx(
+                endpoint,
+                id,
+                header,
+                &data,
+                flags,
+                slot.as_ptr(),
+                null_mut(),
+            )
+        };
+        if status.is_null() {
+            trace!("am_send_pooled: complete");
+        } else if UCS_PTR_IS_PTR(status) {
+            PooledRequestHandle::new(self.worker(), slot, poll_normal).await?;
+        } else {
+            return Err(Error::from_ptr(status).unwrap_err());
+        }
+        self.worker()
+            .am_counters
+            .add_sent(header.len() + data[0].len());
+        Ok(data[0].len())
+    }
+
+    /// Send `len` bytes of registered memory `mem` at `offset` as the data of an active message.
+    ///
+    /// Passing the memory handle lets UCX skip registering the data internally,
+    /// so large messages can use the zero-copy rendezvous protocol.
+    /// `mem` is borrowed until the send is completed.
+    ///
+    /// # Safety
+    /// The region registered by `mem` must stay valid, and must not be written
+    /// otherwise, until the send completes.
+    #[allow(clippy::too_many_arguments)]
+    pub async unsafe fn am_send_mem(
+        &self,
+        id: u32,
+        header: &[u8],
+        mem: &MemoryHandle,
+        offset: usize,
+        len: usize,
+        flags: AmSendFlags,
+    ) -> Result<usize, Error> {
+        let endpoint = self.get_handle()?;
+        self.worker().check_am_header(header)?;
+        let buf = mem.ptr_at(offset, len)?;
+        let _permit = self.send_permit().await;
+        let data = [IoSlice::new(slice::from_raw_parts(buf, len))];
+        let status = am_send_nbx(endpoint, id, header, &data, flags, null_mut(), mem.handle);
+        if status.is_null() {
+            trace!("am_send_mem: complete");
+        } else if UCS_PTR_IS_PTR(status) {
+            RequestHandle::new(self.worker(), status, poll_normal).await?;
+        } else {
+            return Err(Error::from_ptr(status).unwrap_err());
+        }
+        self.worker().am_counters.add_sent(header.len() + len);
+        Ok(len)
+    }
+
+    /// Send an active message that advertises another worker as the reply target.
+    ///
+    /// UCX always attaches the sending endpoint as the reply endpoint, so the
+    /// address of `reply_to`, from [`Worker::address`], is carried in front
+    /// of `header` instead.
+    /// The receiver recovers it with [`AmMsg::take_reply_addr`].
+    pub async fn am_send_reply_to(
+        &self,
+        id: u32,
+        header: &[u8],
+        data: &[u8],
+        reply_to: &WorkerAddress<'_>,
+        flags: AmSendFlags,
+    ) -> Result<usize, Error> {
+        let reply_to = reply_to.as_ref();
+        let mut framed = Vec::with_capacity(REPLY_ADDR_LEN_SIZE + reply_to.len() + header.len());
+        framed.extend_from_slice(&(reply_to.len() as u32).to_le_bytes());
+        framed.extend_from_slice(reply_to);
+        framed.extend_from_slice(header);
+        self.am_send_flags(id, &framed, data, flags).await
+    }
+}
+
+bitflags::bitflags! {
+    /// Attributes of a received active message, see [`AmMsg::recv_attrs`].
+    pub struct AmRecvAttr: u64 {
+        /// The sender passed its endpoint, so the message can be replied to.
+        const REPLY_EP = ucp_am_recv_attr_t::UCP_AM_RECV_ATTR_FIELD_REPLY_EP as u64;
+        /// The data is held by UCX until released.
+        const DATA = ucp_am_recv_attr_t::UCP_AM_RECV_ATTR_FLAG_DATA as u64;
+        /// The data is a rendezvous descriptor to be received.
+        const RNDV = ucp_am_recv_attr_t::UCP_AM_RECV_ATTR_FLAG_RNDV as u64;
+    }
+}
+
+bitflags::bitflags! {
+    /// Flags of an active message send.
+    pub struct AmSendFlags: u32 {
+        /// Pass the sender endpoint to the receiver, so it can [`AmMsg::reply`].
+        const REPLY = ucp_send_am_flags::UCP_AM_SEND_FLAG_REPLY.0;
+        /// Force the eager protocol.
+        const EAGER = ucp_send_am_flags::UCP_AM_SEND_FLAG_EAGER.0;
+        /// Force the rendezvous protocol.
+        const RNDV = ucp_send_am_flags::UCP_AM_SEND_FLAG_RNDV.0;
+        /// Copy the header when the send is submitted, so it needn't stay
+        /// valid until completion.
+        const COPY_HEADER = ucp_send_am_flags::UCP_AM_SEND_FLAG_COPY_HEADER.0;
+    }
+}
+
+#[instrument(
+    level = "trace",
+    skip_all,
+    fields(?endpoint, id = id, header_len = header.len(), request = next_request_id()),
+    ret,
+    err(level = "debug")
+)]
+async fn am_send(
+    worker: &Worker,
+    endpoint: ucp_ep_h,
+    id: u32,
+    header: &[u8],
+    data: &[IoSlice<'_>],
+    flags: AmSendFlags,
+) -> Result<usize, Error> {
+    worker.check_am_header(header)?;
+    let counters = &worker.am_counters;
+    let status = loop {
+        let status =
+            unsafe { am_send_nbx(endpoint, id, header, data, flags, null_mut(), null_mut()) };
+        if UCS_PTR_STATUS(status) != ucs_status_t::UCS_ERR_NO_RESOURCE {
+            break status;
+        }
+        // out of send resources, let the transport drain and retry
+        trace!("am_send: no resource, retrying");
+        worker.progress();
+        futures_lite::future::yield_now().await;
+    };
+    let total_len = data.iter().map(|v| v.len()).sum();
+    if UCS_PTR_IS_PTR(status) {
+        counters.pending_sends.fetch_add(1, Ordering::Relaxed);
+        let result = RequestHandle::new(worker, status, poll_normal).await;
+        counters.pending_sends.fetch_sub(1, Ordering::Relaxed);
+        result?;
+    } else if !status.is_null() {
+        return Err(Error::from_ptr(status).unwrap_err());
+    }
+    counters.add_sent(header.len() + total_len);
+    Ok(total_len)
+}
+
+/// An active message send submitted by [`Endpoint::am_send_start`].
+///
+/// Resolves to the number of data bytes sent. Dropping the handle before
+/// completion leaves the send running, its data is freed once it completes.
+pub struct AmSendHandle {
+    worker: Rc<Worker>,
+    // `None` once completed
+    inflight: Option<(ucs_status_ptr_t, *mut StartedSend)>,
+    data_len: usize,
+}
+
+// State of an `am_send_start` request, freed by the handle once completed,
+// or by the callback if the handle was dropped first
+struct StartedSend {
+    data: Vec<u8>,
+    header_len: usize,
+    // set by the callback
+    status: Cell<Option<ucs_status_t>>,
+    waker: RefCell<Option<Waker>>,
+    detached: Cell<bool>,
+    counters: Rc<AmCounters>,
+    _inflight: InflightGuard,
+    _permit: Option<SendPermit>,
+}
+
+impl AmSendHandle {
+    /// Whether the send completed, successfully or not.
+    pub fn is_complete(&self) -> bool {
+        match self.inflight {
+            None => true,
+            Some((_, send)) => unsafe { (*send).status.get().is_some() },
+        }
+    }
+
+    /// Ask UCX to cancel the send, which then resolves to [`Error::Canceled`].
+    ///
+    /// A send that already went out completes as usual.
+    pub fn cancel(&self) {
+        if let Some((request, _)) = self.inflight {
+            trace!("am_send_start: cancel req={:?}", request);
+            unsafe { ucp_request_cancel(self.worker.handle, request as _) };
+        }
+    }
+}
+
+impl Future for AmSendHandle {
+    type Output = Result<usize, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context) -> Poll<Self::Output> {
+        if let Some((request, send)) = self.inflight {
+            let status = match unsafe { (*send).status.get() } {
+                Some(status) => status,
+                None => {
+                    unsafe { (*send).waker.replace(Some(cx.waker().clone())) };
+                    return Poll::Pending;
+                }
+            };
+            self.inflight = None;
+            let send = unsafe { Box::from_raw(send) };
+            unsafe { ucp_request_free(request) };
+            Error::from_status(status)?;
+            send.counters.add_sent(send.header_len + send.data.len());
+        }
+        Poll::Ready(Ok(self.data_len))
+    }
+}
+
+impl Drop for AmSendHandle {
+    fn drop(&mut self) {
+        if let Some((request, send)) = self.inflight {
+            if unsafe { (*send).status.get() }.is_some() {
+                drop(unsafe { Box::from_raw(send) });
+                unsafe { ucp_request_free(request) };
+            } else {
+                // the callback frees both once the send completes
+                unsafe { (*send).detached.set(true) };
+            }
+        }
+    }
+}
+
+// Submit an active message.
+// `request` is a request slot allocated by user, or null to let UCX allocate one.
+// `memh` is the registered memory of `data`, or null to let UCX register it.
+#[allow(clippy::too_many_arguments)]
+unsafe fn am_send_nbx(
+    endpoint: ucp_ep_h,
+    id: u32,
+    header: &[u8],
+    data: &[IoSlice<'_>],
+    flags: AmSendFlags,
+    request: *mut c_void,
+    memh: ucp_mem_h,
+) -> ucs_status_ptr_t {
+    unsafe extern "C" fn callback(request: *mut c_void, _status: ucs_status_t, _data: *mut c_void) {
+        trace!("am_send: complete");
+        let request = &mut *(request as *mut Request);
+        request.waker.wake();
+    }
+
+    let mut param = MaybeUninit::<ucp_request_param_t>::uninit();
+    let (buffer, count) = {
+        let param = &mut *param.as_mut_ptr();
+        param.op_attr_mask = ucp_op_attr_t::UCP_OP_ATTR_FIELD_CALLBACK as u32
+            | ucp_op_attr_t::UCP_OP_ATTR_FIELD_DATATYPE as u32
+            | ucp_op_attr_t::UCP_OP_ATTR_FIELD_FLAGS as u32;
+        param.flags = 0;
+        param.cb = ucp_request_param_t__bindgen_ty_1 {
+            send: Some(callback),
+        };
+
+        if !request.is_null() {
+            param.op_attr_mask |= ucp_op_attr_t::UCP_OP_ATTR_FIELD_REQUEST as u32;
+            param.request = request;
+        }
+
+        if !memh.is_null() {
+            param.op_attr_mask |= ucp_op_attr_t::UCP_OP_ATTR_FIELD_MEMH as u32;
+            param.memh = memh;
+        }
+
+        param.flags = flags.bits();
+
+        if data.iter().all(|v| v.is_empty()) {
+            // no data, don't hand UCX an empty iov
+            param.datatype = ucp_dt_make_contig(1);
+            (null(), 0)
+        } else if data.len() == 1 {
+            param.datatype = ucp_dt_make_contig(1);
+            (data[0].as_ptr(), data[0].len())
+        } else {
+            param.datatype = ucp_dt_type::UCP_DATATYPE_IOV as _;
+            (data.as_ptr() as _, data.len())
+        }
+    };
+
+    ucp_am_send_nbx(
+        endpoint,
+        id,
+        header.as_ptr() as _,
+        header.len() as _,
+        buffer as _,
+        count as _,
+        param.as_mut_ptr(),
+    )
+}
+
+unsafe fn poll_recv(ptr: ucs_status_ptr_t) -> Poll<()> {
+    let status = ucp_request_check_status(ptr as _);
+    if status == ucs_status_t::UCS_INPROGRESS {
+        Poll::Pending
+    } else {
+        Poll::Ready(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "am")]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn am() {
+        for i in 0..20_usize {
+            spawn_thread!(send_recv(4 << i)).join().unwrap();
+        }
+    }
+
+    async fn send_recv(data_size: usize) {
+        let (endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
 
         let stream1 = worker1.am_stream(16).unwrap();
         let stream2 = worker2.am_stream(12).unwrap();
@@ -596,12 +2216,11 @@ mod tests {
             async {
                 // send msg
                 let result = endpoint2
-                    .am_send(
+                    .am_send_flags(
                         16,
                         header.as_slice(),
                         data.as_slice(),
-                        true,
-                        Some(AmProto::Eager),
+                        AmSendFlags::REPLY | AmSendFlags::EAGER,
                     )
                     .await;
                 assert!(result.is_ok());
@@ -627,7 +2246,7 @@ mod tests {
         tokio::join!(
             async {
                 // send reply
-                let result = unsafe { msg.reply(12, &header, &data, false, None).await };
+                let result = unsafe { msg.reply(12, &header, &data, AmSendFlags::empty()).await };
                 assert!(result.is_ok());
             },
             async {
@@ -643,13 +2262,1371 @@ mod tests {
             }
         );
 
-        assert_eq!(endpoint1.get_rc(), (1, 1));
-        assert_eq!(endpoint2.get_rc(), (1, 1));
+        assert_eq!(endpoint1.get_rc(), (1, 2));
+        assert_eq!(endpoint2.get_rc(), (1, 2));
         assert_eq!(endpoint1.close(false).await, Ok(()));
         assert_eq!(endpoint2.close(false).await, Err(Error::ConnectionReset));
         assert_eq!(endpoint1.get_rc(), (1, 0));
-        assert_eq!(endpoint2.get_rc(), (1, 1));
+        assert_eq!(endpoint2.get_rc(), (1, 2));
         assert_eq!(endpoint2.close(true).await, Ok(()));
         assert_eq!(endpoint2.get_rc(), (1, 0));
     }
+
+    #[test_log::test]
+    fn reply_to() {
+        spawn_thread!(_reply_to()).join().unwrap();
+    }
+
+    async fn _reply_to() {
+        // worker2 sends requests to worker1, replies should go to worker3
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+        let context3 = Context::new().unwrap();
+        let worker3 = context3.create_worker().unwrap();
+        tokio::task::spawn_local(worker3.clone().polling());
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let stream3 = worker3.am_stream(12).unwrap();
+        let backend = worker3.address().unwrap();
+
+        let header = vec![1, 2, 3, 4];
+        let data = vec![1_u8; 64];
+        endpoint2
+            .am_send_reply_to(16, &header, &data, &backend, AmSendFlags::empty())
+            .await
+            .unwrap();
+
+        let mut msg = stream1.wait_msg().await.expect("no msg");
+        let reply_addr = unsafe { msg.take_reply_addr() }.unwrap();
+        assert_eq!(msg.header(), &header);
+        assert_eq!(msg.recv_data().await.unwrap(), data);
+
+        let reply_ep = worker1.connect_addr(&reply_addr).unwrap();
+        reply_ep.am_send(12, &header, &data).await.unwrap();
+
+        let mut reply = stream3.wait_msg().await.expect("no reply");
+        assert_eq!(reply.header(), &header);
+        assert_eq!(reply.recv_data().await.unwrap(), data);
+    }
+
+    #[test_log::test]
+    fn am_send_len() {
+        spawn_thread!(_am_send_len()).join().unwrap();
+    }
+
+    async fn _am_send_len() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let bufs: Vec<Vec<u8>> = (0..16).map(|i| vec![i as u8; 1 << 16]).collect();
+        let iov: Vec<IoSlice> = bufs.iter().map(|buf| IoSlice::new(buf)).collect();
+        let total_len = bufs.iter().map(|buf| buf.len()).sum::<usize>();
+        let (sent, recv_len) = tokio::join!(
+            async {
+                endpoint2
+                    .am_send_vectorized(16, &[], &iov, AmSendFlags::empty())
+                    .await
+                    .unwrap()
+            },
+            async {
+                let mut msg = stream1.wait_msg().await.expect("no msg");
+                msg.recv_data().await.unwrap().len()
+            }
+        );
+        assert_eq!(sent, total_len);
+        assert_eq!(recv_len, total_len);
+        assert_eq!(
+            endpoint2.am_send(16, &[], &bufs[0]).await,
+            Ok(bufs[0].len())
+        );
+    }
+
+    #[test_log::test]
+    fn drop_worker() {
+        let context = Context::new().unwrap();
+        let worker = context.create_worker().unwrap();
+        let streams: Vec<_> = (1..4)
+            .map(|id| {
+                worker.am_stream(id).unwrap();
+                Rc::downgrade(&worker.am_streams.read().unwrap()[&id])
+            })
+            .collect();
+
+        // dropping the worker without unregistering releases all streams
+        drop(worker);
+        assert!(streams.iter().all(|stream| stream.upgrade().is_none()));
+    }
+
+    #[test_log::test]
+    fn reply_echo() {
+        spawn_thread!(_reply_echo()).join().unwrap();
+    }
+
+    async fn _reply_echo() {
+        let (_endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let stream2 = worker2.am_stream(16).unwrap();
+
+        // two requests carrying their correlation token in the header
+        for token in [1_u64, 2] {
+            endpoint2
+                .am_send_flags(16, &token.to_le_bytes(), &[token as u8], AmSendFlags::REPLY)
+                .await
+                .unwrap();
+        }
+        let mut requests = vec![
+            stream1.wait_msg().await.expect("no msg"),
+            stream1.wait_msg().await.expect("no msg"),
+        ];
+
+        // reply in reverse order
+        while let Some(mut request) = requests.pop() {
+            let data = request.recv_data().await.unwrap();
+            let result = unsafe { request.reply_echo(&[data[0] * 10]).await };
+            assert!(result.is_ok());
+        }
+        for _ in 0..2 {
+            let mut reply = stream2.wait_msg().await.expect("no reply");
+            assert_eq!(reply.id(), 16);
+            let mut token = [0; 8];
+            token.copy_from_slice(reply.header());
+            let token = u64::from_le_bytes(token);
+            assert_eq!(reply.recv_data().await.unwrap(), [token as u8 * 10]);
+        }
+    }
+
+    #[test_log::test]
+    fn am_send_inline() {
+        spawn_thread!(_am_send_inline()).join().unwrap();
+    }
+
+    async fn _am_send_inline() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let combined: Vec<u8> = (0..64).collect();
+        for header_len in [0, 1, 8, 63, 64] {
+            let (sent, (header, data)) =
+                tokio::join!(endpoint2.am_send_inline(16, &combined, header_len), async {
+                    let mut msg = stream1.wait_msg().await.expect("no msg");
+                    let header = msg.header().to_vec();
+                    (header, msg.recv_data().await.unwrap())
+                });
+            assert_eq!(sent, Ok(combined.len() - header_len));
+            assert_eq!(header, &combined[..header_len]);
+            assert_eq!(data, &combined[header_len..]);
+        }
+        assert_eq!(
+            endpoint2.am_send_inline(16, &combined, 65).await,
+            Err(Error::InvalidParam)
+        );
+    }
+
+    #[test_log::test]
+    fn recv_data_uninit() {
+        spawn_thread!(_recv_data_uninit()).join().unwrap();
+    }
+
+    async fn _recv_data_uninit() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let mut buf = vec![MaybeUninit::uninit(); 1 << 20];
+        for len in [0, 4, 1 << 12, 1 << 20] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let (sent, received) = tokio::join!(endpoint2.am_send(16, &[], &data), async {
+                let mut msg = stream1.wait_msg().await.expect("no msg");
+                msg.recv_data_uninit(&mut buf)
+                    .await
+                    .map(|data| data.to_vec())
+            });
+            assert_eq!(sent, Ok(len));
+            assert_eq!(received, Ok(data));
+        }
+
+        // too short a buffer, then retry with a larger one
+        for flags in [AmSendFlags::EAGER, AmSendFlags::RNDV] {
+            let data = vec![1_u8; 1 << 12];
+            let (sent, received) =
+                tokio::join!(endpoint2.am_send_flags(16, &[], &data, flags), async {
+                    let mut msg = stream1.wait_msg().await.expect("no msg");
+                    let result = msg.recv_data_uninit(&mut buf[..16]).await.map(|d| d.len());
+                    assert_eq!(result, Err(Error::MessageTruncated));
+                    assert_eq!(msg.data_len(), data.len());
+                    msg.recv_data_uninit(&mut buf)
+                        .await
+                        .map(|data| data.to_vec())
+                });
+            assert_eq!(sent, Ok(data.len()));
+            assert_eq!(received, Ok(data));
+        }
+    }
+
+    #[test_log::test]
+    fn am_send_mem() {
+        spawn_thread!(_am_send_mem()).join().unwrap();
+    }
+
+    async fn _am_send_mem() {
+        const MSG_SIZE: usize = 16 << 20;
+        const ROUNDS: usize = 8;
+
+        let (_endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let mut msg: Vec<u8> = (0..MSG_SIZE).map(|x| x as u8).collect();
+        let expected = msg.clone();
+
+        // `msg` is only accessed through `mem` until it is dropped
+        let mem = MemoryHandle::register(&worker2.context, &mut msg);
+        tokio::join!(
+            async {
+                for _ in 0..ROUNDS {
+                    let sent = unsafe {
+                        endpoint2
+                            .am_send_mem(16, &[], &mem, 0, MSG_SIZE, AmSendFlags::empty())
+                            .await
+                    };
+                    assert_eq!(sent, Ok(MSG_SIZE));
+                }
+            },
+            async {
+                for _ in 0..ROUNDS {
+                    let mut msg = stream1.wait_msg().await.expect("no msg");
+                    assert_eq!(msg.recv_data().await.unwrap(), expected);
+                }
+            }
+        );
+        let result = unsafe {
+            endpoint2
+                .am_send_mem(16, &[], &mem, 1, MSG_SIZE, AmSendFlags::empty())
+                .await
+        };
+        assert_eq!(result, Err(Error::OutOfRange));
+    }
+
+    #[test]
+    fn inline_small_msg() {
+        static SMALL: [u8; INLINE_DATA_LEN] = [7; INLINE_DATA_LEN];
+        static LARGE: [u8; INLINE_DATA_LEN + 1] = [7; INLINE_DATA_LEN + 1];
+
+        let msg = RawMsg::from_raw(1, &[1; INLINE_HEADER_LEN], &SMALL, null_mut(), 0);
+        assert!(!msg.header.spilled());
+        assert!(matches!(msg.data, Some(AmData::Eager(ref data)) if !data.spilled()));
+
+        let msg = RawMsg::from_raw(1, &[1; INLINE_HEADER_LEN + 1], &LARGE, null_mut(), 0);
+        assert!(msg.header.spilled());
+        assert!(matches!(msg.data, Some(AmData::Eager(ref data)) if data.spilled()));
+        assert_eq!(msg.data.unwrap().data(), Some(&LARGE[..]));
+    }
+
+    #[test_log::test]
+    fn am_recv_any() {
+        spawn_thread!(_am_recv_any()).join().unwrap();
+    }
+
+    async fn _am_recv_any() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let streams: Vec<_> = (1..=3).map(|id| worker1.am_stream(id).unwrap()).collect();
+        let (worker1, endpoint2) = (&worker1, &endpoint2);
+        tokio::join!(
+            async move {
+                let msg = worker1.am_recv_any().await.unwrap();
+                assert_eq!(msg.id(), 2);
+                assert_eq!(msg.header(), &[2]);
+            },
+            async move {
+                tokio::task::yield_now().await;
+                endpoint2.am_send(2, &[2], &[]).await.unwrap();
+            }
+        );
+
+        // round-robin: both ids are served before either one twice
+        for id in [1, 3, 1, 3] {
+            endpoint2.am_send(id, &[id as u8], &[]).await.unwrap();
+        }
+        // wait until all messages arrived, so both ids are pending
+        let queued = |i: usize| streams[i].inner.msgs.len();
+        assert!(worker1.progress_until(|| queued(0) == 2 && queued(2) == 2, 1 << 20));
+        // served after id 2, wrapping around
+        let mut ids = Vec::new();
+        for _ in 0..4 {
+            ids.push(worker1.am_recv_any().await.unwrap().id());
+        }
+        assert_eq!(ids, [3, 1, 3, 1]);
+    }
+
+    #[test_log::test]
+    fn shutdown() {
+        spawn_thread!(_shutdown()).join().unwrap();
+    }
+
+    async fn _shutdown() {
+        let context = Context::new().unwrap();
+        let worker = context.create_worker().unwrap();
+        tokio::task::spawn_local(worker.clone().polling());
+
+        let stream = worker.am_stream(16).unwrap();
+        let worker = &worker;
+        tokio::join!(
+            async {
+                assert!(stream.wait_msg().await.is_none());
+            },
+            async move {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                worker.shutdown();
+            }
+        );
+        assert!(worker.am_recv_any().await.is_none());
+    }
+
+    #[test_log::test]
+    fn unregister_parked() {
+        spawn_thread!(_unregister_parked()).join().unwrap();
+    }
+
+    async fn _unregister_parked() {
+        let context = Context::new().unwrap();
+        let worker = context.create_worker().unwrap();
+        tokio::task::spawn_local(worker.clone().polling());
+
+        let stream = worker.am_stream(16).unwrap();
+        let worker = &worker;
+        tokio::join!(
+            async {
+                assert!(stream.wait_msg().await.is_none());
+            },
+            async move {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                unsafe { worker.am_register(16, None, null_mut()).unwrap() };
+            }
+        );
+    }
+
+    #[test_log::test]
+    fn reply_endpoint() {
+        spawn_thread!(_reply_endpoint()).join().unwrap();
+    }
+
+    async fn _reply_endpoint() {
+        let (endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        endpoint2.am_send(16, &[], &[]).await.unwrap();
+        let msg = stream1.wait_msg().await.expect("no msg");
+        assert!(unsafe { msg.reply_endpoint() }.is_none());
+
+        endpoint2
+            .am_send_flags(16, &[], &[], AmSendFlags::REPLY)
+            .await
+            .unwrap();
+        let msg = stream1.wait_msg().await.expect("no msg");
+        let reply_ep = unsafe { msg.reply_endpoint() }.unwrap();
+        let worker2 = &worker2;
+
+        // several tag messages back to the sender
+        tokio::join!(
+            async {
+                reply_ep.tag_send(1, &[1]).await.unwrap();
+                reply_ep.tag_send(2, &[2]).await.unwrap();
+            },
+            async move {
+                let mut buf = [MaybeUninit::uninit(); 1];
+                assert_eq!(worker2.tag_recv(1, &mut buf).await, Ok(1));
+                assert_eq!(worker2.tag_recv(2, &mut buf).await, Ok(1));
+            }
+        );
+        drop(reply_ep);
+        assert!(!endpoint1.is_closed());
+    }
+
+    #[test_log::test]
+    fn am_send_batch() {
+        spawn_thread!(_am_send_batch()).join().unwrap();
+    }
+
+    async fn _am_send_batch() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        const COUNT: u32 = 1000;
+        let stream1 = worker1.am_stream(16).unwrap();
+        let headers: Vec<_> = (0..COUNT).map(|i| i.to_le_bytes()).collect();
+        let data = [IoSlice::new(&[1, 2, 3, 4])];
+        let msgs: Vec<_> = headers
+            .iter()
+            .map(|header| (16, &header[..], &data[..]))
+            .collect();
+        endpoint2.am_send_batch(&msgs).await.unwrap();
+
+        for i in 0..COUNT {
+            let mut msg = stream1.wait_msg().await.expect("no msg");
+            assert_eq!(msg.header(), &i.to_le_bytes());
+            assert_eq!(msg.recv_data().await.unwrap(), [1, 2, 3, 4]);
+        }
+    }
+
+    #[test_log::test]
+    fn am_broadcast() {
+        spawn_thread!(_am_broadcast()).join().unwrap();
+    }
+
+    async fn _am_broadcast() {
+        let context1 = Context::new().unwrap();
+        let worker1 = context1.create_worker().unwrap();
+        tokio::task::spawn_local(worker1.clone().polling());
+        let mut listener = worker1
+            .create_listener("0.0.0.0:0".parse().unwrap())
+            .unwrap();
+        let listen_port = listener.socket_addr().unwrap().port();
+        let mut addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        addr.set_port(listen_port);
+
+        // connect three peers
+        let mut peers = Vec::new();
+        let mut endpoints = Vec::new();
+        for _ in 0..3 {
+            let context = Context::new().unwrap();
+            let worker = context.create_worker().unwrap();
+            tokio::task::spawn_local(worker.clone().polling());
+            let (endpoint1, endpoint) = tokio::join!(
+                async {
+                    let conn1 = listener.next().await;
+                    worker1.accept(conn1).await.unwrap()
+                },
+                async { worker.connect_socket(addr).await.unwrap() },
+            );
+            endpoints.push(endpoint1);
+            peers.push((worker, endpoint));
+        }
+
+        let streams: Vec<_> = peers
+            .iter()
+            .map(|(worker, _)| worker.am_stream(16).unwrap())
+            .collect();
+        let data = vec![7_u8; 1 << 16];
+        worker1
+            .am_broadcast(&endpoints, 16, &[1, 2, 3], &data)
+            .await
+            .unwrap();
+        for stream in &streams {
+            let mut msg = stream.wait_msg().await.expect("no msg");
+            assert_eq!(msg.header(), &[1, 2, 3]);
+            assert_eq!(msg.recv_data().await.unwrap(), data);
+        }
+
+        // endpoints of another worker are refused
+        let foreign = [endpoints[0].clone(), peers[0].1.clone()];
+        assert_eq!(
+            worker1.am_broadcast(&foreign, 16, &[], &data).await,
+            Err(Error::InvalidParam)
+        );
+
+        // a closed endpoint doesn't stop the sends to the others
+        endpoints[1].close(false).await.unwrap();
+        let result = worker1.am_broadcast(&endpoints, 16, &[4], &data).await;
+        assert!(result.is_err());
+        for i in [0, 2] {
+            let mut msg = streams[i].wait_msg().await.expect("no msg");
+            assert_eq!(msg.header(), &[4]);
+            assert_eq!(msg.recv_data().await.unwrap(), data);
+        }
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test_log::test]
+    fn bytes() {
+        spawn_thread!(_bytes()).join().unwrap();
+    }
+
+    #[cfg(feature = "bytes")]
+    async fn _bytes() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        for size in [4, 1 << 20] {
+            // a chain of two chunks
+            let first = Bytes::from(vec![1_u8; size]);
+            let second = Bytes::from(vec![2_u8; size]);
+            let endpoint2 = &endpoint2;
+            let (sent, data) = tokio::join!(
+                async move {
+                    let data = first.clone().chain(second.clone());
+                    endpoint2
+                        .am_send_buf(16, &[], data, AmSendFlags::empty())
+                        .await
+                        .unwrap()
+                },
+                async {
+                    let mut msg = stream1.wait_msg().await.expect("no msg");
+                    msg.recv_data_bytes().await.unwrap()
+                }
+            );
+            assert_eq!(sent, size * 2);
+            assert_eq!(&data[..size], &vec![1_u8; size][..]);
+            assert_eq!(&data[size..], &vec![2_u8; size][..]);
+        }
+
+        // a `Buf` exposing one chunk at a time
+        struct Chunks(Vec<Bytes>);
+        impl Buf for Chunks {
+            fn remaining(&self) -> usize {
+                self.0.iter().map(|chunk| chunk.len()).sum()
+            }
+            fn chunk(&self) -> &[u8] {
+                self.0.first().map_or(&[], |chunk| &chunk[..])
+            }
+            fn advance(&mut self, mut cnt: usize) {
+                while cnt > 0 {
+                    let len = cnt.min(self.0[0].len());
+                    self.0[0].advance(len);
+                    if self.0[0].is_empty() {
+                        self.0.remove(0);
+                    }
+                    cnt -= len;
+                }
+            }
+        }
+        let data = Chunks(vec![Bytes::from_static(&[1, 2]), Bytes::from_static(&[3])]);
+        let (sent, data) = tokio::join!(
+            endpoint2.am_send_buf(16, &[], data, AmSendFlags::empty()),
+            async {
+                let mut msg = stream1.wait_msg().await.expect("no msg");
+                msg.recv_data_bytes().await.unwrap()
+            }
+        );
+        assert_eq!(sent.unwrap(), 3);
+        assert_eq!(&data[..], &[1, 2, 3]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test_log::test]
+    fn serde() {
+        spawn_thread!(_serde()).join().unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    async fn _serde() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let value = (1_u32, String::from("hello"), vec![1_u64, 2, 3]);
+        endpoint2.am_send_serde(16, &[], &value).await.unwrap();
+        let mut msg = stream1.wait_msg().await.expect("no msg");
+        assert_eq!(msg.recv_serde::<(u32, String, Vec<u64>)>().await, Ok(value));
+
+        // size mismatch
+        endpoint2.am_send_serde(16, &[], &1_u64).await.unwrap();
+        let mut msg = stream1.wait_msg().await.expect("no msg");
+        assert_eq!(msg.recv_serde::<String>().await, Err(Error::Deserialize));
+        endpoint2
+            .am_send_serde(16, &[], &(1_u8, 2_u8))
+            .await
+            .unwrap();
+        let mut msg = stream1.wait_msg().await.expect("no msg");
+        assert_eq!(msg.recv_serde::<u8>().await, Err(Error::Deserialize));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test_log::test]
+    fn reply_serde() {
+        spawn_thread!(_reply_serde()).join().unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    async fn _reply_serde() {
+        let (_endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let stream2 = worker2.am_stream(17).unwrap();
+
+        // typed request asking for a reply
+        let request = bincode_options().serialize(&(2_u32, 3_u32)).unwrap();
+        endpoint2
+            .am_send_flags(16, &[], &request, AmSendFlags::REPLY)
+            .await
+            .unwrap();
+        let mut msg = stream1.wait_msg().await.expect("no msg");
+        let (a, b) = msg.recv_serde::<(u32, u32)>().await.unwrap();
+        let reply = (a * b, format!("{} * {}", a, b));
+        let result = unsafe { msg.reply_serde(17, &[1], &reply).await };
+        assert!(result.is_ok());
+
+        let mut msg = stream2.wait_msg().await.expect("no reply");
+        assert_eq!(msg.header(), &[1]);
+        assert_eq!(msg.recv_serde::<(u32, String)>().await, Ok(reply));
+    }
+
+    #[test_log::test]
+    fn stats() {
+        spawn_thread!(_stats()).join().unwrap();
+    }
+
+    async fn _stats() {
+        let (_endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        for _ in 0..10 {
+            endpoint2.am_send(16, &[1, 2], &[3; 8]).await.unwrap();
+        }
+        let stats = worker2.stats();
+        assert_eq!(stats.am_sent, 10);
+        assert_eq!(stats.am_sent_bytes, 100);
+        assert_eq!(stats.am_pending_sends, 0);
+
+        // messages wait in the queue until taken
+        while worker1.stats().am_received < 10 {
+            futures_lite::future::yield_now().await;
+        }
+        let stats = worker1.stats();
+        assert_eq!(stats.am_received_bytes, 100);
+        assert_eq!(stats.am_queue_depths[&16], 10);
+        for _ in 0..10 {
+            stream1.wait_msg().await.expect("no msg");
+        }
+        assert_eq!(worker1.stats().am_queue_depths[&16], 0);
+    }
+
+    #[test_log::test]
+    fn recv_data_scatter() {
+        spawn_thread!(_recv_data_scatter()).join().unwrap();
+    }
+
+    async fn _recv_data_scatter() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let data: Vec<u8> = (0..10).collect();
+        endpoint2.am_send(16, &[], &data).await.unwrap();
+        let mut msg = stream1.wait_msg().await.expect("no msg");
+
+        // header slice, body slice and an unused slice
+        let mut header = [0_u8; 4];
+        let mut body = [0_u8; 8];
+        let mut unused = [0_u8; 4];
+        let iov = [
+            IoSliceMut::new(&mut header),
+            IoSliceMut::new(&mut body),
+            IoSliceMut::new(&mut unused),
+        ];
+        assert_eq!(msg.recv_data_scatter(&iov).await, Ok(vec![4, 6, 0]));
+        assert_eq!(header, [0, 1, 2, 3]);
+        assert_eq!(body[..6], [4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test_log::test]
+    fn rndv_thresh() {
+        spawn_thread!(_rndv_thresh()).join().unwrap();
+    }
+
+    async fn _rndv_thresh() {
+        const THRESH: usize = 8192;
+        let mut config = Config::new().unwrap();
+        config.set_rndv_thresh(THRESH).unwrap();
+        assert_eq!(config.set("NO_SUCH_OPTION", "1"), Err(Error::NoElem));
+        let (_endpoint1, worker1, endpoint2, _, _) =
+            crate::test_util::loopback_with_config(&config)
+                .await
+                .unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let cases = [
+            (THRESH / 2, AmSendFlags::empty(), false),
+            (THRESH * 2, AmSendFlags::empty(), true),
+            (THRESH / 2, AmSendFlags::RNDV, true),
+            (THRESH * 2, AmSendFlags::EAGER, false),
+        ];
+        for (len, flags, rndv) in cases {
+            let data = vec![len as u8; len];
+            let endpoint2 = &endpoint2;
+            let (_, received) = tokio::join!(
+                async move {
+                    endpoint2
+                        .am_send_flags(16, &[], &data, flags)
+                        .await
+                        .unwrap()
+                },
+                async {
+                    let mut msg = stream1.wait_msg().await.expect("no msg");
+                    let data_type = msg.data_type().unwrap();
+                    let attrs = msg.recv_attrs();
+                    assert_eq!(attrs.contains(AmRecvAttr::RNDV), rndv);
+                    assert!(!attrs.contains(AmRecvAttr::REPLY_EP));
+                    let data = msg.recv_data().await.unwrap();
+                    assert_eq!(msg.data_type(), None);
+                    assert_eq!(msg.recv_attrs(), attrs);
+                    (data_type, data)
+                }
+            );
+            assert_eq!(
+                received.0 == AmDataType::Rndv,
+                rndv,
+                "len={}, flags={:?}",
+                len,
+                flags
+            );
+            assert_eq!(received.1, vec![len as u8; len]);
+        }
+    }
+
+    #[test_log::test]
+    fn am_send_owned() {
+        spawn_thread!(_am_send_owned()).join().unwrap();
+    }
+
+    async fn _am_send_owned() {
+        use futures::stream::{FuturesUnordered, StreamExt};
+
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let sends: FuturesUnordered<_> = (0..100_u32)
+            .map(|i| {
+                endpoint2.am_send_owned(16, i.to_le_bytes(), vec![1_u8; 64], AmSendFlags::empty())
+            })
+            .collect();
+        // the endpoint can go away while the sends are in flight
+        drop(endpoint2);
+        let sent: Vec<_> = sends.collect().await;
+        assert!(sent.into_iter().all(|len| len == Ok(64)));
+
+        let mut ids = Vec::new();
+        for _ in 0..100 {
+            let msg = stream1.wait_msg().await.expect("no msg");
+            let mut id = [0; 4];
+            id.copy_from_slice(msg.header());
+            ids.push(u32::from_le_bytes(id));
+        }
+        ids.sort_unstable();
+        assert_eq!(ids, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test_log::test]
+    fn header_too_large() {
+        spawn_thread!(_header_too_large()).join().unwrap();
+    }
+
+    async fn _header_too_large() {
+        let (_endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let max = worker2.max_am_header_len();
+        let header = vec![1_u8; max + 1];
+        assert_eq!(
+            endpoint2.am_send(16, &header, &[]).await,
+            Err(Error::HeaderTooLarge)
+        );
+
+        // the largest header still works
+        endpoint2.am_send(16, &header[..max], &[]).await.unwrap();
+        let msg = stream1.wait_msg().await.expect("no msg");
+        assert_eq!(msg.header().len(), max);
+    }
+
+    #[test_log::test]
+    fn take_data_handle() {
+        spawn_thread!(_take_data_handle()).join().unwrap();
+    }
+
+    async fn _take_data_handle() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        // an eager message larger than a fragment is reassembled by UCX,
+        // which hands out the buffer as a descriptor on any transport
+        let data: Vec<u8> = (0..=255).cycle().take(1 << 20).collect();
+        endpoint2
+            .am_send_flags(16, &[], &data, AmSendFlags::EAGER)
+            .await
+            .unwrap();
+        let mut msg = stream1.wait_msg().await.expect("no msg");
+        assert_eq!(msg.data_type(), Some(AmDataType::Data));
+        let handle = msg.take_data_handle().unwrap();
+        assert_eq!(msg.data_type(), None);
+        drop(msg);
+        assert_eq!(&handle[..], &data[..]);
+
+        // rendezvous data has no descriptor to hand out
+        let (_, mut msg) = tokio::join!(
+            async {
+                endpoint2
+                    .am_send_flags(16, &[], &data, AmSendFlags::RNDV)
+                    .await
+                    .unwrap();
+            },
+            async {
+                let mut msg = stream1.wait_msg().await.expect("no msg");
+                assert!(msg.take_data_handle().is_none());
+                assert_eq!(msg.data_type(), Some(AmDataType::Rndv));
+                assert_eq!(msg.recv_data().await.unwrap(), data);
+                msg
+            }
+        );
+        assert!(msg.take_data_handle().is_none());
+    }
+
+    #[test_log::test]
+    fn am_recv_cancelable() {
+        spawn_thread!(_am_recv_cancelable()).join().unwrap();
+    }
+
+    async fn _am_recv_cancelable() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let _stream1 = worker1.am_stream(16).unwrap();
+        assert!(worker1
+            .am_recv_cancelable(17, CancellationToken::new())
+            .await
+            .is_none());
+
+        // cancel a parked receive
+        let token = CancellationToken::new();
+        let (msg, _) = tokio::join!(worker1.am_recv_cancelable(16, token.clone()), async {
+            tokio::task::yield_now().await;
+            token.cancel();
+        });
+        assert!(msg.is_none());
+
+        // a queued message is returned even though the token is cancelled
+        endpoint2.am_send(16, &[1, 2, 3], &[]).await.unwrap();
+        while worker1.stats().am_received == 0 {
+            tokio::task::yield_now().await;
+        }
+        let msg = worker1.am_recv_cancelable(16, token).await.expect("no msg");
+        assert_eq!(msg.header(), &[1, 2, 3]);
+    }
+
+    #[test_log::test]
+    fn am_send_owned_buf() {
+        spawn_thread!(_am_send_owned_buf()).join().unwrap();
+    }
+
+    async fn _am_send_owned_buf() {
+        let (_endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        // a small eager message and a large rendezvous one,
+        // neither buffer is kept by the caller
+        let stream1 = worker1.am_stream(16).unwrap();
+        let small = vec![1_u8; 16];
+        let large = vec![2_u8; 1 << 20];
+        for (i, data) in [small.clone(), large.clone()].into_iter().enumerate() {
+            let header = [i as u8];
+            endpoint2
+                .am_send_owned_buf(16, &header, data, AmSendFlags::empty())
+                .unwrap();
+        }
+
+        // delivered in order
+        for (i, data) in [small, large].iter().enumerate() {
+            let mut msg = stream1.wait_msg().await.expect("no msg");
+            assert_eq!(msg.header(), &[i as u8]);
+            assert_eq!(&msg.recv_data().await.unwrap(), data);
+        }
+        while worker2.stats().am_pending_sends != 0 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[test_log::test]
+    fn am_register_inline() {
+        spawn_thread!(_am_register_inline()).join().unwrap();
+    }
+
+    async fn _am_register_inline() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let received = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let received1 = received.clone();
+        worker1
+            .am_register_inline(16, move |header, data| {
+                let data = data.expect("rendezvous data").to_vec();
+                received1.borrow_mut().push((header.to_vec(), data));
+            })
+            .unwrap();
+
+        endpoint2.am_send(16, &[1, 2], &[3, 4, 5]).await.unwrap();
+        while received.borrow().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(*received.borrow(), vec![(vec![1, 2], vec![3, 4, 5])]);
+        assert_eq!(worker1.stats().am_queue_depths.get(&16), None);
+
+        // a stream takes over the id
+        let stream1 = worker1.am_stream(16).unwrap();
+        endpoint2.am_send(16, &[6], &[]).await.unwrap();
+        let msg = stream1.wait_msg().await.expect("no msg");
+        assert_eq!(msg.header(), &[6]);
+        assert_eq!(received.borrow().len(), 1);
+    }
+
+    #[test_log::test]
+    fn am_register_inline_replace_self() {
+        spawn_thread!(_am_register_inline_replace_self())
+            .join()
+            .unwrap();
+    }
+
+    async fn _am_register_inline_replace_self() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        // the first handler frees itself by registering a second one
+        let received = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let (received1, worker) = (received.clone(), Rc::downgrade(&worker1));
+        worker1
+            .am_register_inline(17, move |header, _| {
+                let received2 = received1.clone();
+                let worker = worker.upgrade().unwrap();
+                worker
+                    .am_register_inline(17, move |header, _| {
+                        received2.borrow_mut().push((2, header.to_vec()));
+                    })
+                    .unwrap();
+                received1.borrow_mut().push((1, header.to_vec()));
+            })
+            .unwrap();
+
+        endpoint2.am_send(17, &[1], &[]).await.unwrap();
+        endpoint2.am_send(17, &[2], &[]).await.unwrap();
+        while received.borrow().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(*received.borrow(), vec![(1, vec![1]), (2, vec![2])]);
+    }
+
+    #[test_log::test]
+    fn recv_data_chunk() {
+        spawn_thread!(_recv_data_chunk()).join().unwrap();
+    }
+
+    async fn _recv_data_chunk() {
+        const LEN: usize = 4 << 20;
+        const CHUNK: usize = 64 << 10;
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let data: Vec<u8> = (0..LEN).map(|i| (i % 251) as u8).collect();
+        let (_, received) = tokio::join!(
+            async {
+                endpoint2
+                    .am_send_flags(16, &[], &data, AmSendFlags::RNDV)
+                    .await
+                    .unwrap();
+            },
+            async {
+                let mut msg = stream1.wait_msg().await.expect("no msg");
+                assert_eq!(msg.data_type(), Some(AmDataType::Rndv));
+                assert_eq!(msg.remaining_data_len(), LEN);
+
+                assert_eq!(msg.recv_data_chunk(&mut []).await, Err(Error::InvalidParam));
+                assert_eq!(msg.remaining_data_len(), LEN);
+
+                let mut received = Vec::with_capacity(LEN);
+                let mut buf = vec![0_u8; CHUNK];
+                loop {
+                    let len = msg.recv_data_chunk(&mut buf).await.unwrap();
+                    if len == 0 {
+                        break;
+                    }
+                    received.extend_from_slice(&buf[..len]);
+                    assert_eq!(msg.remaining_data_len(), LEN - received.len());
+                    if msg.remaining_data_len() > 0 {
+                        // not a partial receive: UCX fetched the whole
+                        // rendezvous payload on the first call
+                        assert_eq!(msg.data_type(), Some(AmDataType::Eager));
+                        // the rest can't be received as if nothing was consumed
+                        assert_eq!(msg.recv_data().await, Err(Error::InvalidParam));
+                        assert!(msg.take_data_handle().is_none());
+                    }
+                }
+                assert_eq!(msg.data_type(), None);
+                assert!(msg.recv_attrs().contains(AmRecvAttr::RNDV));
+                received
+            }
+        );
+        assert_eq!(received, data);
+    }
+
+    #[test_log::test]
+    fn recv_data_fill() {
+        spawn_thread!(_recv_data_fill()).join().unwrap();
+    }
+
+    async fn _recv_data_fill() {
+        const LEN: usize = 1000;
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let data: Vec<u8> = (0..LEN).map(|i| i as u8).collect();
+        for flags in [AmSendFlags::EAGER, AmSendFlags::RNDV] {
+            let (_, mut msg) = tokio::join!(
+                async {
+                    endpoint2
+                        .am_send_flags(16, &[], &data, flags)
+                        .await
+                        .unwrap();
+                },
+                async { stream1.wait_msg().await.expect("no msg") }
+            );
+
+            // stops at the capacity of the slices
+            let mut head = [0_u8; 100];
+            let mut body = [0_u8; 200];
+            let mut iov = [IoSliceMut::new(&mut head), IoSliceMut::new(&mut body)];
+            assert_eq!(msg.recv_data_fill(&mut iov).await, Ok(300));
+            assert_eq!(msg.remaining_data_len(), LEN - 300);
+            assert_eq!(&head[..], &data[..100]);
+            assert_eq!(&body[..], &data[100..300]);
+
+            // stops when the data is exhausted
+            let mut rest = vec![0_u8; LEN];
+            let mut iov = [IoSliceMut::new(&mut rest)];
+            assert_eq!(msg.recv_data_fill(&mut iov).await, Ok(LEN - 300));
+            assert_eq!(&rest[..LEN - 300], &data[300..]);
+            assert_eq!(msg.recv_data_fill(&mut iov).await, Ok(0));
+        }
+    }
+
+    #[test_log::test]
+    fn am_try_recv() {
+        spawn_thread!(_am_try_recv()).join().unwrap();
+    }
+
+    async fn _am_try_recv() {
+        let context1 = Context::new().unwrap();
+        let worker1 = context1.create_worker().unwrap();
+        let context2 = Context::new().unwrap();
+        let worker2 = context2.create_worker().unwrap();
+        let polling1 = tokio::task::spawn_local(worker1.clone().polling());
+        let polling2 = tokio::task::spawn_local(worker2.clone().polling());
+
+        // connect with each other
+        let mut listener = worker1
+            .create_listener("0.0.0.0:0".parse().unwrap())
+            .unwrap();
+        let listen_port = listener.socket_addr().unwrap().port();
+        let mut addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        addr.set_port(listen_port);
+        let (_endpoint1, endpoint2) = tokio::join!(
+            async {
+                let conn1 = listener.next().await;
+                worker1.accept(conn1).await.unwrap()
+            },
+            async { worker2.connect_socket(addr).await.unwrap() },
+        );
+        let _stream1 = worker1.am_stream(16).unwrap();
+        assert!(worker1.am_try_recv(16).is_none());
+        assert!(worker1.am_try_recv(17).is_none());
+
+        // drive progress by hand from here on
+        polling1.abort();
+        polling2.abort();
+        endpoint2
+            .am_send_owned_buf(16, &[1], vec![2; 8], AmSendFlags::empty())
+            .unwrap();
+        let msg = loop {
+            worker2.progress();
+            worker1.progress();
+            if let Some(msg) = worker1.am_try_recv(16) {
+                break msg;
+            }
+        };
+        assert_eq!(msg.header(), &[1]);
+        assert_eq!(msg.get_data(), Some(&[2; 8][..]));
+        assert!(worker1.am_try_recv(16).is_none());
+    }
+
+    #[test_log::test]
+    fn recv_data_into_mem() {
+        spawn_thread!(_recv_data_into_mem()).join().unwrap();
+    }
+
+    async fn _recv_data_into_mem() {
+        const LEN: usize = 1 << 20;
+        const OFFSET: usize = 16;
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let data: Vec<u8> = (0..LEN).map(|x| x as u8).collect();
+        let mut buf = vec![0_u8; LEN + OFFSET];
+        let mem = MemoryHandle::register(&worker1.context, &mut buf);
+        for (len, flags) in [(64, AmSendFlags::EAGER), (LEN, AmSendFlags::RNDV)] {
+            let (sent, received) = tokio::join!(
+                endpoint2.am_send_flags(16, &[], &data[..len], flags),
+                async {
+                    let mut msg = stream1.wait_msg().await.expect("no msg");
+                    // doesn't fit, the data is kept
+                    let result = unsafe { msg.recv_data_into_mem(&mem, LEN).await };
+                    assert_eq!(result, Err(Error::OutOfRange));
+                    // `buf` is only accessed through `mem` until it is dropped
+                    unsafe { msg.recv_data_into_mem(&mem, OFFSET).await }
+                }
+            );
+            assert_eq!(sent, Ok(len));
+            assert_eq!(received, Ok(len));
+        }
+        drop(mem);
+        assert_eq!(&buf[..OFFSET], &[0; OFFSET]);
+        assert_eq!(&buf[OFFSET..], &data[..]);
+    }
+
+    #[test_log::test]
+    fn header_only() {
+        spawn_thread!(_header_only()).join().unwrap();
+    }
+
+    async fn _header_only() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let empty: [IoSlice; 0] = [];
+        let sends: [&[IoSlice]; 3] = [&empty, &[IoSlice::new(&[])], &[IoSlice::new(&[]); 2]];
+        for (i, data) in sends.into_iter().enumerate() {
+            let header = [i as u8; 8];
+            let (sent, mut msg) = tokio::join!(
+                async {
+                    endpoint2
+                        .am_send_vectorized(16, &header, data, AmSendFlags::empty())
+                        .await
+                },
+                async { stream1.wait_msg().await.expect("no msg") },
+            );
+            assert_eq!(sent, Ok(0));
+            assert_eq!(msg.header(), &header);
+            assert!(!msg.contains_data());
+            assert_eq!(msg.recv_data().await.unwrap(), Vec::<u8>::new());
+        }
+    }
+
+    #[test_log::test]
+    fn am_send_order() {
+        spawn_thread!(_am_send_order()).join().unwrap();
+    }
+
+    async fn _am_send_order() {
+        const COUNT: u32 = 10_000;
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        // submit all sends back-to-back, mixing in rendezvous ones
+        let mut sends: Vec<_> = (0..COUNT)
+            .map(|seq| {
+                let flags = if seq % 7 == 0 {
+                    AmSendFlags::RNDV
+                } else {
+                    AmSendFlags::empty()
+                };
+                Box::pin(endpoint2.am_send_owned(16, seq.to_le_bytes(), vec![seq as u8; 64], flags))
+            })
+            .collect();
+        for send in sends.iter_mut() {
+            let _ = futures::poll!(send.as_mut());
+        }
+        let (sent, _) = tokio::join!(futures::future::join_all(sends), async {
+            for seq in 0..COUNT {
+                let mut msg = stream1.wait_msg().await.expect("no msg");
+                let mut received = [0; 4];
+                received.copy_from_slice(msg.header());
+                assert_eq!(u32::from_le_bytes(received), seq);
+                assert_eq!(msg.recv_data().await.unwrap(), vec![seq as u8; 64]);
+            }
+        });
+        assert!(sent.into_iter().all(|len| len == Ok(64)));
+    }
+
+    #[test_log::test]
+    fn am_send_flood() {
+        spawn_thread!(_am_send_flood()).join().unwrap();
+    }
+
+    async fn _am_send_flood() {
+        const COUNT: usize = 4096;
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        // submit far more than the transport can take before draining any
+        let stream1 = worker1.am_stream(16).unwrap();
+        let data = vec![1; 64 * 1024];
+        let mut sends: Vec<_> = (0..COUNT)
+            .map(|_| Box::pin(endpoint2.am_send(16, &[], &data)))
+            .collect();
+        for send in sends.iter_mut() {
+            let _ = futures::poll!(send.as_mut());
+        }
+        let (sent, _) = tokio::join!(futures::future::join_all(sends), async {
+            for _ in 0..COUNT {
+                let mut msg = stream1.wait_msg().await.expect("no msg");
+                assert_eq!(msg.recv_data().await.unwrap().len(), data.len());
+            }
+        });
+        assert!(sent.into_iter().all(|len| len == Ok(data.len())));
+    }
+
+    #[test_log::test]
+    fn am_eager_fragmented() {
+        spawn_thread!(_am_eager_fragmented()).join().unwrap();
+    }
+
+    async fn _am_eager_fragmented() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        // far beyond any transport segment, so eager sends go in fragments
+        let stream1 = worker1.am_stream(16).unwrap();
+        for len in [64 << 10, 1 << 20, 4 << 20] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let (sent, received) = tokio::join!(
+                endpoint2.am_send_flags(16, &[1, 2], &data, AmSendFlags::EAGER),
+                async {
+                    let mut msg = stream1.wait_msg().await.expect("no msg");
+                    assert_eq!(msg.header(), &[1, 2]);
+                    assert_eq!(msg.data_len(), len);
+                    assert!(!msg.recv_attrs().contains(AmRecvAttr::RNDV));
+                    msg.recv_data().await.unwrap()
+                }
+            );
+            assert_eq!(sent, Ok(len));
+            assert!(received == data);
+        }
+    }
+
+    #[test_log::test]
+    fn am_send_start() {
+        spawn_thread!(_am_send_start()).join().unwrap();
+    }
+
+    async fn _am_send_start() {
+        let (_endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let data: Vec<Vec<u8>> = (0..3).map(|i| vec![i; 1 << 20]).collect();
+        let headers = [[0], [1], [2]];
+        let handles: Vec<_> = (0..3)
+            .map(|i| {
+                endpoint2
+                    .am_send_start(16, &headers[i], data[i].clone(), AmSendFlags::RNDV)
+                    .unwrap()
+            })
+            .collect();
+
+        // complete them in reverse order while receiving in order
+        let (_, sent) = tokio::join!(
+            async {
+                for i in 0..3 {
+                    let mut msg = stream1.wait_msg().await.expect("no msg");
+                    assert_eq!(msg.header(), &headers[i]);
+                    assert_eq!(msg.recv_data().await.unwrap(), data[i]);
+                }
+            },
+            async {
+                let mut sent = Vec::new();
+                for handle in handles.into_iter().rev() {
+                    sent.push(handle.await);
+                }
+                sent
+            }
+        );
+        assert!(sent.into_iter().all(|len| len == Ok(1 << 20)));
+
+        // a completed send can't be canceled
+        let mut handle = endpoint2
+            .am_send_start(16, &[3], Vec::new(), AmSendFlags::EAGER)
+            .unwrap();
+        let msg = stream1.wait_msg().await.expect("no msg");
+        assert_eq!(msg.header(), &[3]);
+        while !handle.is_complete() {
+            tokio::task::yield_now().await;
+        }
+        handle.cancel();
+        assert_eq!((&mut handle).await, Ok(0));
+
+        // a dropped handle leaves the send running
+        let handle = endpoint2
+            .am_send_start(16, &[4], data[0].clone(), AmSendFlags::RNDV)
+            .unwrap();
+        drop(handle);
+        let mut msg = stream1.wait_msg().await.expect("no msg");
+        assert_eq!(msg.header(), &[4]);
+        assert_eq!(msg.recv_data().await.unwrap(), data[0]);
+        while worker2.stats().am_pending_sends != 0 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[test_log::test]
+    fn discard_data() {
+        spawn_thread!(_discard_data()).join().unwrap();
+    }
+
+    async fn _discard_data() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let data = vec![1; 1 << 20];
+        for flags in [AmSendFlags::EAGER, AmSendFlags::RNDV] {
+            // the send completes while the message is still held
+            let (sent, mut msg) =
+                tokio::join!(endpoint2.am_send_flags(16, &[1], &data, flags), async {
+                    let mut msg = stream1.wait_msg().await.expect("no msg");
+                    assert_eq!(msg.header(), &[1]);
+                    assert!(msg.contains_data());
+                    msg.discard_data();
+                    msg
+                });
+            assert_eq!(sent, Ok(data.len()));
+            assert!(!msg.contains_data());
+            assert_eq!(msg.data_len(), 0);
+            assert_eq!(msg.recv_data().await.unwrap(), Vec::<u8>::new());
+        }
+    }
+
+    #[test_log::test]
+    fn into_owned() {
+        spawn_thread!(_into_owned()).join().unwrap();
+    }
+
+    async fn _into_owned() {
+        let (_endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        // eager, data and rendezvous messages
+        let stream1 = worker1.am_stream(16).unwrap();
+        let stream2 = worker2.am_stream(17).unwrap();
+        let sizes = [8, 64 << 10, 1 << 20];
+        let flags = [AmSendFlags::EAGER, AmSendFlags::empty(), AmSendFlags::RNDV];
+        let (_, msgs) = tokio::join!(
+            async {
+                for i in 0..3 {
+                    let data = vec![i as u8; sizes[i]];
+                    let flags = flags[i] | AmSendFlags::REPLY;
+                    endpoint2
+                        .am_send_flags(16, &[i as u8], &data, flags)
+                        .await
+                        .unwrap();
+                }
+            },
+            async {
+                let mut msgs = Vec::new();
+                for _ in 0..3 {
+                    let msg = stream1.wait_msg().await.expect("no msg");
+                    msgs.push(msg.into_owned().await.unwrap());
+                }
+                msgs
+            }
+        );
+
+        // process them after the receive loop, in a 'static task
+        let handle = tokio::task::spawn_local(async move {
+            for (i, mut msg) in msgs.into_iter().enumerate() {
+                assert_eq!(msg.id(), 16);
+                assert_eq!(msg.header(), &[i as u8]);
+                assert_eq!(msg.take_data(), vec![i as u8; sizes[i]]);
+                assert!(msg.need_reply());
+                unsafe { msg.reply(17, &[i as u8], &[], AmSendFlags::empty()) }
+                    .await
+                    .unwrap();
+            }
+        });
+        for i in 0..3 {
+            let msg = stream2.wait_msg().await.expect("no reply");
+            assert_eq!(msg.header(), &[i as u8]);
+        }
+        handle.await.unwrap();
+    }
 }
@@ -0,0 +1,364 @@
+use super::*;
+
+/// Callback invoked with the error when the peer of an endpoint fails.
+pub type ErrorHandler = Rc<dyn Fn(Error)>;
+
+/// Error handling mode of an [`Endpoint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrHandlingMode {
+    /// No guarantee about error reporting, for the best performance.
+    None,
+    /// Failures of the peer are reported, at some performance cost.
+    Peer,
+}
+
+impl From<ErrHandlingMode> for ucp_err_handling_mode_t {
+    fn from(mode: ErrHandlingMode) -> Self {
+        match mode {
+            ErrHandlingMode::None => ucp_err_handling_mode_t::UCP_ERR_HANDLING_MODE_NONE,
+            ErrHandlingMode::Peer => ucp_err_handling_mode_t::UCP_ERR_HANDLING_MODE_PEER,
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// Extra flags of an endpoint, see [`EndpointBuilder::flags`].
+    pub struct EndpointFlags: u32 {
+        /// Don't connect to a listener of the same worker.
+        const NO_LOOPBACK = ucp_ep_params_flags_field::UCP_EP_PARAMS_FLAGS_NO_LOOPBACK.0;
+        /// Send the client id of the worker along with the connection request.
+        const SEND_CLIENT_ID = ucp_ep_params_flags_field::UCP_EP_PARAMS_FLAGS_SEND_CLIENT_ID.0;
+    }
+}
+
+/// Builder of an [`Endpoint`] connecting to a remote listener,
+/// created by [`Worker::endpoint_builder`].
+#[derive(Debug)]
+pub struct EndpointBuilder<'a> {
+    worker: &'a Rc<Worker>,
+    params: ConnectParams,
+}
+
+// Kept by the endpoint to `reconnect` with the same parameters.
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub(super) struct ConnectParams {
+    remote_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+    err_mode: ErrHandlingMode,
+    #[derivative(Debug = "ignore")]
+    pub(super) error_handler: Option<ErrorHandler>,
+    flags: EndpointFlags,
+    name: Option<String>,
+    pub(super) max_inflight: Option<usize>,
+}
+
+impl<'a> EndpointBuilder<'a> {
+    pub(super) fn new(worker: &'a Rc<Worker>) -> Self {
+        EndpointBuilder {
+            worker,
+            params: ConnectParams {
+                remote_addr: None,
+                local_addr: None,
+                err_mode: ErrHandlingMode::Peer,
+                error_handler: None,
+                flags: EndpointFlags::empty(),
+                name: None,
+                max_inflight: None,
+            },
+        }
+    }
+
+    /// Address of the remote listener, required.
+    pub fn connect(mut self, addr: SocketAddr) -> Self {
+        self.params.remote_addr = Some(addr);
+        self
+    }
+
+    /// Local address to connect from, chosen by the system by default.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.params.local_addr = Some(addr);
+        self
+    }
+
+    /// Error handling mode, [`ErrHandlingMode::Peer`] by default.
+    ///
+    /// Peer failures are only reported in the peer mode.
+    pub fn err_mode(mut self, err_mode: ErrHandlingMode) -> Self {
+        self.params.err_mode = err_mode;
+        self
+    }
+
+    /// Callback invoked on peer failure, in addition to updating the endpoint status.
+    ///
+    /// It is called inside worker progress, so it must not block.
+    pub fn error_handler(mut self, handler: impl Fn(Error) + 'static) -> Self {
+        self.params.error_handler = Some(Rc::new(handler));
+        self
+    }
+
+    /// Extra endpoint flags, none by default.
+    pub fn flags(mut self, flags: EndpointFlags) -> Self {
+        self.params.flags = flags;
+        self
+    }
+
+    /// Name of the endpoint shown in UCX logs and [`Endpoint::query`].
+    pub fn name(mut self, name: &str) -> Self {
+        self.params.name = Some(name.to_string());
+        self
+    }
+
+    /// Maximal number of sends in flight, unlimited by default.
+    ///
+    /// Once reached, active message, tag and stream sends wait for a previous
+    /// send to complete before submitting, in the order they started waiting.
+    /// Sends submitted without awaiting, like `Endpoint::am_send_start`,
+    /// fail with [`Error::NoReource`] instead.
+    pub fn max_inflight(mut self, max: usize) -> Self {
+        self.params.max_inflight = Some(max);
+        self
+    }
+
+    /// Create the `Endpoint` and wait until it is connected.
+    ///
+    /// Fails with [`Error::InvalidParam`] if no remote address is given.
+    pub async fn build(self) -> Result<Endpoint, Error> {
+        connect(self.worker, self.params).await
+    }
+}
+
+pub(super) async fn connect(
+    worker: &Rc<Worker>,
+    connect: ConnectParams,
+) -> Result<Endpoint, Error> {
+    let addr = connect.remote_addr.ok_or(Error::InvalidParam)?;
+    let sockaddr = socket2::SockAddr::from(addr);
+    #[allow(invalid_value)]
+    #[allow(clippy::uninit_assumed_init)]
+    let mut params = ucp_ep_params {
+        field_mask: (ucp_ep_params_field::UCP_EP_PARAM_FIELD_FLAGS
+            | ucp_ep_params_field::UCP_EP_PARAM_FIELD_SOCK_ADDR
+            | ucp_ep_params_field::UCP_EP_PARAM_FIELD_ERR_HANDLING_MODE)
+            .0 as u64,
+        // the builder always connects to a listener
+        flags: connect.flags.bits()
+            | ucp_ep_params_flags_field::UCP_EP_PARAMS_FLAGS_CLIENT_SERVER.0,
+        sockaddr: ucs_sock_addr {
+            addr: sockaddr.as_ptr() as _,
+            addrlen: sockaddr.len(),
+        },
+        err_mode: connect.err_mode.into(),
+        ..unsafe { MaybeUninit::uninit().assume_init() }
+    };
+    // UCX copies them in `ucp_ep_create`
+    let local_sockaddr = connect.local_addr.map(socket2::SockAddr::from);
+    if let Some(local_sockaddr) = &local_sockaddr {
+        params.field_mask |= ucp_ep_params_field::UCP_EP_PARAM_FIELD_LOCAL_SOCK_ADDR.0 as u64;
+        params.local_sockaddr = ucs_sock_addr {
+            addr: local_sockaddr.as_ptr() as _,
+            addrlen: local_sockaddr.len(),
+        };
+    }
+    let name = match &connect.name {
+        Some(name) => Some(CString::new(name.as_str()).map_err(|_| Error::InvalidParam)?),
+        None => None,
+    };
+    if let Some(name) = &name {
+        params.field_mask |= ucp_ep_params_field::UCP_EP_PARAM_FIELD_NAME.0 as u64;
+        params.name = name.as_ptr();
+    }
+    let endpoint = Endpoint::create(worker, params, Some(connect))?;
+
+    // Workaround for UCX bug: https://github.com/openucx/ucx/issues/6872
+    // This also waits until the connection is established.
+    // If the future is dropped meanwhile, `endpoint` is dropped after `buf`
+    // and force closed, which cancels the send before UCX reads `buf`.
+    let buf = [0, 1, 2, 3];
+    endpoint.stream_send(&buf).await?;
+
+    Ok(endpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use std::cell::RefCell;
+
+    #[test_log::test]
+    fn builder() {
+        spawn_thread!(_builder()).join().unwrap();
+    }
+
+    async fn _builder() {
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let errors2 = errors.clone();
+        let (endpoint1, _, endpoint2, worker2, addr) =
+            loopback_connect(&Config::new().unwrap(), |worker2, addr| async move {
+                worker2
+                    .endpoint_builder()
+                    .connect(addr)
+                    .bind("127.0.0.1:0".parse().unwrap())
+                    .name("builder-test")
+                    .err_mode(ErrHandlingMode::Peer)
+                    .flags(EndpointFlags::NO_LOOPBACK)
+                    .error_handler(move |err| errors2.borrow_mut().push(err))
+                    .build()
+                    .await
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            worker2.endpoint_builder().build().await.err(),
+            Some(Error::InvalidParam)
+        );
+        let attr = endpoint2.query().unwrap();
+        assert_eq!(attr.name, "builder-test");
+        assert_eq!(attr.remote_addr, Some(addr));
+        assert!(errors.borrow().is_empty());
+
+        // the handler observes the peer going away
+        endpoint1.close(false).await.unwrap();
+        endpoint2.wait_error().await;
+        assert_eq!(*errors.borrow(), vec![Error::ConnectionReset]);
+    }
+
+    #[test_log::test]
+    fn reconnect() {
+        spawn_thread!(_reconnect()).join().unwrap();
+    }
+
+    async fn _reconnect() {
+        let config = Config::new().unwrap();
+        let (endpoint1, worker1, endpoint2, _, addr) = loopback_with_config(&config).await.unwrap();
+
+        // kill the peer
+        drop(endpoint1);
+        drop(worker1);
+        endpoint2.wait_error().await;
+        assert!(!endpoint2.is_alive());
+
+        // restart it on the same port and reconnect
+        let worker1 = spawn_worker(&config).unwrap();
+        let mut bind: SocketAddr = "0.0.0.0:0".parse().unwrap();
+        bind.set_port(addr.port());
+        let mut listener = worker1.create_listener(bind).unwrap();
+        let (_endpoint1, endpoint3) = tokio::join!(
+            async {
+                let conn1 = listener.next().await;
+                worker1.accept(conn1).await.unwrap()
+            },
+            async { endpoint2.reconnect().await.unwrap() },
+        );
+        assert!(endpoint3.is_alive());
+        tokio::join!(
+            async { endpoint3.tag_send(1, &[1]).await.unwrap() },
+            async {
+                let mut buf = [MaybeUninit::uninit(); 1];
+                worker1.tag_recv(1, &mut buf).await.unwrap()
+            }
+        );
+
+        // accepted endpoints don't know where to reconnect
+        let (endpoint1, _, _endpoint4, _) = loopback().await.unwrap();
+        assert_eq!(endpoint1.reconnect().await.err(), Some(Error::InvalidParam));
+    }
+
+    #[test_log::test]
+    fn max_inflight() {
+        spawn_thread!(_max_inflight()).join().unwrap();
+    }
+
+    async fn _max_inflight() {
+        const COUNT: usize = 10;
+        const LEN: usize = 1 << 20;
+        let (_endpoint1, worker1, endpoint2, worker2, _) =
+            loopback_connect(&Config::new().unwrap(), |worker2, addr| async move {
+                worker2
+                    .endpoint_builder()
+                    .connect(addr)
+                    .max_inflight(2)
+                    .build()
+                    .await
+            })
+            .await
+            .unwrap();
+
+        // rendezvous sends stay in flight until received
+        let data = vec![1; LEN];
+        let mut sends: Vec<_> = (0..COUNT)
+            .map(|tag| Box::pin(endpoint2.tag_send(tag as u64, &data)))
+            .collect();
+        for send in sends.iter_mut() {
+            assert!(futures::poll!(send.as_mut()).is_pending());
+        }
+        assert_eq!(worker2.inflight_requests(), 2);
+
+        let (sent, _) = tokio::join!(futures::future::join_all(sends), async {
+            let mut buf = vec![MaybeUninit::uninit(); LEN];
+            for tag in 0..COUNT {
+                let len = worker1.tag_recv(tag as u64, &mut buf).await.unwrap();
+                assert_eq!(len, LEN);
+                assert!(worker2.inflight_requests() <= 2);
+            }
+        });
+        assert!(sent.into_iter().all(|len| len == Ok(LEN)));
+    }
+
+    #[cfg(feature = "am")]
+    #[test_log::test]
+    fn max_inflight_am() {
+        spawn_thread!(_max_inflight_am()).join().unwrap();
+    }
+
+    #[cfg(feature = "am")]
+    async fn _max_inflight_am() {
+        use std::io::IoSlice;
+
+        const COUNT: usize = 8;
+        const LEN: usize = 1 << 20;
+        let (_endpoint1, worker1, endpoint2, worker2, _) =
+            loopback_connect(&Config::new().unwrap(), |worker2, addr| async move {
+                worker2
+                    .endpoint_builder()
+                    .connect(addr)
+                    .max_inflight(2)
+                    .build()
+                    .await
+            })
+            .await
+            .unwrap();
+        let stream1 = worker1.am_stream(16).unwrap();
+        let receive = || async {
+            for i in 0..COUNT {
+                let mut msg = stream1.wait_msg().await.expect("no msg");
+                assert_eq!(msg.header(), [i as u8]);
+                assert_eq!(msg.recv_data().await.unwrap().len(), LEN);
+            }
+        };
+
+        // waiting sends are submitted in the order they started waiting
+        let data = vec![1; LEN];
+        let mut sends: Vec<_> = (0..COUNT)
+            .map(|i| Box::pin(endpoint2.am_send(16, &[i as u8], &data)))
+            .collect();
+        for send in sends.iter_mut() {
+            assert!(futures::poll!(send.as_mut()).is_pending());
+        }
+        let started = endpoint2.am_send_start(16, &[], Vec::new(), AmSendFlags::empty());
+        assert_eq!(started.err(), Some(Error::NoReource));
+        let (sent, _) = tokio::join!(futures::future::join_all(sends), receive());
+        assert!(sent.into_iter().all(|len| len == Ok(LEN)));
+
+        // a batch larger than the limit
+        let headers: Vec<_> = (0..COUNT as u8).map(|i| [i]).collect();
+        let iov = [IoSlice::new(&data)];
+        let msgs: Vec<_> = headers
+            .iter()
+            .map(|header| (16, &header[..], &iov[..]))
+            .collect();
+        let (sent, _) = tokio::join!(endpoint2.am_send_batch(&msgs), receive());
+        assert_eq!(sent, Ok(()));
+    }
+}
@@ -0,0 +1,332 @@
+use super::tag::poll_tag;
+use super::*;
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// A non-contiguous data layout that UCX packs and unpacks through callbacks.
+///
+/// Implement this for layouts that are neither contiguous nor expressible as
+/// an iovec, e.g. strided matrix tiles, and pass the value to
+/// `tag_send_generic`/`tag_recv_generic`.
+/// Offsets are into the packed (contiguous) representation.
+pub trait Datatype: 'static {
+    /// Length of the packed representation.
+    ///
+    /// On the receive side this is the capacity; longer messages fail with
+    /// [`Error::MessageTruncated`].
+    fn packed_size(&self) -> usize;
+
+    /// Packs data starting at `offset` into `dest`, returning the number of bytes written.
+    fn pack(&self, offset: usize, dest: &mut [u8]) -> usize;
+
+    /// Unpacks `src`, which starts at `offset` of the packed representation.
+    fn unpack(&mut self, offset: usize, src: &[u8]);
+}
+
+lazy_static::lazy_static! {
+    // UCX may still call into a datatype after the request using it completes,
+    // so datatypes are created once per type and never destroyed.
+    static ref DATATYPES: Mutex<HashMap<TypeId, ucp_datatype_t>> = Mutex::new(HashMap::new());
+}
+
+unsafe extern "C" fn start_pack<T: Datatype>(
+    _context: *mut c_void,
+    buffer: *const c_void,
+    _count: usize,
+) -> *mut c_void {
+    buffer as _
+}
+
+unsafe extern "C" fn start_unpack<T: Datatype>(
+    _context: *mut c_void,
+    buffer: *mut c_void,
+    _count: usize,
+) -> *mut c_void {
+    buffer
+}
+
+unsafe extern "C" fn packed_size<T: Datatype>(state: *mut c_void) -> usize {
+    (*(state as *const T)).packed_size()
+}
+
+unsafe extern "C" fn pack<T: Datatype>(
+    state: *mut c_void,
+    offset: usize,
+    dest: *mut c_void,
+    max_length: usize,
+) -> usize {
+    let dest = std::slice::from_raw_parts_mut(dest as *mut u8, max_length);
+    (*(state as *const T)).pack(offset, dest)
+}
+
+unsafe extern "C" fn unpack<T: Datatype>(
+    state: *mut c_void,
+    offset: usize,
+    src: *const c_void,
+    length: usize,
+) -> ucs_status_t {
+    let src = std::slice::from_raw_parts(src as *const u8, length);
+    (*(state as *mut T)).unpack(offset, src);
+    ucs_status_t::UCS_OK
+}
+
+unsafe extern "C" fn finish(_state: *mut c_void) {}
+
+pub(super) fn generic_datatype<T: Datatype>() -> Result<ucp_datatype_t, Error> {
+    let mut datatypes = DATATYPES.lock().unwrap();
+    if let Some(&datatype) = datatypes.get(&TypeId::of::<T>()) {
+        return Ok(datatype);
+    }
+    let ops = ucp_generic_dt_ops_t {
+        start_pack: Some(start_pack::<T>),
+        start_unpack: Some(start_unpack::<T>),
+        packed_size: Some(packed_size::<T>),
+        pack: Some(pack::<T>),
+        unpack: Some(unpack::<T>),
+        finish: Some(finish),
+    };
+    let mut datatype = MaybeUninit::uninit();
+    let status = unsafe { ucp_dt_create_generic(&ops, null_mut(), datatype.as_mut_ptr()) };
+    Error::from_status(status)?;
+    let datatype = unsafe { datatype.assume_init() };
+    datatypes.insert(TypeId::of::<T>(), datatype);
+    Ok(datatype)
+}
+
+impl Worker {
+    /// Receives a message with `tag`, unpacking it into `data`.
+    ///
+    /// Returns the length of the packed message.
+    pub async fn tag_recv_generic<T: Datatype>(
+        &self,
+        tag: u64,
+        data: &mut T,
+    ) -> Result<usize, Error> {
+        trace!(
+            "tag_recv_generic: worker={:?}, tag={}, len={}",
+            self.handle,
+            tag,
+            data.packed_size()
+        );
+        unsafe extern "C" fn callback(
+            request: *mut c_void,
+            status: ucs_status_t,
+            info: *mut ucp_tag_recv_info,
+        ) {
+            let length = (*info).length;
+            trace!(
+                "tag_recv_generic: complete. req={:?}, status={:?}, len={}",
+                request,
+                status,
+                length
+            );
+            let request = &mut *(request as *mut Request);
+            request.waker.wake();
+        }
+        let datatype = generic_datatype::<T>()?;
+        let status = unsafe {
+            ucp_tag_recv_nb(
+                self.handle,
+                data as *mut T as _,
+                1,
+                datatype,
+                tag,
+                u64::max_value(),
+                Some(callback),
+            )
+        };
+        Error::from_ptr(status)?;
+        RequestHandle::new(self, status, poll_tag)
+            .await
+            .map(|info| info.1)
+    }
+}
+
+impl Endpoint {
+    /// Sends `data` with `tag`, packing it through its [`Datatype`] implementation.
+    ///
+    /// Returns the length of the packed message.
+    pub async fn tag_send_generic<T: Datatype>(&self, tag: u64, data: &T) -> Result<usize, Error> {
+        trace!(
+            "tag_send_generic: endpoint={:?} len={}",
+            self.handle,
+            data.packed_size()
+        );
+        unsafe extern "C" fn callback(request: *mut c_void, status: ucs_status_t) {
+            trace!(
+                "tag_send_generic: complete. req={:?}, status={:?}",
+                request,
+                status
+            );
+            let request = &mut *(request as *mut Request);
+            request.waker.wake();
+        }
+        let datatype = generic_datatype::<T>()?;
+        let _permit = self.send_permit().await;
+        let status = unsafe {
+            ucp_tag_send_nb(
+                self.get_handle()?,
+                data as *const T as _,
+                1,
+                datatype,
+                tag,
+                Some(callback),
+            )
+        };
+        if status.is_null() {
+            trace!("tag_send_generic: complete");
+        } else if UCS_PTR_IS_PTR(status) {
+            RequestHandle::new(self.worker(), status, poll_normal).await?;
+        } else {
+            return Err(Error::from_ptr(status).unwrap_err());
+        }
+        Ok(data.packed_size())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every `stride`-th byte of `data`.
+    struct Strided {
+        data: Vec<u8>,
+        stride: usize,
+    }
+
+    impl Datatype for Strided {
+        fn packed_size(&self) -> usize {
+            (self.data.len() + self.stride - 1) / self.stride
+        }
+
+        fn pack(&self, offset: usize, dest: &mut [u8]) -> usize {
+            let elems = self.data.iter().step_by(self.stride).skip(offset);
+            let mut len = 0;
+            for (dest, src) in dest.iter_mut().zip(elems) {
+                *dest = *src;
+                len += 1;
+            }
+            len
+        }
+
+        fn unpack(&mut self, offset: usize, src: &[u8]) {
+            let elems = self.data.iter_mut().step_by(self.stride).skip(offset);
+            for (dest, src) in elems.zip(src) {
+                *dest = *src;
+            }
+        }
+    }
+
+    #[test_log::test]
+    fn generic() {
+        for i in 0..16_usize {
+            spawn_thread!(_generic(4 << i)).join().unwrap();
+        }
+    }
+
+    async fn _generic(len: usize) {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let send = Strided {
+            data: (0..len).map(|i| i as u8).collect(),
+            stride: 2,
+        };
+        let mut recv = Strided {
+            data: vec![0; len * 3],
+            stride: 6,
+        };
+        let (sent, received) = tokio::join!(
+            async { endpoint2.tag_send_generic(1, &send).await.unwrap() },
+            async { worker1.tag_recv_generic(1, &mut recv).await.unwrap() },
+        );
+        assert_eq!(sent, len / 2);
+        assert_eq!(received, len / 2);
+        for i in 0..len / 2 {
+            assert_eq!(recv.data[i * 6], send.data[i * 2]);
+        }
+        assert!(recv.data.iter().skip(1).step_by(6).all(|&x| x == 0));
+    }
+
+    /// A `HEIGHT`x`WIDTH` tile at `(row, col)` of a row-major `SIZE`x`SIZE` matrix.
+    #[cfg(feature = "am")]
+    struct Tile {
+        matrix: Vec<u8>,
+        row: usize,
+        col: usize,
+    }
+
+    #[cfg(feature = "am")]
+    impl Tile {
+        const SIZE: usize = 64;
+        const HEIGHT: usize = 16;
+        const WIDTH: usize = 8;
+
+        // index into the matrix of byte `offset` of the packed tile
+        fn index(&self, offset: usize) -> usize {
+            let (row, col) = (offset / Self::WIDTH, offset % Self::WIDTH);
+            (self.row + row) * Self::SIZE + self.col + col
+        }
+    }
+
+    #[cfg(feature = "am")]
+    impl Datatype for Tile {
+        fn packed_size(&self) -> usize {
+            Self::HEIGHT * Self::WIDTH
+        }
+
+        fn pack(&self, offset: usize, dest: &mut [u8]) -> usize {
+            let len = dest.len().min(self.packed_size() - offset);
+            for (i, dest) in dest[..len].iter_mut().enumerate() {
+                *dest = self.matrix[self.index(offset + i)];
+            }
+            len
+        }
+
+        fn unpack(&mut self, offset: usize, src: &[u8]) {
+            for (i, src) in src.iter().enumerate() {
+                let index = self.index(offset + i);
+                self.matrix[index] = *src;
+            }
+        }
+    }
+
+    #[cfg(feature = "am")]
+    #[test_log::test]
+    fn am_recv_tile() {
+        spawn_thread!(_am_recv_tile()).join().unwrap();
+    }
+
+    #[cfg(feature = "am")]
+    async fn _am_recv_tile() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let packed: Vec<u8> = (0..Tile::HEIGHT * Tile::WIDTH)
+            .map(|i| i as u8 + 1)
+            .collect();
+        for flags in [AmSendFlags::EAGER, AmSendFlags::RNDV] {
+            let mut tile = Tile {
+                matrix: vec![0; Tile::SIZE * Tile::SIZE],
+                row: 8,
+                col: 24,
+            };
+            let (sent, received) =
+                tokio::join!(endpoint2.am_send_flags(16, &[], &packed, flags), async {
+                    let mut msg = stream1.wait_msg().await.expect("no msg");
+                    msg.recv_data_dt(&mut tile).await
+                });
+            assert_eq!(sent, Ok(packed.len()));
+            assert_eq!(received, Ok(packed.len()));
+            for (i, x) in tile.matrix.iter().enumerate() {
+                let (row, col) = (i / Tile::SIZE, i % Tile::SIZE);
+                if (8..8 + Tile::HEIGHT).contains(&row) && (24..24 + Tile::WIDTH).contains(&col) {
+                    let offset = (row - 8) * Tile::WIDTH + col - 24;
+                    assert_eq!(*x, packed[offset]);
+                } else {
+                    assert_eq!(*x, 0);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use super::tag::poll_tag;
+use super::*;
+
+/// Type of the memory a buffer lives in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemType {
+    /// Default system memory.
+    Host,
+    /// NVIDIA CUDA device memory.
+    Cuda,
+    /// NVIDIA CUDA managed (unified) memory.
+    CudaManaged,
+    /// AMD ROCm device memory.
+    Rocm,
+}
+
+impl From<MemType> for ucs_memory_type_t {
+    fn from(mem_type: MemType) -> Self {
+        match mem_type {
+            MemType::Host => ucs_memory_type::UCS_MEMORY_TYPE_HOST,
+            MemType::Cuda => ucs_memory_type::UCS_MEMORY_TYPE_CUDA,
+            MemType::CudaManaged => ucs_memory_type::UCS_MEMORY_TYPE_CUDA_MANAGED,
+            MemType::Rocm => ucs_memory_type::UCS_MEMORY_TYPE_ROCM,
+        }
+    }
+}
+
+impl MemType {
+    fn from_raw(mem_type: ucs_memory_type_t) -> Option<Self> {
+        match mem_type {
+            ucs_memory_type::UCS_MEMORY_TYPE_HOST => Some(MemType::Host),
+            ucs_memory_type::UCS_MEMORY_TYPE_CUDA => Some(MemType::Cuda),
+            ucs_memory_type::UCS_MEMORY_TYPE_CUDA_MANAGED => Some(MemType::CudaManaged),
+            ucs_memory_type::UCS_MEMORY_TYPE_ROCM => Some(MemType::Rocm),
+            _ => None,
+        }
+    }
+}
+
+impl Context {
+    /// Whether the context supports memory of `mem_type`.
+    pub fn supports_mem_type(&self, mem_type: MemType) -> Result<bool, Error> {
+        let memory_types = self.query()?.memory_types;
+        Ok(memory_types & (1 << ucs_memory_type_t::from(mem_type) as u64) != 0)
+    }
+}
+
+impl MemoryHandle {
+    /// Register `len` bytes of `mem_type` memory at `ptr`, e.g. a CUDA allocation.
+    ///
+    /// # Safety
+    /// `ptr` must be valid for `len` bytes of `mem_type` memory while the handle lives.
+    pub unsafe fn register_mem_type(
+        context: &Arc<Context>,
+        ptr: *mut u8,
+        len: usize,
+        mem_type: MemType,
+    ) -> Result<Self, Error> {
+        Self::map(context, ptr, len, mem_type.into())
+    }
+
+    /// Type of the registered memory.
+    pub fn mem_type(&self) -> Option<MemType> {
+        MemType::from_raw(self.mem_type)
+    }
+}
+
+impl Worker {
+    /// Detect the type of the memory `len` bytes at `ptr` live in.
+    ///
+    /// The result can be passed to `tag_send_mem_type`/`tag_recv_mem_type`.
+    /// Detection maps the region into UCX for a moment, so cache the result
+    /// instead of calling this on every send.
+    /// Fails with [`Error::Unsupported`] for memory types `MemType` doesn't know.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to `len` bytes allocated in host or device memory,
+    /// which stay allocated until this returns. Device memory can't be
+    /// borrowed as a slice, hence the raw pointer.
+    pub unsafe fn detect_memory_type(&self, ptr: *const u8, len: usize) -> Result<MemType, Error> {
+        let context = self.context.handle;
+        #[allow(invalid_value)]
+        #[allow(clippy::uninit_assumed_init)]
+        let params = ucp_mem_map_params_t {
+            field_mask: (ucp_mem_map_params_field::UCP_MEM_MAP_PARAM_FIELD_ADDRESS
+                | ucp_mem_map_params_field::UCP_MEM_MAP_PARAM_FIELD_LENGTH)
+                .0 as u64,
+            address: ptr as _,
+            length: len as _,
+            ..unsafe { MaybeUninit::uninit().assume_init() }
+        };
+        let mut handle = MaybeUninit::uninit();
+        let status = unsafe { ucp_mem_map(context, &params, handle.as_mut_ptr()) };
+        Error::from_status(status)?;
+        let handle = unsafe { handle.assume_init() };
+
+        let mut attr = MaybeUninit::<ucp_mem_attr_t>::uninit();
+        unsafe { &mut *attr.as_mut_ptr() }.field_mask =
+            ucp_mem_attr_field::UCP_MEM_ATTR_FIELD_MEM_TYPE.0 as u64;
+        let status = unsafe { ucp_mem_query(handle, attr.as_mut_ptr()) };
+        unsafe { ucp_mem_unmap(context, handle) };
+        Error::from_status(status)?;
+        let mem_type = unsafe { attr.assume_init() }.mem_type;
+        trace!(
+            "detect_memory_type: ptr={:?}, len={}, mem_type={:?}",
+            ptr,
+            len,
+            mem_type
+        );
+        MemType::from_raw(mem_type).ok_or(Error::Unsupported)
+    }
+
+    /// Like `tag_recv`, except that `buf` is memory of `mem_type`.
+    ///
+    /// # Safety
+    /// `buf` must be valid for writing `len` bytes of `mem_type` memory.
+    pub async unsafe fn tag_recv_mem_type(
+        &self,
+        tag: u64,
+        buf: *mut u8,
+        len: usize,
+        mem_type: MemType,
+    ) -> Result<usize, Error> {
+        trace!(
+            "tag_recv_mem_type: worker={:?}, tag={}, len={}, mem_type={:?}",
+            self.handle,
+            tag,
+            len,
+            mem_type
+        );
+        unsafe extern "C" fn callback(
+            request: *mut c_void,
+            status: ucs_status_t,
+            info: *const ucp_tag_recv_info,
+            _user_data: *mut c_void,
+        ) {
+            let length = (*info).length;
+            trace!(
+                "tag_recv_mem_type: complete. req={:?}, status={:?}, len={}",
+                request,
+                status,
+                length
+            );
+            let request = &mut *(request as *mut Request);
+            request.waker.wake();
+        }
+        let mut info = MaybeUninit::<ucp_tag_recv_info>::uninit();
+        let mut param = MaybeUninit::<ucp_request_param_t>::uninit();
+        {
+            let param = &mut *param.as_mut_ptr();
+            param.op_attr_mask = ucp_op_attr_t::UCP_OP_ATTR_FIELD_CALLBACK as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_DATATYPE as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_MEMORY_TYPE as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_RECV_INFO as u32;
+            param.cb = ucp_request_param_t__bindgen_ty_1 {
+                recv: Some(callback),
+            };
+            param.datatype = ucp_dt_make_contig(1);
+            param.memory_type = mem_type.into();
+            param.recv_info = ucp_request_param_t__bindgen_ty_2 {
+                tag_info: info.as_mut_ptr(),
+            };
+        }
+        let status = ucp_tag_recv_nbx(
+            self.handle,
+            buf as _,
+            len as _,
+            tag,
+            u64::max_value(),
+            param.as_ptr(),
+        );
+        if status.is_null() {
+            let info = info.assume_init();
+            trace!("tag_recv_mem_type: complete. len={}", info.length);
+            Ok(info.length as usize)
+        } else if UCS_PTR_IS_PTR(status) {
+            RequestHandle::new(self, status, poll_tag)
+                .await
+                .map(|info| info.1)
+        } else {
+            Err(Error::from_ptr(status).unwrap_err())
+        }
+    }
+}
+
+impl Endpoint {
+    /// Like `tag_send`, except that `buf` is memory of `mem_type`.
+    ///
+    /// # Safety
+    /// `buf` must be valid for reading `len` bytes of `mem_type` memory.
+    pub async unsafe fn tag_send_mem_type(
+        &self,
+        tag: u64,
+        buf: *const u8,
+        len: usize,
+        mem_type: MemType,
+    ) -> Result<usize, Error> {
+        trace!(
+            "tag_send_mem_type: endpoint={:?} len={}, mem_type={:?}",
+            self.handle,
+            len,
+            mem_type
+        );
+        unsafe extern "C" fn callback(
+            request: *mut c_void,
+            status: ucs_status_t,
+            _user_data: *mut c_void,
+        ) {
+            trace!(
+                "tag_send_mem_type: complete. req={:?}, status={:?}",
+                request,
+                status
+            );
+            let request = &mut *(request as *mut Request);
+            request.waker.wake();
+        }
+        let handle = self.get_handle()?;
+        let mut param = MaybeUninit::<ucp_request_param_t>::uninit();
+        {
+            let param = &mut *param.as_mut_ptr();
+            param.op_attr_mask = ucp_op_attr_t::UCP_OP_ATTR_FIELD_CALLBACK as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_DATATYPE as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_MEMORY_TYPE as u32;
+            param.cb = ucp_request_param_t__bindgen_ty_1 {
+                send: Some(callback),
+            };
+            param.datatype = ucp_dt_make_contig(1);
+            param.memory_type = mem_type.into();
+        }
+        let status = ucp_tag_send_nbx(handle, buf as _, len as _, tag, param.as_ptr());
+        if status.is_null() {
+            trace!("tag_send_mem_type: complete");
+        } else if UCS_PTR_IS_PTR(status) {
+            RequestHandle::new(self.worker(), status, poll_normal).await?;
+        } else {
+            return Err(Error::from_ptr(status).unwrap_err());
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // cudart is loaded at runtime, so the tests link on hosts without CUDA
+    type CudaMalloc = unsafe extern "C" fn(*mut *mut c_void, usize) -> i32;
+    type CudaFree = unsafe extern "C" fn(*mut c_void) -> i32;
+    type CudaMemcpy = unsafe extern "C" fn(*mut c_void, *const c_void, usize, i32) -> i32;
+
+    struct Cudart {
+        malloc: CudaMalloc,
+        free: CudaFree,
+        memcpy: CudaMemcpy,
+    }
+
+    impl Cudart {
+        fn load() -> Self {
+            unsafe {
+                let lib = libc::dlopen(b"libcudart.so\0".as_ptr() as _, libc::RTLD_NOW);
+                assert!(!lib.is_null(), "failed to load libcudart.so");
+                let sym = |name: &[u8]| {
+                    let sym = libc::dlsym(lib, name.as_ptr() as _);
+                    assert!(!sym.is_null(), "missing cudart symbol");
+                    sym
+                };
+                Cudart {
+                    malloc: std::mem::transmute::<*mut c_void, CudaMalloc>(sym(b"cudaMalloc\0")),
+                    free: std::mem::transmute::<*mut c_void, CudaFree>(sym(b"cudaFree\0")),
+                    memcpy: std::mem::transmute::<*mut c_void, CudaMemcpy>(sym(b"cudaMemcpy\0")),
+                }
+            }
+        }
+
+        fn alloc(&self, len: usize) -> *mut u8 {
+            let mut ptr = null_mut();
+            assert_eq!(unsafe { (self.malloc)(&mut ptr, len) }, 0);
+            ptr as _
+        }
+
+        // copy `dst.len()` bytes of device memory at `src` to `dst`
+        fn copy_to_host(&self, dst: &mut [u8], src: *const u8) {
+            const CUDA_MEMCPY_DEVICE_TO_HOST: i32 = 2;
+            let status = unsafe {
+                (self.memcpy)(
+                    dst.as_mut_ptr() as _,
+                    src as _,
+                    dst.len(),
+                    CUDA_MEMCPY_DEVICE_TO_HOST,
+                )
+            };
+            assert_eq!(status, 0);
+        }
+
+        fn release(&self, ptr: *mut u8) {
+            unsafe { (self.free)(ptr as _) };
+        }
+    }
+
+    #[test_log::test]
+    fn mem_type_host() {
+        spawn_thread!(send_recv(MemType::Host)).join().unwrap();
+    }
+
+    #[test_log::test]
+    #[ignore = "requires a CUDA device"]
+    fn mem_type_cuda() {
+        spawn_thread!(send_recv(MemType::Cuda)).join().unwrap();
+    }
+
+    #[test_log::test]
+    fn detect_host() {
+        let context = Context::new().unwrap();
+        let worker = context.create_worker().unwrap();
+        let buf = vec![0_u8; 4096];
+        let mem_type = unsafe { worker.detect_memory_type(buf.as_ptr(), buf.len()) };
+        assert_eq!(mem_type, Ok(MemType::Host));
+    }
+
+    #[test_log::test]
+    #[ignore = "requires a CUDA device"]
+    fn detect_cuda() {
+        const LEN: usize = 4096;
+        let context = Context::new().unwrap();
+        assert!(context.supports_mem_type(MemType::Cuda).unwrap());
+        let worker = context.create_worker().unwrap();
+        let cuda = Cudart::load();
+        let ptr = cuda.alloc(LEN);
+        let mem_type = unsafe { worker.detect_memory_type(ptr, LEN) };
+        cuda.release(ptr);
+        assert_eq!(mem_type, Ok(MemType::Cuda));
+    }
+
+    #[cfg(feature = "am")]
+    #[test_log::test]
+    #[ignore = "requires a CUDA device"]
+    fn am_recv_cuda() {
+        spawn_thread!(_am_recv_cuda()).join().unwrap();
+    }
+
+    #[cfg(feature = "am")]
+    async fn _am_recv_cuda() {
+        const LEN: usize = 1 << 20;
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let cuda = Cudart::load();
+        let ptr = cuda.alloc(LEN);
+        let mem = unsafe {
+            MemoryHandle::register_mem_type(&worker1.context, ptr as _, LEN, MemType::Cuda)
+        }
+        .unwrap();
+        assert_eq!(mem.mem_type(), Some(MemType::Cuda));
+
+        let stream1 = worker1.am_stream(16).unwrap();
+        let send_buf = vec![7_u8; LEN];
+        let (sent, received) = tokio::join!(
+            endpoint2.am_send_flags(16, &[], &send_buf, AmSendFlags::RNDV),
+            async {
+                let mut msg = stream1.wait_msg().await.expect("no msg");
+                unsafe { msg.recv_data_into_mem(&mem, 0).await }
+            }
+        );
+        assert_eq!(sent, Ok(LEN));
+        assert_eq!(received, Ok(LEN));
+
+        let mut host_buf = vec![0_u8; LEN];
+        drop(mem);
+        cuda.copy_to_host(&mut host_buf, ptr);
+        cuda.release(ptr);
+        assert_eq!(host_buf, send_buf);
+    }
+
+    async fn send_recv(mem_type: MemType) {
+        const LEN: usize = 1 << 20;
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        // receive host memory into memory of `mem_type`
+        let send_buf = vec![7_u8; LEN];
+        let mut host_buf = vec![0_u8; LEN];
+        let cuda = (mem_type != MemType::Host).then(Cudart::load);
+        let recv_buf = match &cuda {
+            None => host_buf.as_mut_ptr(),
+            Some(cuda) => cuda.alloc(LEN),
+        };
+        let (sent, received) = tokio::join!(
+            async {
+                let sent = unsafe {
+                    endpoint2.tag_send_mem_type(1, send_buf.as_ptr(), LEN, MemType::Host)
+                };
+                sent.await.unwrap()
+            },
+            async {
+                let received = unsafe { worker1.tag_recv_mem_type(1, recv_buf, LEN, mem_type) };
+                received.await.unwrap()
+            }
+        );
+        assert_eq!(sent, LEN);
+        assert_eq!(received, LEN);
+
+        if let Some(cuda) = &cuda {
+            cuda.copy_to_host(&mut host_buf, recv_buf);
+            cuda.release(recv_buf);
+        }
+        assert_eq!(host_buf, send_buf);
+    }
+}
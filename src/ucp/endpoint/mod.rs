@@ -1,38 +1,69 @@
 use super::*;
-use std::cell::Cell;
+use derivative::*;
+use futures::channel::oneshot;
+use futures::future::{FutureExt, Shared};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::ffi::CStr;
 use std::future::Future;
 use std::net::SocketAddr;
 use std::pin::Pin;
 use std::rc::Weak;
 use std::sync::atomic::AtomicBool;
-use std::task::Poll;
+use std::task::{Poll, Waker};
 
 #[cfg(feature = "am")]
 mod am;
+mod builder;
+mod datatype;
+#[cfg(feature = "cuda")]
+mod mem_type;
+mod pool;
 mod rma;
 mod stream;
 mod tag;
 
 #[cfg(feature = "am")]
 pub use self::am::*;
+pub use self::builder::*;
+pub use self::datatype::*;
+#[cfg(feature = "cuda")]
+pub use self::mem_type::*;
+pub use self::pool::*;
 pub use self::rma::*;
 pub use self::stream::*;
 pub use self::tag::*;
 
 // State associate with ucp_ep_h
 // todo: Add a `get_user_data` to UCX
-#[derive(Debug)]
+#[derive(Derivative)]
+#[derivative(Debug)]
 struct EndpointInner {
     closed: AtomicBool,
+    closing: Cell<bool>,
     status: Cell<ucs_status_t>,
+    #[derivative(Debug = "ignore")]
+    error_sender: Cell<Option<oneshot::Sender<ucs_status_t>>>,
+    #[derivative(Debug = "ignore")]
+    error: Shared<oneshot::Receiver<ucs_status_t>>,
+    connect: Option<ConnectParams>,
+    send_limit: Option<Rc<SendLimit>>,
+    id: EndpointId,
     worker: Rc<Worker>,
 }
 
 impl EndpointInner {
     fn new(worker: Rc<Worker>) -> Self {
+        let (sender, recver) = oneshot::channel();
         EndpointInner {
+            id: worker.next_endpoint_id(),
             closed: AtomicBool::new(false),
+            closing: Cell::new(false),
             status: Cell::new(ucs_status_t::UCS_OK),
+            error_sender: Cell::new(Some(sender)),
+            error: recver.shared(),
+            connect: None,
+            send_limit: None,
             worker,
         }
     }
@@ -50,6 +81,7 @@ impl EndpointInner {
         {
             // release a weak reference
             let _weak = unsafe { Weak::from_raw(Rc::as_ptr(self)) };
+            self.worker.endpoints.unregister(self);
             self.set_status(ucs_status_t::UCS_ERR_CONNECTION_RESET);
         }
     }
@@ -62,7 +94,11 @@ impl EndpointInner {
     #[inline]
     fn set_status(&self, status: ucs_status_t) {
         if status != ucs_status_t::UCS_OK {
-            self.status.set(status)
+            self.status.set(status);
+            // wake up `wait_error` on the first error
+            if let Some(sender) = self.error_sender.take() {
+                let _ = sender.send(status);
+            }
         }
     }
 
@@ -73,7 +109,170 @@ impl EndpointInner {
     }
 }
 
+// Number of sends left before reaching `EndpointBuilder::max_inflight`
+#[derive(Debug)]
+struct SendLimit {
+    available: Cell<usize>,
+    // sends waiting for a permit, served in order
+    waiters: RefCell<VecDeque<Rc<SendWaiter>>>,
+}
+
+#[derive(Debug, Default)]
+struct SendWaiter {
+    granted: Cell<bool>,
+    waker: RefCell<Option<Waker>>,
+}
+
+impl SendLimit {
+    fn new(max: usize) -> Self {
+        SendLimit {
+            available: Cell::new(max),
+            waiters: RefCell::default(),
+        }
+    }
+
+    fn try_acquire(self: &Rc<Self>) -> Option<SendPermit> {
+        let available = self.available.get();
+        if available == 0 {
+            return None;
+        }
+        self.available.set(available - 1);
+        Some(SendPermit(self.clone()))
+    }
+
+    async fn acquire(self: &Rc<Self>) -> SendPermit {
+        if let Some(permit) = self.try_acquire() {
+            return permit;
+        }
+        let waiter = Rc::new(SendWaiter::default());
+        self.waiters.borrow_mut().push_back(waiter.clone());
+        SendWait {
+            limit: self.clone(),
+            waiter,
+        }
+        .await
+    }
+
+    // hand a returned permit to the first waiter
+    fn release(&self) {
+        match self.waiters.borrow_mut().pop_front() {
+            Some(waiter) => {
+                waiter.granted.set(true);
+                if let Some(waker) = waiter.waker.take() {
+                    waker.wake();
+                }
+            }
+            None => self.available.set(self.available.get() + 1),
+        }
+    }
+}
+
+// A queued `SendLimit::acquire`
+struct SendWait {
+    limit: Rc<SendLimit>,
+    waiter: Rc<SendWaiter>,
+}
+
+impl Future for SendWait {
+    type Output = SendPermit;
+    fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context) -> Poll<Self::Output> {
+        if self.waiter.granted.replace(false) {
+            return Poll::Ready(SendPermit(self.limit.clone()));
+        }
+        *self.waiter.waker.borrow_mut() = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for SendWait {
+    fn drop(&mut self) {
+        if self.waiter.granted.get() {
+            // granted but never taken, pass it on
+            self.limit.release();
+        } else {
+            let waiter = &self.waiter;
+            let mut waiters = self.limit.waiters.borrow_mut();
+            waiters.retain(|w| !Rc::ptr_eq(w, waiter));
+        }
+    }
+}
+
+// A send counted by `SendLimit` until dropped
+struct SendPermit(Rc<SendLimit>);
+
+impl Drop for SendPermit {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+// Endpoints created on a worker which are not closed yet.
+//
+// Entries are removed in `EndpointInner::closed`. They only hold weak
+// references, so `live` skips an `inner` that is already gone.
+#[derive(Default)]
+pub(crate) struct EndpointRegistry {
+    endpoints: RefCell<Vec<(ucp_ep_h, Weak<EndpointInner>)>>,
+}
+
+impl EndpointRegistry {
+    fn register(&self, handle: ucp_ep_h, inner: &Rc<EndpointInner>) {
+        let mut endpoints = self.endpoints.borrow_mut();
+        endpoints.push((handle, Rc::downgrade(inner)));
+    }
+
+    fn unregister(&self, inner: &Rc<EndpointInner>) {
+        let mut endpoints = self.endpoints.borrow_mut();
+        endpoints.retain(|(_, weak)| weak.as_ptr() != Rc::as_ptr(inner));
+    }
+
+    fn live(&self) -> Vec<Endpoint> {
+        let endpoints = self.endpoints.borrow();
+        endpoints
+            .iter()
+            .filter_map(|(handle, weak)| {
+                let inner = weak.upgrade()?;
+                Some(Endpoint {
+                    handle: *handle,
+                    inner,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Worker {
+    /// Flush all endpoints of this worker which are neither dropped nor closed,
+    /// e.g. before shutting down.
+    ///
+    /// Returns the first error after all flushes complete.
+    pub async fn flush_all(&self) -> Result<(), Error> {
+        let endpoints = self.endpoints.live();
+        trace!(
+            "flush_all: worker={:?}, endpoints={}",
+            self.handle,
+            endpoints.len()
+        );
+        let flushes = endpoints.iter().map(|endpoint| endpoint.flush());
+        futures::future::join_all(flushes)
+            .await
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Identifier of an [`Endpoint`], unique among the endpoints of its worker.
+///
+/// It is assigned on connect or accept and shared by all clones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EndpointId(pub(super) u64);
+
 /// Communication endpoint.
+///
+/// Cloning an `Endpoint` is cheap, all clones share the same UCX endpoint.
+/// The endpoint is force closed when the last clone is dropped without calling
+/// [`Endpoint::close`]. Once it is closed through any clone, operations on the
+/// other clones fail with [`Error::ConnectionReset`].
 #[derive(Debug, Clone)]
 pub struct Endpoint {
     handle: ucp_ep_h,
@@ -81,8 +280,18 @@ pub struct Endpoint {
 }
 
 impl Endpoint {
-    fn create(worker: &Rc<Worker>, mut params: ucp_ep_params) -> Result<Self, Error> {
-        let inner = Rc::new(EndpointInner::new(worker.clone()));
+    fn create(
+        worker: &Rc<Worker>,
+        mut params: ucp_ep_params,
+        connect: Option<ConnectParams>,
+    ) -> Result<Self, Error> {
+        let mut inner = EndpointInner::new(worker.clone());
+        inner.send_limit = connect
+            .as_ref()
+            .and_then(|c| c.max_inflight)
+            .map(|max| Rc::new(SendLimit::new(max)));
+        inner.connect = connect;
+        let inner = Rc::new(inner);
         let weak = Rc::downgrade(&inner);
 
         // ucp endpoint keep a weak reference to inner
@@ -92,13 +301,17 @@ impl Endpoint {
             let weak: Weak<EndpointInner> = Weak::from_raw(arg as _);
             if let Some(inner) = weak.upgrade() {
                 inner.set_status(status);
+                let connect = inner.connect.as_ref();
+                if let Some(handler) = connect.and_then(|c| c.error_handler.as_ref()) {
+                    handler(Error::from_error(status));
+                }
+                inner.worker.report_error(inner.id, status);
                 // don't drop weak reference
                 std::mem::forget(weak);
             } else {
                 // no strong rc, force close endpoint here
                 let status = ucp_ep_close_nb(ep, ucp_ep_close_mode::UCP_EP_CLOSE_MODE_FORCE as _);
-                let _ = Error::from_ptr(status)
-                    .map_err(|err| error!("Force close endpoint failed, {}", err));
+                free_close_request(status);
             }
         }
 
@@ -121,38 +334,10 @@ impl Endpoint {
 
         let handle = unsafe { handle.assume_init() };
         trace!("create endpoint={:?}", handle);
+        worker.endpoints.register(handle, &inner);
         Ok(Self { handle, inner })
     }
 
-    pub(super) async fn connect_socket(
-        worker: &Rc<Worker>,
-        addr: SocketAddr,
-    ) -> Result<Self, Error> {
-        let sockaddr = socket2::SockAddr::from(addr);
-        #[allow(invalid_value)]
-        #[allow(clippy::uninit_assumed_init)]
-        let params = ucp_ep_params {
-            field_mask: (ucp_ep_params_field::UCP_EP_PARAM_FIELD_FLAGS
-                | ucp_ep_params_field::UCP_EP_PARAM_FIELD_SOCK_ADDR
-                | ucp_ep_params_field::UCP_EP_PARAM_FIELD_ERR_HANDLING_MODE)
-                .0 as u64,
-            flags: ucp_ep_params_flags_field::UCP_EP_PARAMS_FLAGS_CLIENT_SERVER.0,
-            sockaddr: ucs_sock_addr {
-                addr: sockaddr.as_ptr() as _,
-                addrlen: sockaddr.len(),
-            },
-            err_mode: ucp_err_handling_mode_t::UCP_ERR_HANDLING_MODE_PEER,
-            ..unsafe { MaybeUninit::uninit().assume_init() }
-        };
-        let endpoint = Endpoint::create(worker, params)?;
-
-        // Workaround for UCX bug: https://github.com/openucx/ucx/issues/6872
-        let buf = [0, 1, 2, 3];
-        endpoint.stream_send(&buf).await?;
-
-        Ok(endpoint)
-    }
-
     pub(super) fn connect_addr(
         worker: &Rc<Worker>,
         addr: *const ucp_address_t,
@@ -167,7 +352,7 @@ impl Endpoint {
             err_mode: ucp_err_handling_mode_t::UCP_ERR_HANDLING_MODE_PEER,
             ..unsafe { MaybeUninit::uninit().assume_init() }
         };
-        Endpoint::create(worker, params)
+        Endpoint::create(worker, params, None)
     }
 
     pub(super) async fn accept(
@@ -177,11 +362,25 @@ impl Endpoint {
         #[allow(invalid_value)]
         #[allow(clippy::uninit_assumed_init)]
         let params = ucp_ep_params {
-            field_mask: ucp_ep_params_field::UCP_EP_PARAM_FIELD_CONN_REQUEST.0 as u64,
+            field_mask: (ucp_ep_params_field::UCP_EP_PARAM_FIELD_CONN_REQUEST
+                | ucp_ep_params_field::UCP_EP_PARAM_FIELD_ERR_HANDLING_MODE)
+                .0 as u64,
             conn_request: connection.handle,
+            err_mode: ucp_err_handling_mode_t::UCP_ERR_HANDLING_MODE_PEER,
             ..unsafe { MaybeUninit::uninit().assume_init() }
         };
-        let endpoint = Endpoint::create(worker, params)?;
+        #[cfg(feature = "test-util")]
+        if crate::test_util::take_accept_failure() {
+            trace!(
+                "accept: failure injected, reject request={:?}",
+                connection.handle
+            );
+            // SAFETY: `fail_next_accepts` requires the listener to outlive the accept
+            unsafe { connection.reject()? };
+            return Err(Error::NoReource);
+        }
+        // UCX rejects the request if this fails, so the client doesn't hang
+        let endpoint = Endpoint::create(worker, params, None)?;
 
         // Workaround for UCX bug: https://github.com/openucx/ucx/issues/6872
         let mut buf = [MaybeUninit::<u8>::uninit(); 4];
@@ -190,16 +389,150 @@ impl Endpoint {
         Ok(endpoint)
     }
 
+    /// Create a new endpoint with the parameters this one was built with,
+    /// e.g. after the peer failed and restarted.
+    ///
+    /// The error handler is shared with the new endpoint. This endpoint is left
+    /// as is, drop or close it.
+    /// Fails with [`Error::InvalidParam`] for endpoints not created by
+    /// [`Worker::connect_socket`] or an [`EndpointBuilder`].
+    pub async fn reconnect(&self) -> Result<Endpoint, Error> {
+        let connect = self.inner.connect.clone().ok_or(Error::InvalidParam)?;
+        trace!("reconnect: endpoint={:?}", self.handle);
+        builder::connect(self.worker(), connect).await
+    }
+
+    /// The id of the endpoint, as reported by [`Worker::error_events`].
+    pub fn id(&self) -> EndpointId {
+        self.inner.id
+    }
+
     /// Whether the endpoint is closed.
     pub fn is_closed(&self) -> bool {
         self.inner.is_closed()
     }
 
+    /// Whether the endpoint is neither closed nor failed.
+    ///
+    /// Peer failures are only noticed on traffic or keepalive probes,
+    /// see [`Config::set_keepalive_interval`].
+    pub fn is_alive(&self) -> bool {
+        !self.inner.is_closed() && self.inner.check().is_ok()
+    }
+
     /// Get the endpoint status.
     pub fn get_status(&self) -> Result<(), Error> {
         self.inner.check()
     }
 
+    /// Wait until an error happens on the endpoint.
+    ///
+    /// This resolves when the peer fails or the endpoint is closed,
+    /// so per-connection state can be cleaned up.
+    pub async fn wait_error(&self) -> Error {
+        let status = self.inner.error.clone().await;
+        Error::from_error(status.unwrap_or(ucs_status_t::UCS_ERR_CONNECTION_RESET))
+    }
+
+    /// Fetches information about the endpoint.
+    ///
+    /// Socket addresses are only available for endpoints created from
+    /// a socket address or a connection request.
+    pub fn query(&self) -> Result<EndpointAttr, Error> {
+        let handle = self.get_handle()?;
+        const MAX_TRANSPORTS: usize = 16;
+        let mut entries = [ucp_transport_entry_t {
+            transport_name: std::ptr::null(),
+            device_name: std::ptr::null(),
+        }; MAX_TRANSPORTS];
+        let mut attr = MaybeUninit::<ucp_ep_attr_t>::uninit();
+        unsafe {
+            let attr = &mut *attr.as_mut_ptr();
+            attr.field_mask = (ucp_ep_attr_field::UCP_EP_ATTR_FIELD_NAME
+                | ucp_ep_attr_field::UCP_EP_ATTR_FIELD_TRANSPORTS)
+                .0 as u64;
+            attr.transports = ucp_transports_t {
+                entries: entries.as_mut_ptr(),
+                num_entries: MAX_TRANSPORTS as _,
+                entry_size: std::mem::size_of::<ucp_transport_entry_t>() as _,
+            };
+        }
+        let status = unsafe { ucp_ep_query(handle, attr.as_mut_ptr()) };
+        Error::from_status(status)?;
+        let attr = unsafe { attr.assume_init() };
+
+        let to_string = |ptr: *const c_char| {
+            if ptr.is_null() {
+                String::new()
+            } else {
+                unsafe { CStr::from_ptr(ptr) }
+                    .to_string_lossy()
+                    .into_owned()
+            }
+        };
+        let transports = entries[..attr.transports.num_entries as usize]
+            .iter()
+            .map(|entry| {
+                (
+                    to_string(entry.transport_name),
+                    to_string(entry.device_name),
+                )
+            })
+            .collect();
+
+        Ok(EndpointAttr {
+            name: to_string(attr.name.as_ptr()),
+            local_addr: self.query_sockaddr(ucp_ep_attr_field::UCP_EP_ATTR_FIELD_LOCAL_SOCKADDR),
+            remote_addr: self.query_sockaddr(ucp_ep_attr_field::UCP_EP_ATTR_FIELD_REMOTE_SOCKADDR),
+            transports,
+        })
+    }
+
+    // queried apart from other fields, which fails if the endpoint has no socket address
+    fn query_sockaddr(&self, field: ucp_ep_attr_field) -> Option<SocketAddr> {
+        let mut attr = MaybeUninit::<ucp_ep_attr_t>::uninit();
+        unsafe { &mut *attr.as_mut_ptr() }.field_mask = field.0 as u64;
+        let status = unsafe { ucp_ep_query(self.handle, attr.as_mut_ptr()) };
+        Error::from_status(status).ok()?;
+        let attr = unsafe { attr.assume_init() };
+        let sockaddr = if field == ucp_ep_attr_field::UCP_EP_ATTR_FIELD_LOCAL_SOCKADDR {
+            attr.local_sockaddr
+        } else {
+            attr.remote_sockaddr
+        };
+        let len = std::mem::size_of_val(&sockaddr) as _;
+        let sockaddr = unsafe { socket2::SockAddr::new(std::mem::transmute(sockaddr), len) };
+        sockaddr.as_socket()
+    }
+
+    /// Returns the underlying UCX endpoint handle, for calling `ucx1-sys`
+    /// functions this crate doesn't wrap.
+    ///
+    /// The handle is valid only while this endpoint, or a clone of it, is
+    /// alive and not closed; it is returned even after closing, when it must
+    /// no longer be used. Keeping raw calls consistent with the crate's own,
+    /// e.g. not closing the handle behind its back, is up to the caller.
+    pub fn as_raw(&self) -> ucp_ep_h {
+        self.handle
+    }
+
+    // held while a send is in flight, `None` without `EndpointBuilder::max_inflight`
+    async fn send_permit(&self) -> Option<SendPermit> {
+        match &self.inner.send_limit {
+            Some(limit) => Some(limit.acquire().await),
+            None => None,
+        }
+    }
+
+    // like `send_permit` for sends submitted without awaiting,
+    // fails with `NoReource` instead of waiting
+    fn try_send_permit(&self) -> Result<Option<SendPermit>, Error> {
+        match &self.inner.send_limit {
+            Some(limit) => limit.try_acquire().map(Some).ok_or(Error::NoReource),
+            None => Ok(None),
+        }
+    }
+
     #[inline]
     fn get_handle(&self) -> Result<ucp_ep_h, Error> {
         self.inner.check()?;
@@ -213,37 +546,44 @@ impl Endpoint {
         }
     }
 
+    /// Wait until the connection is established.
+    ///
+    /// Endpoints created from a worker address are wired up lazily, so this
+    /// tells a reachable peer from an unreachable one before the first send.
+    /// [`Worker::connect_socket`] already waits for it.
+    pub async fn connected(&self) -> Result<(), Error> {
+        let handle = self.get_handle()?;
+        // a flush completes once the pending wireup did
+        flush_ep(self.worker(), handle).await?;
+        self.inner.check()
+    }
+
     /// This routine flushes all outstanding AMO and RMA communications on the endpoint.
     pub async fn flush(&self) -> Result<(), Error> {
         let handle = self.get_handle()?;
-        trace!("flush: endpoint={:?}", handle);
-        unsafe extern "C" fn callback(request: *mut c_void, _status: ucs_status_t) {
-            trace!("flush: complete");
-            let request = &mut *(request as *mut Request);
-            request.waker.wake();
-        }
-        let status = unsafe { ucp_ep_flush_nb(handle, 0, Some(callback)) };
-        if status.is_null() {
-            trace!("flush: complete");
-            Ok(())
-        } else if UCS_PTR_IS_PTR(status) {
-            RequestHandle {
-                ptr: status,
-                poll_fn: poll_normal,
-            }
-            .await
-        } else {
-            Error::from_ptr(status)
-        }
+        flush_ep(self.worker(), handle).await
     }
 
     /// This routine close connection.
+    ///
+    /// Closing an endpoint that is already closed, or being closed through
+    /// another clone, is a no-op returning `Ok`.
+    /// The endpoint counts as closed once the close is submitted, even if it
+    /// fails or this future is dropped, since UCX invalidates the handle.
     pub async fn close(&self, force: bool) -> Result<(), Error> {
-        if force && self.is_closed() {
+        if self.is_closed() {
             return Ok(());
         } else if !force {
             self.get_status()?;
         }
+        if self.inner.closing.replace(true) {
+            // another clone is closing the endpoint
+            while !self.is_closed() {
+                futures_lite::future::yield_now().await;
+            }
+            return Ok(());
+        }
+        let _closed = CloseGuard(&self.inner);
 
         trace!("close: endpoint={:?}", self.handle);
         let mode = if force {
@@ -254,22 +594,18 @@ impl Endpoint {
         let status = unsafe { ucp_ep_close_nb(self.handle, mode) };
         if status.is_null() {
             trace!("close: complete");
-            self.inner.closed();
             Ok(())
         } else if UCS_PTR_IS_PTR(status) {
-            let result = loop {
-                if let Poll::Ready(result) = unsafe { poll_normal(status) } {
-                    unsafe { ucp_request_free(status as _) };
+            // no completion callback to wake us, so poll it.
+            // the request is freed on drop, also if this future is dropped
+            let request = RequestHandle::new(self.worker(), status, poll_normal);
+            loop {
+                if let Poll::Ready(result) = unsafe { poll_normal(request.ptr) } {
                     break result;
                 } else {
                     futures_lite::future::yield_now().await;
                 }
-            };
-            if result.is_ok() {
-                self.inner.closed();
             }
-
-            result
         } else {
             // todo: maybe this shouldn't treat as error ...
             let status = UCS_PTR_RAW_STATUS(status);
@@ -292,24 +628,68 @@ impl Endpoint {
 
 impl Drop for Endpoint {
     fn drop(&mut self) {
-        if !self.inner.is_closed() {
+        // other clones are still using the endpoint
+        if Rc::strong_count(&self.inner) > 1 {
+            return;
+        }
+        if !self.inner.is_closed() && !self.inner.closing.get() {
             trace!("destroy endpoint={:?}", self.handle);
-            let status = unsafe {
-                ucp_ep_close_nb(
+            unsafe {
+                let status = ucp_ep_close_nb(
                     self.handle,
                     ucp_ep_close_mode::UCP_EP_CLOSE_MODE_FORCE as u32,
-                )
-            };
-            let _ = Error::from_ptr(status).map_err(|err| error!("Failed to force close, {}", err));
+                );
+                free_close_request(status);
+            }
             self.inner.closed();
         }
     }
 }
 
+// Marks the endpoint closed when `close` finishes, however it finishes.
+struct CloseGuard<'a>(&'a Rc<EndpointInner>);
+
+impl Drop for CloseGuard<'_> {
+    fn drop(&mut self) {
+        self.0.closed();
+    }
+}
+
+/// Attributes of an [`Endpoint`].
+#[derive(Debug, Clone)]
+pub struct EndpointAttr {
+    /// Name of the endpoint.
+    pub name: String,
+    /// Local socket address of the connection.
+    pub local_addr: Option<SocketAddr>,
+    /// Socket address of the peer.
+    pub remote_addr: Option<SocketAddr>,
+    /// Names of the transports and devices in use, as `(transport, device)`.
+    pub transports: Vec<(String, String)>,
+}
+
 /// A handle to the request returned from async IO functions.
+///
+/// The request is freed exactly once on drop, whether it completed or not.
+/// UCX keeps an uncompleted request alive until it completes.
 struct RequestHandle<T> {
     ptr: ucs_status_ptr_t,
     poll_fn: unsafe fn(ucs_status_ptr_t) -> Poll<T>,
+    _inflight: InflightGuard,
+}
+
+impl<T> RequestHandle<T> {
+    fn new(
+        worker: &Worker,
+        ptr: ucs_status_ptr_t,
+        poll_fn: unsafe fn(ucs_status_ptr_t) -> Poll<T>,
+    ) -> Self {
+        RequestHandle {
+            ptr,
+            poll_fn,
+            _inflight: worker.inflight.track(),
+        }
+    }
 }
 
 impl<T> Future for RequestHandle<T> {
@@ -331,6 +711,106 @@ impl<T> Drop for RequestHandle<T> {
     }
 }
 
+/// Many requests completed through a single future.
+///
+/// Each poll checks the pending requests and frees the completed ones.
+/// Resolves to the results in the order the requests were pushed.
+#[cfg_attr(not(feature = "am"), allow(dead_code))]
+struct MultiRequest {
+    // with the send permit released once the request completes
+    requests: Vec<(usize, RequestHandle<Result<(), Error>>, Option<SendPermit>)>,
+    results: Vec<Result<(), Error>>,
+}
+
+#[cfg_attr(not(feature = "am"), allow(dead_code))]
+impl MultiRequest {
+    fn new() -> Self {
+        MultiRequest {
+            requests: Vec::new(),
+            results: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, request: RequestHandle<Result<(), Error>>, permit: Option<SendPermit>) {
+        self.requests.push((self.results.len(), request, permit));
+        self.results.push(Ok(()));
+    }
+
+    fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    // Wait for a send permit of `endpoint`, completing the pushed requests
+    // meanwhile, since they may hold the permits
+    async fn send_permit(&mut self, endpoint: &Endpoint) -> Option<SendPermit> {
+        let permit = endpoint.send_permit();
+        futures::pin_mut!(permit);
+        futures::future::poll_fn(|cx| {
+            if let Poll::Ready(permit) = permit.as_mut().poll(cx) {
+                return Poll::Ready(permit);
+            }
+            self.poll_requests(cx);
+            permit.as_mut().poll(cx)
+        })
+        .await
+    }
+
+    // Drop the completed requests, returns whether all are completed.
+    fn poll_requests(&mut self, cx: &mut std::task::Context) -> bool {
+        let results = &mut self.results;
+        self.requests.retain(|(index, request, _)| {
+            let req = unsafe { &mut *(request.ptr as *mut Request) };
+            req.waker.register(cx.waker());
+            match unsafe { (request.poll_fn)(request.ptr) } {
+                Poll::Ready(result) => {
+                    results[*index] = result;
+                    false
+                }
+                Poll::Pending => true,
+            }
+        });
+        self.requests.is_empty()
+    }
+}
+
+impl Future for MultiRequest {
+    type Output = Vec<Result<(), Error>>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context) -> Poll<Self::Output> {
+        if self.poll_requests(cx) {
+            Poll::Ready(std::mem::take(&mut self.results))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+async fn flush_ep(worker: &Worker, handle: ucp_ep_h) -> Result<(), Error> {
+    trace!("flush: endpoint={:?}", handle);
+    unsafe extern "C" fn callback(request: *mut c_void, _status: ucs_status_t) {
+        trace!("flush: complete");
+        let request = &mut *(request as *mut Request);
+        request.waker.wake();
+    }
+    let status = unsafe { ucp_ep_flush_nb(handle, 0, Some(callback)) };
+    if status.is_null() {
+        trace!("flush: complete");
+        Ok(())
+    } else if UCS_PTR_IS_PTR(status) {
+        RequestHandle::new(worker, status, poll_normal).await
+    } else {
+        Error::from_ptr(status)
+    }
+}
+
+// Nobody waits for a force close, let UCX release the request on completion.
+unsafe fn free_close_request(status: ucs_status_ptr_t) {
+    if UCS_PTR_IS_PTR(status) {
+        ucp_request_free(status as _);
+    } else {
+        let _ = Error::from_ptr(status).map_err(|err| error!("Failed to force close, {}", err));
+    }
+}
+
 unsafe fn poll_normal(ptr: ucs_status_ptr_t) -> Poll<Result<(), Error>> {
     let status = ucp_request_check_status(ptr as _);
     if status == ucs_status_t::UCS_INPROGRESS {
@@ -339,3 +819,532 @@ unsafe fn poll_normal(ptr: ucs_status_ptr_t) -> Poll<Result<(), Error>> {
         Poll::Ready(Error::from_status(status))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn clone() {
+        spawn_thread!(_clone()).join().unwrap();
+    }
+
+    async fn _clone() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        // send from both clones
+        let endpoint3 = endpoint2.clone();
+        assert_eq!(endpoint2.get_rc(), (2, 2));
+        tokio::join!(
+            async {
+                endpoint2.tag_send(1, &[1]).await.unwrap();
+                endpoint3.tag_send(2, &[2]).await.unwrap();
+            },
+            async {
+                let mut buf = [MaybeUninit::uninit(); 1];
+                worker1.tag_recv(1, &mut buf).await.unwrap();
+                worker1.tag_recv(2, &mut buf).await.unwrap();
+            }
+        );
+
+        // dropping a clone doesn't close the endpoint
+        drop(endpoint3);
+        assert_eq!(endpoint2.get_rc(), (1, 2));
+        assert!(!endpoint2.is_closed());
+
+        // closing one clone closes all of them
+        let endpoint3 = endpoint2.clone();
+        assert_eq!(endpoint2.close(false).await, Ok(()));
+        assert!(endpoint3.is_closed());
+        assert_eq!(
+            endpoint3.tag_send(1, &[1]).await,
+            Err(Error::ConnectionReset)
+        );
+        assert_eq!(endpoint3.close(true).await, Ok(()));
+        assert_eq!(endpoint2.get_rc(), (2, 0));
+    }
+
+    #[test_log::test]
+    fn drop_without_close() {
+        spawn_thread!(_drop_without_close()).join().unwrap();
+    }
+
+    async fn _drop_without_close() {
+        let (endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        // dropping without closing force closes the endpoint
+        drop(endpoint2);
+        assert_eq!(endpoint1.wait_error().await, Error::ConnectionReset);
+
+        // repeated closes are no-ops
+        assert_eq!(endpoint1.close(true).await, Ok(()));
+        assert_eq!(endpoint1.close(true).await, Ok(()));
+        assert_eq!(endpoint1.close(false).await, Ok(()));
+        drop(endpoint1);
+        assert_eq!(Rc::strong_count(&worker2), 1);
+        drop(worker2);
+        drop(worker1);
+    }
+
+    #[test_log::test]
+    fn multi_request() {
+        spawn_thread!(_multi_request()).join().unwrap();
+    }
+
+    async fn _multi_request() {
+        const N: u64 = 50;
+        let (_endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        unsafe extern "C" fn callback(request: *mut c_void, _status: ucs_status_t) {
+            let request = &mut *(request as *mut Request);
+            request.waker.wake();
+        }
+        // large enough to stay pending until the receiver posts a buffer
+        let buf = vec![7_u8; 1 << 20];
+        let mut requests = MultiRequest::new();
+        for tag in 0..N {
+            let status = unsafe {
+                ucp_tag_send_nb(
+                    endpoint2.get_handle().unwrap(),
+                    buf.as_ptr() as _,
+                    buf.len() as _,
+                    ucp_dt_make_contig(1),
+                    tag,
+                    Some(callback),
+                )
+            };
+            assert!(UCS_PTR_IS_PTR(status));
+            requests.push(RequestHandle::new(&worker2, status, poll_normal), None);
+        }
+        assert_eq!(requests.len(), N as usize);
+        assert_eq!(worker2.inflight_requests(), N as usize);
+
+        let (results, _) = tokio::join!(requests, async {
+            let mut recv = vec![MaybeUninit::uninit(); 1 << 20];
+            for tag in 0..N {
+                assert_eq!(worker1.tag_recv(tag, &mut recv).await, Ok(1 << 20));
+            }
+        });
+        assert_eq!(results.len(), N as usize);
+        assert!(results.iter().all(|result| result.is_ok()));
+        assert_eq!(worker2.inflight_requests(), 0);
+    }
+
+    #[test_log::test]
+    fn wait_error() {
+        spawn_thread!(_wait_error()).join().unwrap();
+    }
+
+    async fn _wait_error() {
+        let (endpoint1, _, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        // the peer going away is observed by the client
+        let (err, _) = tokio::join!(endpoint2.wait_error(), async {
+            endpoint1.close(false).await.unwrap()
+        });
+        assert_eq!(err, Error::ConnectionReset);
+        assert_eq!(endpoint2.get_status(), Err(Error::ConnectionReset));
+        assert_eq!(endpoint2.wait_error().await, Error::ConnectionReset);
+    }
+
+    #[test_log::test]
+    fn keepalive() {
+        spawn_thread!(_keepalive()).join().unwrap();
+    }
+
+    async fn _keepalive() {
+        let interval = Duration::from_millis(100);
+        let mut config = Config::new().unwrap();
+        config.set_keepalive_interval(interval).unwrap();
+        // the workers are polled in a set of their own, so it can be dropped
+        let polling = tokio::task::LocalSet::new();
+        let (endpoint1, _worker1, endpoint2, worker2, _) = polling
+            .run_until(crate::test_util::loopback_with_config(&config))
+            .await
+            .unwrap();
+        assert!(endpoint1.is_alive());
+        assert!(endpoint2.is_alive());
+
+        // the peer goes silent without closing anything
+        drop(polling);
+        tokio::task::spawn_local(worker2.clone().polling());
+        assert!(endpoint1.is_alive());
+        // a few rounds, as UCX checks only some endpoints per interval
+        tokio::time::timeout(interval * 10, endpoint2.wait_error())
+            .await
+            .expect("peer failure not detected");
+        assert!(!endpoint2.is_alive());
+    }
+
+    #[test_log::test]
+    fn inflight_requests() {
+        spawn_thread!(_inflight_requests()).join().unwrap();
+    }
+
+    async fn _inflight_requests() {
+        const N: u64 = 4;
+        const LEN: usize = 1 << 20;
+        let (_endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+        assert_eq!(worker2.inflight_requests(), 0);
+
+        // rendezvous sends stay in flight until the receives are posted
+        tokio::task::spawn_local(async move {
+            let buf = vec![1_u8; LEN];
+            let sends = (0..N).map(|tag| endpoint2.tag_send(tag, &buf));
+            for sent in futures::future::join_all(sends).await {
+                assert_eq!(sent, Ok(LEN));
+            }
+        });
+        while worker2.inflight_requests() < N as usize {
+            tokio::task::yield_now().await;
+        }
+
+        for tag in 0..N {
+            let mut buf = vec![MaybeUninit::<u8>::uninit(); LEN];
+            worker1.tag_recv(tag, &mut buf).await.unwrap();
+        }
+        worker2.wait_idle().await;
+        assert_eq!(worker2.inflight_requests(), 0);
+    }
+
+    // resident set size in bytes
+    fn rss() -> usize {
+        let statm = std::fs::read_to_string("/proc/self/statm").unwrap();
+        let pages: usize = statm.split_whitespace().nth(1).unwrap().parse().unwrap();
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        pages * page_size as usize
+    }
+
+    #[test_log::test]
+    #[ignore = "stress test"]
+    fn request_leak() {
+        spawn_thread!(_request_leak()).join().unwrap();
+    }
+
+    async fn _request_leak() {
+        const N: u64 = 1_000_000;
+        let (_endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        // receives are posted first, so each of them is a request
+        let mut baseline = 0;
+        for i in 0..N {
+            let mut buf = [MaybeUninit::<u8>::uninit(); 8];
+            let (received, sent) = tokio::join!(
+                worker1.tag_recv(i, &mut buf),
+                endpoint2.tag_send(i, &i.to_ne_bytes())
+            );
+            assert_eq!(received, Ok(8));
+            assert_eq!(sent, Ok(8));
+            // UCX grows its request pool during warm up
+            if i == N / 10 {
+                baseline = rss();
+            }
+        }
+        assert_eq!(worker1.inflight_requests(), 0);
+        assert_eq!(worker2.inflight_requests(), 0);
+        let growth = rss().saturating_sub(baseline);
+        assert!(growth < 16 << 20, "memory grew by {} bytes", growth);
+    }
+
+    #[cfg(feature = "time")]
+    #[test_log::test]
+    fn connect_timeout() {
+        spawn_thread!(_connect_timeout()).join().unwrap();
+    }
+
+    #[cfg(feature = "time")]
+    async fn _connect_timeout() {
+        let context = Context::new().unwrap();
+        let worker = context.create_worker().unwrap();
+        tokio::task::spawn_local(worker.clone().polling());
+
+        // TEST-NET-1, packets are dropped
+        let addr: SocketAddr = "192.0.2.1:10000".parse().unwrap();
+        let timeout = std::time::Duration::from_millis(100);
+        let start = std::time::Instant::now();
+        let result = worker.connect_timeout(addr, timeout).await;
+        assert!(result.is_err());
+        assert!(start.elapsed() < timeout * 10);
+    }
+
+    #[cfg(feature = "time")]
+    #[test_log::test]
+    fn connect_retry() {
+        spawn_thread!(_connect_retry()).join().unwrap();
+    }
+
+    #[cfg(feature = "time")]
+    async fn _connect_retry() {
+        let context1 = Context::new().unwrap();
+        let worker1 = context1.create_worker().unwrap();
+        let context2 = Context::new().unwrap();
+        let worker2 = context2.create_worker().unwrap();
+        tokio::task::spawn_local(worker1.clone().polling());
+        tokio::task::spawn_local(worker2.clone().polling());
+
+        // a port nobody listens on yet
+        let port = std::net::TcpListener::bind("0.0.0.0:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        addr.set_port(port);
+        let backoff = std::time::Duration::from_millis(50);
+
+        assert!(worker2.connect_retry(addr, 2, backoff).await.is_err());
+        assert_eq!(
+            worker2.connect_retry(addr, 0, backoff).await.err(),
+            Some(Error::InvalidParam)
+        );
+
+        // the listener comes up after the first attempt failed
+        let (_endpoint1, endpoint2) = tokio::join!(
+            async {
+                tokio::time::sleep(backoff * 4).await;
+                let mut listen_addr: SocketAddr = "0.0.0.0:0".parse().unwrap();
+                listen_addr.set_port(port);
+                let mut listener = worker1.create_listener(listen_addr).unwrap();
+                let conn1 = listener.next().await;
+                worker1.accept(conn1).await.unwrap()
+            },
+            async { worker2.connect_retry(addr, 8, backoff).await.unwrap() },
+        );
+        let (sent, received) = tokio::join!(endpoint2.tag_send(1, &[1, 2, 3]), async {
+            let mut buf = [MaybeUninit::uninit(); 3];
+            worker1.tag_recv(1, &mut buf).await
+        });
+        assert_eq!(sent, Ok(3));
+        assert_eq!(received, Ok(3));
+    }
+
+    #[test_log::test]
+    fn query() {
+        spawn_thread!(_query()).join().unwrap();
+    }
+
+    async fn _query() {
+        let (endpoint1, _, endpoint2, _, addr) =
+            crate::test_util::loopback_with_config(&Config::new().unwrap())
+                .await
+                .unwrap();
+
+        let attr2 = endpoint2.query().unwrap();
+        assert!(!attr2.name.is_empty());
+        assert!(!attr2.transports.is_empty());
+        assert_eq!(attr2.remote_addr, Some(addr));
+        let attr1 = endpoint1.query().unwrap();
+        assert_eq!(attr1.local_addr.map(|addr| addr.port()), Some(addr.port()));
+        assert_eq!(attr1.remote_addr, attr2.local_addr);
+    }
+
+    #[test_log::test]
+    #[ignore = "needs two network devices in UCX_TEST_NET_DEVICES, e.g. mlx5_0:1,mlx5_1:1"]
+    fn net_devices() {
+        spawn_thread!(_net_devices()).join().unwrap();
+    }
+
+    async fn _net_devices() {
+        let devices = std::env::var("UCX_TEST_NET_DEVICES").unwrap();
+        let devices: Vec<&str> = devices.split(',').collect();
+        assert_eq!(devices.len(), 2);
+        let server_context = Context::new().unwrap();
+        let server = server_context.create_worker().unwrap();
+        tokio::task::spawn_local(server.clone().polling());
+        let mut listener = server
+            .create_listener("0.0.0.0:0".parse().unwrap())
+            .unwrap();
+        let listen_port = listener.socket_addr().unwrap().port();
+        let mut addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        addr.set_port(listen_port);
+
+        // one client worker per device
+        for device in devices {
+            let mut config = Config::new().unwrap();
+            config.set_net_devices(&[device]).unwrap();
+            let context = Context::new_with_config(&config).unwrap();
+            let worker = context.create_worker().unwrap();
+            tokio::task::spawn_local(worker.clone().polling());
+            let (_server_endpoint, endpoint) = tokio::join!(
+                async {
+                    let conn = listener.next().await;
+                    server.accept(conn).await.unwrap()
+                },
+                async { worker.connect_socket(addr).await.unwrap() },
+            );
+            let transports = endpoint.query().unwrap().transports;
+            assert!(
+                transports.iter().any(|(_, dev)| dev == device),
+                "{:?} doesn't use {}",
+                transports,
+                device
+            );
+        }
+    }
+
+    #[test_log::test]
+    fn shared_memory_only() {
+        spawn_thread!(_shared_memory_only()).join().unwrap();
+    }
+
+    async fn _shared_memory_only() {
+        let mut config = Config::new().unwrap();
+        config.set_shared_memory_only().unwrap();
+        // socket addresses need a network transport, connect by worker address
+        let worker1 = crate::test_util::spawn_worker(&config).unwrap();
+        let worker2 = crate::test_util::spawn_worker(&config).unwrap();
+
+        let endpoint2 = worker2.connect_addr(&worker1.address().unwrap()).unwrap();
+        tokio::join!(
+            async { endpoint2.tag_send(1, &[1; 64]).await.unwrap() },
+            async {
+                let mut buf = [MaybeUninit::uninit(); 64];
+                worker1.tag_recv(1, &mut buf).await.unwrap()
+            }
+        );
+
+        const SHM: [&str; 6] = ["posix", "sysv", "cma", "knem", "xpmem", "self"];
+        let transports = endpoint2.query().unwrap().transports;
+        assert!(!transports.is_empty());
+        assert!(
+            transports.iter().all(|(tl, _)| SHM.contains(&tl.as_str())),
+            "{:?} is not shared memory",
+            transports
+        );
+    }
+
+    #[test_log::test]
+    fn connected() {
+        spawn_thread!(_connected()).join().unwrap();
+    }
+
+    async fn _connected() {
+        let context1 = Context::new().unwrap();
+        let worker1 = context1.create_worker().unwrap();
+        let context2 = Context::new().unwrap();
+        let worker2 = context2.create_worker().unwrap();
+        tokio::task::spawn_local(worker1.clone().polling());
+        tokio::task::spawn_local(worker2.clone().polling());
+
+        let endpoint = worker2.connect_addr(&worker1.address().unwrap()).unwrap();
+        assert_eq!(endpoint.connected().await, Ok(()));
+
+        // the address of a worker which is gone
+        let context3 = Context::new().unwrap();
+        let worker3 = context3.create_worker().unwrap();
+        let addr = worker3.address().unwrap().as_ref().to_vec();
+        drop(worker3);
+        drop(context3);
+        let addr = unsafe { ExternalWorkerAddress::new(addr) };
+        let result = match worker2.connect_addr(&addr) {
+            Ok(endpoint) => tokio::time::timeout(Duration::from_secs(30), endpoint.connected())
+                .await
+                .expect("not failed in time"),
+            Err(err) => Err(err),
+        };
+        assert!(result.is_err());
+    }
+
+    #[test_log::test]
+    fn flush_all() {
+        spawn_thread!(_flush_all()).join().unwrap();
+    }
+
+    async fn _flush_all() {
+        let context1 = Context::new().unwrap();
+        let worker1 = context1.create_worker().unwrap();
+        let context2 = Context::new().unwrap();
+        let worker2 = context2.create_worker().unwrap();
+        tokio::task::spawn_local(worker1.clone().polling());
+        tokio::task::spawn_local(worker2.clone().polling());
+        let mut listener = worker1
+            .create_listener("0.0.0.0:0".parse().unwrap())
+            .unwrap();
+        let listen_port = listener.socket_addr().unwrap().port();
+        let mut addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        addr.set_port(listen_port);
+
+        // three connections, and a dropped one which is not flushed
+        let mut endpoints = Vec::new();
+        for _ in 0..4 {
+            let (endpoint1, endpoint2) = tokio::join!(
+                async {
+                    let conn1 = listener.next().await;
+                    worker1.accept(conn1).await.unwrap()
+                },
+                async { worker2.connect_socket(addr).await.unwrap() },
+            );
+            endpoints.push((endpoint1, endpoint2));
+        }
+        drop(endpoints.pop());
+        assert_eq!(worker2.endpoints.live().len(), 3);
+
+        let mut region = vec![0_u8; 3];
+        let mem = MemoryHandle::register(&context1, &mut region);
+        let rkey_buf = mem.pack();
+        for (i, (_, endpoint2)) in endpoints.iter().enumerate() {
+            let rkey = RKey::unpack(endpoint2, rkey_buf.as_ref());
+            let addr = region.as_ptr() as u64 + i as u64;
+            endpoint2.put(&[i as u8 + 1], addr, &rkey).await.unwrap();
+        }
+        worker2.flush_all().await.unwrap();
+        assert_eq!(region, [1, 2, 3]);
+
+        // closed endpoints are skipped
+        endpoints[0].1.close(false).await.unwrap();
+        assert_eq!(worker2.endpoints.live().len(), 2);
+        worker2.flush_all().await.unwrap();
+    }
+
+    #[test_log::test]
+    fn error_events() {
+        spawn_thread!(_error_events()).join().unwrap();
+    }
+
+    async fn _error_events() {
+        use futures::StreamExt;
+
+        let context1 = Context::new().unwrap();
+        let worker1 = context1.create_worker().unwrap();
+        tokio::task::spawn_local(worker1.clone().polling());
+        let mut listener = worker1
+            .create_listener("0.0.0.0:0".parse().unwrap())
+            .unwrap();
+        let listen_port = listener.socket_addr().unwrap().port();
+        let mut addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        addr.set_port(listen_port);
+        let mut events = worker1.error_events();
+
+        // accept two peers
+        let mut endpoints = Vec::new();
+        let mut peers = Vec::new();
+        for _ in 0..2 {
+            let context2 = Context::new().unwrap();
+            let worker2 = context2.create_worker().unwrap();
+            tokio::task::spawn_local(worker2.clone().polling());
+            let (endpoint1, endpoint2) = tokio::join!(
+                async {
+                    let conn1 = listener.next().await;
+                    worker1.accept(conn1).await.unwrap()
+                },
+                async { worker2.connect_socket(addr).await.unwrap() },
+            );
+            endpoints.push(endpoint1);
+            peers.push((context2, worker2, endpoint2));
+        }
+        assert_ne!(endpoints[0].id(), endpoints[1].id());
+        assert_eq!(endpoints[0].clone().id(), endpoints[0].id());
+
+        // kill both peers
+        drop(peers);
+        let mut failed = vec![
+            events.next().await.unwrap().0,
+            events.next().await.unwrap().0,
+        ];
+        failed.sort();
+        let mut ids: Vec<_> = endpoints.iter().map(|e| e.id()).collect();
+        ids.sort();
+        assert_eq!(failed, ids);
+        assert!(endpoints.iter().all(|e| !e.is_alive()));
+    }
+}
@@ -0,0 +1,190 @@
+use super::*;
+use std::alloc::Layout;
+use std::cell::RefCell;
+use std::mem::ManuallyDrop;
+use std::ptr::NonNull;
+
+/// A fixed-size pool of request slots allocated by the application.
+///
+/// Operations issued with a pool hand a slot to UCX by `UCP_OP_ATTR_FIELD_REQUEST`,
+/// so UCX doesn't need to allocate a request for them. A slot is reused once
+/// its operation is completed.
+#[derive(Debug)]
+pub struct RequestPool {
+    memory: NonNull<u8>,
+    layout: Layout,
+    slot_size: usize,
+    capacity: usize,
+    // offset of our `Request` in a slot, UCP request data is stored before it
+    offset: usize,
+    free: RefCell<Vec<usize>>,
+}
+
+impl RequestPool {
+    /// Create a pool with `capacity` request slots for operations on `context`.
+    pub fn new(context: &Context, capacity: usize) -> Result<Self, Error> {
+        let ucp_size = context.request_size()?;
+        let align = std::mem::align_of::<Request>().max(64);
+        let offset = round_up(ucp_size, align);
+        let slot_size = round_up(offset + std::mem::size_of::<Request>(), align);
+        let layout = Layout::from_size_align(slot_size * capacity.max(1), align)
+            .map_err(|_| Error::InvalidParam)?;
+        let memory = NonNull::new(unsafe { std::alloc::alloc(layout) }).ok_or(Error::NoMemory)?;
+        Ok(RequestPool {
+            memory,
+            layout,
+            slot_size,
+            capacity,
+            offset,
+            free: RefCell::new((0..capacity).rev().collect()),
+        })
+    }
+
+    /// Number of slots which are not in use.
+    pub fn available(&self) -> usize {
+        self.free.borrow().len()
+    }
+
+    pub(super) fn alloc(&self) -> Result<RequestSlot<'_>, Error> {
+        let index = self.free.borrow_mut().pop().ok_or(Error::NoReource)?;
+        let slot = RequestSlot { pool: self, index };
+        unsafe { (slot.as_ptr() as *mut Request).write(Request::default()) };
+        Ok(slot)
+    }
+}
+
+impl Drop for RequestPool {
+    fn drop(&mut self) {
+        if self.available() != self.capacity {
+            // UCX may still write to the slots of unfinished requests
+            error!("Request pool dropped with requests in flight, leak its memory");
+            return;
+        }
+        unsafe { std::alloc::dealloc(self.memory.as_ptr(), self.layout) };
+    }
+}
+
+#[inline]
+fn round_up(size: usize, align: usize) -> usize {
+    (size + align - 1) / align * align
+}
+
+/// A request slot borrowed from a [`RequestPool`].
+pub(super) struct RequestSlot<'a> {
+    pool: &'a RequestPool,
+    index: usize,
+}
+
+impl<'a> RequestSlot<'a> {
+    /// The pointer passed to UCX as the request.
+    pub(super) fn as_ptr(&self) -> *mut c_void {
+        let offset = self.index * self.pool.slot_size + self.pool.offset;
+        unsafe { self.pool.memory.as_ptr().add(offset) as _ }
+    }
+}
+
+impl<'a> Drop for RequestSlot<'a> {
+    fn drop(&mut self) {
+        unsafe { std::ptr::drop_in_place(self.as_ptr() as *mut Request) };
+        self.pool.free.borrow_mut().push(self.index);
+    }
+}
+
+/// Like `RequestHandle`, for requests stored in a pooled slot.
+///
+/// The slot is released back to the pool instead of `ucp_request_free`.
+pub(super) struct PooledRequestHandle<'a, T> {
+    slot: ManuallyDrop<RequestSlot<'a>>,
+    poll_fn: unsafe fn(ucs_status_ptr_t) -> Poll<T>,
+    _inflight: InflightGuard,
+}
+
+impl<'a, T> PooledRequestHandle<'a, T> {
+    pub(super) fn new(
+        worker: &Worker,
+        slot: RequestSlot<'a>,
+        poll_fn: unsafe fn(ucs_status_ptr_t) -> Poll<T>,
+    ) -> Self {
+        PooledRequestHandle {
+            slot: ManuallyDrop::new(slot),
+            poll_fn,
+            _inflight: worker.inflight.track(),
+        }
+    }
+}
+
+impl<'a, T> Future for PooledRequestHandle<'a, T> {
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context) -> Poll<Self::Output> {
+        let ptr = self.slot.as_ptr();
+        if let ret @ Poll::Ready(_) = unsafe { (self.poll_fn)(ptr) } {
+            return ret;
+        }
+        let request = unsafe { &mut *(ptr as *mut Request) };
+        request.waker.register(cx.waker());
+        unsafe { (self.poll_fn)(ptr) }
+    }
+}
+
+impl<'a, T> Drop for PooledRequestHandle<'a, T> {
+    fn drop(&mut self) {
+        let status = unsafe { ucp_request_check_status(self.slot.as_ptr()) };
+        if status == ucs_status_t::UCS_INPROGRESS {
+            // the slot can't be reused before UCX completes the request
+            warn!("request dropped in flight, slot {} leaked", self.slot.index);
+        } else {
+            unsafe { ManuallyDrop::drop(&mut self.slot) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn pool() {
+        spawn_thread!(_pool()).join().unwrap();
+    }
+
+    async fn _pool() {
+        const CAPACITY: usize = 4;
+
+        let (_endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        let pool1 = RequestPool::new(&worker1.context, CAPACITY).unwrap();
+        let pool2 = RequestPool::new(&worker2.context, CAPACITY).unwrap();
+
+        // every request of these operations lives in the pools
+        let msg = vec![1_u8; 0x10000];
+        for i in 0..1000 {
+            let (sent, recv) = tokio::join!(endpoint2.tag_send_pooled(&pool2, i, &msg), async {
+                let mut buf = vec![MaybeUninit::uninit(); msg.len()];
+                worker1.tag_recv_pooled(&pool1, i, !0, &mut buf).await
+            });
+            assert_eq!(sent, Ok(msg.len()));
+            assert_eq!(recv, Ok((i, msg.len())));
+        }
+        #[cfg(feature = "am")]
+        {
+            let stream1 = worker1.am_stream(16).unwrap();
+            for _ in 0..1000 {
+                let sent = endpoint2.am_send_pooled(&pool2, 16, &[], &msg, AmSendFlags::empty());
+                assert_eq!(sent.await, Ok(msg.len()));
+                let mut recv = stream1.wait_msg().await.expect("no msg");
+                assert_eq!(recv.recv_data().await.unwrap(), msg);
+            }
+        }
+        assert_eq!(pool1.available(), CAPACITY);
+        assert_eq!(pool2.available(), CAPACITY);
+
+        // exhausted pool
+        let slots: Vec<_> = (0..CAPACITY).map(|_| pool2.alloc().unwrap()).collect();
+        assert_eq!(
+            endpoint2.tag_send_pooled(&pool2, 0, &msg).await,
+            Err(Error::NoReource)
+        );
+        drop(slots);
+        assert_eq!(pool2.available(), CAPACITY);
+    }
+}
@@ -4,29 +4,66 @@ use super::*;
 /// which is optimized for remote memory access operations.
 #[derive(Debug)]
 pub struct MemoryHandle {
-    handle: ucp_mem_h,
+    pub(super) handle: ucp_mem_h,
     context: Arc<Context>,
+    address: *mut u8,
+    length: usize,
+    pub(super) mem_type: ucs_memory_type_t,
 }
 
 impl MemoryHandle {
     /// Register memory region.
     pub fn register(context: &Arc<Context>, region: &mut [u8]) -> Self {
+        let mem_type = ucs_memory_type::UCS_MEMORY_TYPE_HOST;
+        unsafe { Self::map(context, region.as_mut_ptr(), region.len(), mem_type) }.unwrap()
+    }
+
+    // register `length` bytes of `mem_type` memory at `address`
+    pub(super) unsafe fn map(
+        context: &Arc<Context>,
+        address: *mut u8,
+        length: usize,
+        mem_type: ucs_memory_type_t,
+    ) -> Result<Self, Error> {
         #[allow(invalid_value)]
         #[allow(clippy::uninit_assumed_init)]
         let params = ucp_mem_map_params_t {
             field_mask: (ucp_mem_map_params_field::UCP_MEM_MAP_PARAM_FIELD_ADDRESS
-                | ucp_mem_map_params_field::UCP_MEM_MAP_PARAM_FIELD_LENGTH)
+                | ucp_mem_map_params_field::UCP_MEM_MAP_PARAM_FIELD_LENGTH
+                | ucp_mem_map_params_field::UCP_MEM_MAP_PARAM_FIELD_MEMORY_TYPE)
                 .0 as u64,
-            address: region.as_ptr() as _,
-            length: region.len() as _,
-            ..unsafe { MaybeUninit::uninit().assume_init() }
+            address: address as _,
+            length: length as _,
+            memory_type: mem_type,
+            ..MaybeUninit::uninit().assume_init()
         };
         let mut handle = MaybeUninit::uninit();
-        let status = unsafe { ucp_mem_map(context.handle, &params, handle.as_mut_ptr()) };
-        assert_eq!(status, ucs_status_t::UCS_OK);
-        MemoryHandle {
-            handle: unsafe { handle.assume_init() },
+        let status = ucp_mem_map(context.handle, &params, handle.as_mut_ptr());
+        Error::from_status(status)?;
+        Ok(MemoryHandle {
+            handle: handle.assume_init(),
             context: context.clone(),
+            address,
+            length,
+            mem_type,
+        })
+    }
+
+    /// Length of the registered memory region.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Whether the registered memory region is empty.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    // pointer to `len` bytes at `offset` of the region
+    pub(super) fn ptr_at(&self, offset: usize, len: usize) -> Result<*mut u8, Error> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.length => Ok(unsafe { self.address.add(offset) }),
+            _ => Err(Error::OutOfRange),
         }
     }
 
@@ -112,65 +149,79 @@ impl Endpoint {
     /// Stores a contiguous block of data into remote memory.
     pub async fn put(&self, buf: &[u8], remote_addr: u64, rkey: &RKey) -> Result<(), Error> {
         trace!("put: endpoint={:?} len={}", self.handle, buf.len());
-        unsafe extern "C" fn callback(request: *mut c_void, status: ucs_status_t) {
-            trace!("put: complete. req={:?}, status={:?}", request, status);
-            let request = &mut *(request as *mut Request);
-            request.waker.wake();
-        }
-        let status = unsafe {
-            ucp_put_nb(
-                self.get_handle()?,
-                buf.as_ptr() as _,
-                buf.len() as _,
-                remote_addr,
-                rkey.handle,
-                Some(callback),
-            )
-        };
-        if status.is_null() {
-            trace!("put: complete.");
-            Ok(())
-        } else if UCS_PTR_IS_PTR(status) {
-            RequestHandle {
-                ptr: status,
-                poll_fn: poll_normal,
-            }
-            .await
-        } else {
-            Error::from_ptr(status)
-        }
+        put(self.worker(), self.get_handle()?, buf, remote_addr, rkey).await
     }
 
     /// Loads a contiguous block of data from remote memory.
     pub async fn get(&self, buf: &mut [u8], remote_addr: u64, rkey: &RKey) -> Result<(), Error> {
         trace!("get: endpoint={:?} len={}", self.handle, buf.len());
-        unsafe extern "C" fn callback(request: *mut c_void, status: ucs_status_t) {
-            trace!("get: complete. req={:?}, status={:?}", request, status);
-            let request = &mut *(request as *mut Request);
-            request.waker.wake();
-        }
-        let status = unsafe {
-            ucp_get_nb(
-                self.get_handle()?,
-                buf.as_mut_ptr() as _,
-                buf.len() as _,
-                remote_addr,
-                rkey.handle,
-                Some(callback),
-            )
-        };
-        if status.is_null() {
-            trace!("get: complete.");
-            Ok(())
-        } else if UCS_PTR_IS_PTR(status) {
-            RequestHandle {
-                ptr: status,
-                poll_fn: poll_normal,
-            }
-            .await
-        } else {
-            Error::from_ptr(status)
-        }
+        get(self.worker(), self.get_handle()?, buf, remote_addr, rkey).await
+    }
+}
+
+// Store `buf` to remote memory through a raw endpoint, shared with `EndpointRef`.
+pub(super) async fn put(
+    worker: &Worker,
+    endpoint: ucp_ep_h,
+    buf: &[u8],
+    remote_addr: u64,
+    rkey: &RKey,
+) -> Result<(), Error> {
+    unsafe extern "C" fn callback(request: *mut c_void, status: ucs_status_t) {
+        trace!("put: complete. req={:?}, status={:?}", request, status);
+        let request = &mut *(request as *mut Request);
+        request.waker.wake();
+    }
+    let status = unsafe {
+        ucp_put_nb(
+            endpoint,
+            buf.as_ptr() as _,
+            buf.len() as _,
+            remote_addr,
+            rkey.handle,
+            Some(callback),
+        )
+    };
+    if status.is_null() {
+        trace!("put: complete.");
+        Ok(())
+    } else if UCS_PTR_IS_PTR(status) {
+        RequestHandle::new(worker, status, poll_normal).await
+    } else {
+        Error::from_ptr(status)
+    }
+}
+
+// Load remote memory into `buf` through a raw endpoint, shared with `EndpointRef`.
+pub(super) async fn get(
+    worker: &Worker,
+    endpoint: ucp_ep_h,
+    buf: &mut [u8],
+    remote_addr: u64,
+    rkey: &RKey,
+) -> Result<(), Error> {
+    unsafe extern "C" fn callback(request: *mut c_void, status: ucs_status_t) {
+        trace!("get: complete. req={:?}, status={:?}", request, status);
+        let request = &mut *(request as *mut Request);
+        request.waker.wake();
+    }
+    let status = unsafe {
+        ucp_get_nb(
+            endpoint,
+            buf.as_mut_ptr() as _,
+            buf.len() as _,
+            remote_addr,
+            rkey.handle,
+            Some(callback),
+        )
+    };
+    if status.is_null() {
+        trace!("get: complete.");
+        Ok(())
+    } else if UCS_PTR_IS_PTR(status) {
+        RequestHandle::new(worker, status, poll_normal).await
+    } else {
+        Error::from_ptr(status)
     }
 }
 
@@ -233,13 +284,41 @@ mod tests {
             .unwrap();
         assert_eq!(&buf1[..], &buf2[..]);
 
-        assert_eq!(endpoint1.get_rc(), (1, 1));
-        assert_eq!(endpoint2.get_rc(), (1, 1));
+        assert_eq!(endpoint1.get_rc(), (1, 2));
+        assert_eq!(endpoint2.get_rc(), (1, 2));
         assert_eq!(endpoint1.close(false).await, Ok(()));
         assert_eq!(endpoint2.close(false).await, Err(Error::ConnectionReset));
         assert_eq!(endpoint1.get_rc(), (1, 0));
-        assert_eq!(endpoint2.get_rc(), (1, 1));
+        assert_eq!(endpoint2.get_rc(), (1, 2));
         assert_eq!(endpoint2.close(true).await, Ok(()));
         assert_eq!(endpoint2.get_rc(), (1, 0));
     }
+
+    #[test_log::test]
+    fn put_fence_get() {
+        spawn_thread!(_put_fence_get()).join().unwrap();
+    }
+
+    async fn _put_fence_get() {
+        let (_endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        let mut buf1: Vec<u8> = vec![0; 0x1000];
+        let mem1 = MemoryHandle::register(&worker1.context, &mut buf1);
+        let rkey_buf = mem1.pack();
+        let rkey2 = RKey::unpack(&endpoint2, rkey_buf.as_ref());
+
+        // the get is ordered after the put without flushing in between
+        let put_buf: Vec<u8> = (0..0x1000).map(|x| (x * 7) as u8).collect();
+        let mut get_buf: Vec<u8> = vec![0; 0x1000];
+        endpoint2
+            .put(&put_buf, buf1.as_mut_ptr() as u64, &rkey2)
+            .await
+            .unwrap();
+        worker2.fence().unwrap();
+        endpoint2
+            .get(&mut get_buf, buf1.as_ptr() as u64, &rkey2)
+            .await
+            .unwrap();
+        assert_eq!(get_buf, put_buf);
+    }
 }
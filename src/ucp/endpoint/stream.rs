@@ -1,40 +1,12 @@
 use super::*;
+use std::ops::Deref;
 
 impl Endpoint {
     /// Sends data through stream.
     pub async fn stream_send(&self, buf: &[u8]) -> Result<usize, Error> {
         trace!("stream_send: endpoint={:?} len={}", self.handle, buf.len());
-        unsafe extern "C" fn callback(request: *mut c_void, status: ucs_status_t) {
-            trace!(
-                "stream_send: complete. req={:?}, status={:?}",
-                request,
-                status
-            );
-            let request = &mut *(request as *mut Request);
-            request.waker.wake();
-        }
-        let status = unsafe {
-            ucp_stream_send_nb(
-                self.get_handle()?,
-                buf.as_ptr() as _,
-                buf.len() as _,
-                ucp_dt_make_contig(1),
-                Some(callback),
-                0,
-            )
-        };
-        if status.is_null() {
-            trace!("stream_send: complete");
-        } else if UCS_PTR_IS_PTR(status) {
-            RequestHandle {
-                ptr: status,
-                poll_fn: poll_normal,
-            }
-            .await?;
-        } else {
-            return Err(Error::from_ptr(status).unwrap_err());
-        }
-        Ok(buf.len())
+        let _permit = self.send_permit().await;
+        stream_send(self.worker(), self.get_handle()?, buf).await
     }
 
     /// Receives data from stream.
@@ -67,15 +39,62 @@ impl Endpoint {
             trace!("stream_recv: complete. len={}", length);
             Ok(length)
         } else if UCS_PTR_IS_PTR(status) {
-            Ok(RequestHandle {
-                ptr: status,
-                poll_fn: poll_stream,
-            }
-            .await)
+            Ok(RequestHandle::new(self.worker(), status, poll_stream).await)
         } else {
             Err(Error::from_ptr(status).unwrap_err())
         }
     }
+
+    /// Receives the data available on the stream without copying it,
+    /// waiting until there is some.
+    ///
+    /// The data stays in UCX memory until the returned handle is dropped.
+    pub async fn stream_recv_data(&self) -> Result<StreamDataHandle<'_>, Error> {
+        trace!("stream_recv_data: endpoint={:?}", self.handle);
+        loop {
+            let mut length = MaybeUninit::<usize>::uninit();
+            let status =
+                unsafe { ucp_stream_recv_data_nb(self.get_handle()?, length.as_mut_ptr()) };
+            if UCS_PTR_IS_PTR(status) {
+                let length = unsafe { length.assume_init() };
+                trace!("stream_recv_data: complete. len={}", length);
+                let data = unsafe { std::slice::from_raw_parts(status as *const u8, length) };
+                return Ok(StreamDataHandle {
+                    endpoint: self,
+                    data,
+                });
+            } else if !status.is_null() {
+                return Err(Error::from_ptr(status).unwrap_err());
+            }
+            // no data yet
+            self.worker().wait_progress().await;
+        }
+    }
+}
+
+/// Stream data held by UCX, see [`Endpoint::stream_recv_data`].
+///
+/// The data is released back to UCX on drop.
+pub struct StreamDataHandle<'a> {
+    endpoint: &'a Endpoint,
+    data: &'a [u8],
+}
+
+impl<'a> Deref for StreamDataHandle<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl<'a> Drop for StreamDataHandle<'a> {
+    fn drop(&mut self) {
+        // a closed endpoint already released its data
+        if let Ok(handle) = self.endpoint.get_handle() {
+            unsafe { ucp_stream_data_release(handle, self.data.as_ptr() as _) };
+        }
+    }
 }
 
 unsafe fn poll_stream(ptr: ucs_status_ptr_t) -> Poll<usize> {
@@ -87,3 +106,81 @@ unsafe fn poll_stream(ptr: ucs_status_ptr_t) -> Poll<usize> {
         Poll::Ready(len.assume_init())
     }
 }
+
+// Send `buf` on a raw endpoint, shared with `EndpointRef`.
+pub(super) async fn stream_send(
+    worker: &Worker,
+    endpoint: ucp_ep_h,
+    buf: &[u8],
+) -> Result<usize, Error> {
+    unsafe extern "C" fn callback(request: *mut c_void, status: ucs_status_t) {
+        trace!(
+            "stream_send: complete. req={:?}, status={:?}",
+            request,
+            status
+        );
+        let request = &mut *(request as *mut Request);
+        request.waker.wake();
+    }
+    let status = unsafe {
+        ucp_stream_send_nb(
+            endpoint,
+            buf.as_ptr() as _,
+            buf.len() as _,
+            ucp_dt_make_contig(1),
+            Some(callback),
+            0,
+        )
+    };
+    if status.is_null() {
+        trace!("stream_send: complete");
+    } else if UCS_PTR_IS_PTR(status) {
+        RequestHandle::new(worker, status, poll_normal).await?;
+    } else {
+        return Err(Error::from_ptr(status).unwrap_err());
+    }
+    Ok(buf.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn stream_recv_data() {
+        spawn_thread!(_stream_recv_data()).join().unwrap();
+    }
+
+    async fn _stream_recv_data() {
+        let (endpoint1, _, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let data: Vec<u8> = (0..1 << 24).map(|i| (i % 251) as u8).collect();
+
+        // copying receive
+        let (sent, copied) = tokio::join!(endpoint2.stream_send(&data), async {
+            let mut buf = vec![MaybeUninit::uninit(); data.len()];
+            let mut received = 0;
+            while received < data.len() {
+                received += endpoint1.stream_recv(&mut buf[received..]).await.unwrap();
+            }
+            buf.into_iter()
+                .map(|x| unsafe { x.assume_init() })
+                .collect::<Vec<u8>>()
+        });
+        assert_eq!(sent, Ok(data.len()));
+
+        // zero-copy receive
+        let (sent, received) = tokio::join!(endpoint2.stream_send(&data), async {
+            let mut received = Vec::with_capacity(data.len());
+            while received.len() < data.len() {
+                let chunk = endpoint1.stream_recv_data().await.unwrap();
+                assert!(!chunk.is_empty());
+                received.extend_from_slice(&chunk);
+            }
+            received
+        });
+        assert_eq!(sent, Ok(data.len()));
+        assert_eq!(copied, data);
+        assert_eq!(received, copied);
+    }
+}
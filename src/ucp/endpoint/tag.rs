@@ -1,5 +1,116 @@
 use super::*;
 use std::io::{IoSlice, IoSliceMut};
+use std::ops::BitOr;
+use tracing::instrument;
+
+/// A message tag, with helpers for the convention of routing by rank and operation.
+///
+/// The rank takes the top 16 bits, the operation the next 16 bits
+/// and a sequence number the low 32 bits:
+///
+/// ```
+/// use async_ucx::ucp::Tag;
+///
+/// let tag = Tag::with_rank_and_op(3, 7).with_seq(42);
+/// assert_eq!(tag, Tag(0x0003_0007_0000_002a));
+/// assert_eq!((tag.rank(), tag.op(), tag.seq()), (3, 7, 42));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Tag(pub u64);
+
+impl Tag {
+    const RANK_SHIFT: u32 = 48;
+    const OP_SHIFT: u32 = 32;
+
+    /// A tag addressing `op` of `rank`, with sequence number 0.
+    pub const fn with_rank_and_op(rank: u16, op: u16) -> Self {
+        Tag((rank as u64) << Self::RANK_SHIFT | (op as u64) << Self::OP_SHIFT)
+    }
+
+    /// Replaces the sequence number.
+    pub const fn with_seq(self, seq: u32) -> Self {
+        Tag(self.0 & !TagMask::SEQ.0 | seq as u64)
+    }
+
+    /// The rank, in the top 16 bits.
+    pub const fn rank(self) -> u16 {
+        (self.0 >> Self::RANK_SHIFT) as u16
+    }
+
+    /// The operation, in the 16 bits below the rank.
+    pub const fn op(self) -> u16 {
+        (self.0 >> Self::OP_SHIFT) as u16
+    }
+
+    /// The sequence number, in the low 32 bits.
+    pub const fn seq(self) -> u32 {
+        self.0 as u32
+    }
+
+    /// Whether a message sent with `tag` is received by a receive of this tag and `mask`.
+    ///
+    /// ```
+    /// use async_ucx::ucp::{Tag, TagMask};
+    ///
+    /// let recv = Tag::with_rank_and_op(3, 7);
+    /// assert!(recv.matches(recv.with_seq(42), TagMask::RANK | TagMask::OP));
+    /// assert!(!recv.matches(recv.with_seq(42), TagMask::EXACT));
+    /// assert!(!recv.matches(Tag::with_rank_and_op(4, 7), TagMask::RANK));
+    /// assert!(recv.matches(Tag(u64::MAX), TagMask::ANY));
+    /// ```
+    pub const fn matches(self, tag: Tag, mask: TagMask) -> bool {
+        (self.0 ^ tag.0) & mask.0 == 0
+    }
+}
+
+impl From<u64> for Tag {
+    fn from(tag: u64) -> Self {
+        Tag(tag)
+    }
+}
+
+impl From<Tag> for u64 {
+    fn from(tag: Tag) -> Self {
+        tag.0
+    }
+}
+
+/// The bits of a [`Tag`] a receive matches on, combined with `|`.
+///
+/// ```
+/// use async_ucx::ucp::TagMask;
+///
+/// assert_eq!(TagMask::RANK | TagMask::OP | TagMask::SEQ, TagMask::EXACT);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TagMask(pub u64);
+
+impl TagMask {
+    /// Matches all bits.
+    pub const EXACT: Self = TagMask(u64::MAX);
+    /// Matches any tag.
+    pub const ANY: Self = TagMask(0);
+    /// Matches the rank.
+    pub const RANK: Self = TagMask(0xffff << Tag::RANK_SHIFT);
+    /// Matches the operation.
+    pub const OP: Self = TagMask(0xffff << Tag::OP_SHIFT);
+    /// Matches the sequence number.
+    pub const SEQ: Self = TagMask(0xffff_ffff);
+}
+
+impl Default for TagMask {
+    fn default() -> Self {
+        TagMask::EXACT
+    }
+}
+
+impl BitOr for TagMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        TagMask(self.0 | rhs.0)
+    }
+}
 
 impl Worker {
     /// Receives a message with `tag`.
@@ -10,19 +121,25 @@ impl Worker {
     }
 
     /// Receives a message with `tag` and `tag_mask`.
+    #[instrument(
+        level = "trace",
+        skip_all,
+        fields(
+            worker = ?self.handle,
+            tag = tag,
+            mask = tag_mask,
+            len = buf.len(),
+            request = next_request_id()
+        ),
+        ret,
+        err(level = "debug")
+    )]
     pub async fn tag_recv_mask(
         &self,
         tag: u64,
         tag_mask: u64,
         buf: &mut [MaybeUninit<u8>],
     ) -> Result<(u64, usize), Error> {
-        trace!(
-            "tag_recv: worker={:?}, tag={}, mask={:#x} len={}",
-            self.handle,
-            tag,
-            tag_mask,
-            buf.len()
-        );
         unsafe extern "C" fn callback(
             request: *mut c_void,
             status: ucs_status_t,
@@ -51,11 +168,237 @@ impl Worker {
         };
 
         Error::from_ptr(status)?;
-        RequestHandle {
-            ptr: status,
-            poll_fn: poll_tag,
+        RequestHandle::new(self, status, poll_tag).await
+    }
+
+    /// Like `tag_recv_mask`, except that it takes a typed tag and mask.
+    ///
+    /// Returns the tag of the received message and its length.
+    pub async fn tag_recv_matching(
+        &self,
+        tag: Tag,
+        mask: TagMask,
+        buf: &mut [MaybeUninit<u8>],
+    ) -> Result<(Tag, usize), Error> {
+        let (tag, len) = self.tag_recv_mask(tag.0, mask.0, buf).await?;
+        Ok((Tag(tag), len))
+    }
+
+    /// Receives a message with `tag` and `tag_mask` into a registered memory region.
+    ///
+    /// At most `len` bytes are written to `mem` starting at `offset`.
+    /// Passing the memory handle lets UCX skip registering the buffer internally,
+    /// so large messages can use the zero-copy rendezvous protocol.
+    ///
+    /// # Safety
+    /// The region registered by `mem` must stay valid, and must not be accessed
+    /// otherwise, until the receive completes.
+    pub async unsafe fn tag_recv_mem(
+        &self,
+        tag: u64,
+        tag_mask: u64,
+        mem: &MemoryHandle,
+        offset: usize,
+        len: usize,
+    ) -> Result<(u64, usize), Error> {
+        let buf = mem.ptr_at(offset, len)?;
+        trace!(
+            "tag_recv_mem: worker={:?}, tag={}, mask={:#x} offset={} len={}",
+            self.handle,
+            tag,
+            tag_mask,
+            offset,
+            len
+        );
+        unsafe extern "C" fn callback(
+            request: *mut c_void,
+            status: ucs_status_t,
+            info: *const ucp_tag_recv_info,
+            _user_data: *mut c_void,
+        ) {
+            let length = (*info).length;
+            trace!(
+                "tag_recv_mem: complete. req={:?}, status={:?}, len={}",
+                request,
+                status,
+                length
+            );
+            let request = &mut *(request as *mut Request);
+            request.waker.wake();
+        }
+        let mut info = MaybeUninit::<ucp_tag_recv_info>::uninit();
+        let mut param = MaybeUninit::<ucp_request_param_t>::uninit();
+        unsafe {
+            let param = &mut *param.as_mut_ptr();
+            param.op_attr_mask = ucp_op_attr_t::UCP_OP_ATTR_FIELD_CALLBACK as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_DATATYPE as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_MEMH as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_RECV_INFO as u32;
+            param.cb = ucp_request_param_t__bindgen_ty_1 {
+                recv: Some(callback),
+            };
+            param.datatype = ucp_dt_make_contig(1);
+            param.memh = mem.handle;
+            param.recv_info = ucp_request_param_t__bindgen_ty_2 {
+                tag_info: info.as_mut_ptr(),
+            };
+        }
+        let status = unsafe {
+            ucp_tag_recv_nbx(
+                self.handle,
+                buf as _,
+                len as _,
+                tag,
+                tag_mask,
+                param.as_ptr(),
+            )
+        };
+        if status.is_null() {
+            let info = unsafe { info.assume_init() };
+            trace!("tag_recv_mem: complete. len={}", info.length);
+            Ok((info.sender_tag, info.length as usize))
+        } else if UCS_PTR_IS_PTR(status) {
+            RequestHandle::new(self, status, poll_tag).await
+        } else {
+            Err(Error::from_ptr(status).unwrap_err())
+        }
+    }
+
+    /// Like `tag_recv_mask`, except that the request is allocated from `pool`.
+    ///
+    /// Fails with [`Error::NoReource`] if the pool is exhausted.
+    pub async fn tag_recv_pooled(
+        &self,
+        pool: &RequestPool,
+        tag: u64,
+        tag_mask: u64,
+        buf: &mut [MaybeUninit<u8>],
+    ) -> Result<(u64, usize), Error> {
+        trace!(
+            "tag_recv_pooled: worker={:?}, tag={}, mask={:#x} len={}",
+            self.handle,
+            tag,
+            tag_mask,
+            buf.len()
+        );
+        unsafe extern "C" fn callback(
+            request: *mut c_void,
+            status: ucs_status_t,
+            info: *const ucp_tag_recv_info,
+            _user_data: *mut c_void,
+        ) {
+            let length = (*info).length;
+            trace!(
+                "tag_recv_pooled: complete. req={:?}, status={:?}, len={}",
+                request,
+                status,
+                length
+            );
+            let request = &mut *(request as *mut Request);
+            request.waker.wake();
+        }
+        let slot = pool.alloc()?;
+        let mut info = MaybeUninit::<ucp_tag_recv_info>::uninit();
+        let mut param = MaybeUninit::<ucp_request_param_t>::uninit();
+        unsafe {
+            let param = &mut *param.as_mut_ptr();
+            param.op_attr_mask = ucp_op_attr_t::UCP_OP_ATTR_FIELD_CALLBACK as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_DATATYPE as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_REQUEST as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_RECV_INFO as u32;
+            param.cb = ucp_request_param_t__bindgen_ty_1 {
+                recv: Some(callback),
+            };
+            param.datatype = ucp_dt_make_contig(1);
+            param.request = slot.as_ptr();
+            param.recv_info = ucp_request_param_t__bindgen_ty_2 {
+                tag_info: info.as_mut_ptr(),
+            };
+        }
+        let status = unsafe {
+            ucp_tag_recv_nbx(
+                self.handle,
+                buf.as_mut_ptr() as _,
+                buf.len() as _,
+                tag,
+                tag_mask,
+                param.as_ptr(),
+            )
+        };
+        if status.is_null() {
+            let info = unsafe { info.assume_init() };
+            trace!("tag_recv_pooled: complete. len={}", info.length);
+            Ok((info.sender_tag, info.length as usize))
+        } else if UCS_PTR_IS_PTR(status) {
+            PooledRequestHandle::new(self, slot, poll_tag).await
+        } else {
+            Err(Error::from_ptr(status).unwrap_err())
+        }
+    }
+
+    /// Receives a message with `tag` and `tag_mask` into a buffer allocated
+    /// to its exact length.
+    ///
+    /// The message is probed for again each time the worker makes progress,
+    /// so the worker must be progressed meanwhile.
+    /// Returns the data and the tag of the sender.
+    ///
+    /// The message is taken off the queue once it is probed, so cancelling
+    /// the receive afterwards loses it.
+    pub async fn tag_recv_alloc(&self, tag: u64, tag_mask: u64) -> Result<(Vec<u8>, u64), Error> {
+        trace!(
+            "tag_recv_alloc: worker={:?}, tag={}, mask={:#x}",
+            self.handle,
+            tag,
+            tag_mask
+        );
+        unsafe extern "C" fn callback(
+            request: *mut c_void,
+            status: ucs_status_t,
+            info: *mut ucp_tag_recv_info,
+        ) {
+            let length = (*info).length;
+            trace!(
+                "tag_recv_alloc: complete. req={:?}, status={:?}, len={}",
+                request,
+                status,
+                length
+            );
+            let request = &mut *(request as *mut Request);
+            request.waker.wake();
         }
-        .await
+
+        // the message is taken off the unexpected queue, receive it without yielding
+        let mut info = MaybeUninit::<ucp_tag_recv_info>::uninit();
+        let message = loop {
+            let message =
+                unsafe { ucp_tag_probe_nb(self.handle, tag, tag_mask, 1, info.as_mut_ptr()) };
+            if !message.is_null() {
+                break message;
+            }
+            self.wait_progress().await;
+        };
+        let info = unsafe { info.assume_init() };
+        let mut buf = Vec::<u8>::with_capacity(info.length as usize);
+        let status = unsafe {
+            ucp_tag_msg_recv_nb(
+                self.handle,
+                buf.as_mut_ptr() as _,
+                info.length as _,
+                ucp_dt_make_contig(1),
+                message,
+                Some(callback),
+            )
+        };
+        Error::from_ptr(status)?;
+        let mut recv = AllocRecv {
+            request: RequestHandle::new(self, status, poll_tag),
+            buf: Some(buf),
+        };
+        let (sender_tag, len) = (&mut recv.request).await?;
+        let mut buf = recv.buf.take().unwrap();
+        unsafe { buf.set_len(len) };
+        Ok((buf, sender_tag))
     }
 
     /// Like `tag_recv`, except that it reads into a slice of buffers.
@@ -96,26 +439,50 @@ impl Worker {
             )
         };
         Error::from_ptr(status)?;
-        RequestHandle {
-            ptr: status,
-            poll_fn: poll_tag,
-        }
-        .await
-        .map(|info| info.1)
+        RequestHandle::new(self, status, poll_tag)
+            .await
+            .map(|info| info.1)
     }
 }
 
 impl Endpoint {
     /// Sends a messages with `tag`.
+    #[instrument(
+        level = "trace",
+        skip_all,
+        fields(endpoint = ?self.handle, tag = tag, len = buf.len(), request = next_request_id()),
+        ret,
+        err(level = "debug")
+    )]
     pub async fn tag_send(&self, tag: u64, buf: &[u8]) -> Result<usize, Error> {
-        trace!("tag_send: endpoint={:?} len={}", self.handle, buf.len());
+        let _permit = self.send_permit().await;
+        let iov = [IoSlice::new(buf)];
+        tag_send(self.worker(), self.get_handle()?, tag, &iov).await
+    }
+
+    /// Like `tag_send`, except that it completes only once the peer matched
+    /// the message with a receive.
+    ///
+    /// This costs at least a round trip to the peer, and waits for as long
+    /// as the peer doesn't post a matching receive.
+    pub async fn tag_send_sync(&self, tag: u64, buf: &[u8]) -> Result<usize, Error> {
+        trace!(
+            "tag_send_sync: endpoint={:?} len={}",
+            self.handle,
+            buf.len()
+        );
+        let _permit = self.send_permit().await;
         unsafe extern "C" fn callback(request: *mut c_void, status: ucs_status_t) {
-            trace!("tag_send: complete. req={:?}, status={:?}", request, status);
+            trace!(
+                "tag_send_sync: complete. req={:?}, status={:?}",
+                request,
+                status
+            );
             let request = &mut *(request as *mut Request);
             request.waker.wake();
         }
         let status = unsafe {
-            ucp_tag_send_nb(
+            ucp_tag_send_sync_nb(
                 self.get_handle()?,
                 buf.as_ptr() as _,
                 buf.len() as _,
@@ -125,62 +492,129 @@ impl Endpoint {
             )
         };
         if status.is_null() {
-            trace!("tag_send: complete");
+            trace!("tag_send_sync: complete");
         } else if UCS_PTR_IS_PTR(status) {
-            RequestHandle {
-                ptr: status,
-                poll_fn: poll_normal,
-            }
-            .await?;
+            RequestHandle::new(self.worker(), status, poll_normal).await?;
         } else {
             return Err(Error::from_ptr(status).unwrap_err());
         }
         Ok(buf.len())
     }
 
-    /// Like `tag_send`, except that it reads into a slice of buffers.
-    pub async fn tag_send_vectored(&self, tag: u64, iov: &[IoSlice<'_>]) -> Result<usize, Error> {
+    /// Like `tag_send`, except that the request is allocated from `pool`.
+    ///
+    /// Fails with [`Error::NoReource`] if the pool is exhausted.
+    pub async fn tag_send_pooled(
+        &self,
+        pool: &RequestPool,
+        tag: u64,
+        buf: &[u8],
+    ) -> Result<usize, Error> {
         trace!(
-            "tag_send_vectored: endpoint={:?} iov.len={}",
+            "tag_send_pooled: endpoint={:?} len={}",
             self.handle,
-            iov.len()
+            buf.len()
         );
-        unsafe extern "C" fn callback(request: *mut c_void, status: ucs_status_t) {
+        unsafe extern "C" fn callback(
+            request: *mut c_void,
+            status: ucs_status_t,
+            _user_data: *mut c_void,
+        ) {
             trace!(
-                "tag_send_vectored: complete. req={:?}, status={:?}",
+                "tag_send_pooled: complete. req={:?}, status={:?}",
                 request,
                 status
             );
             let request = &mut *(request as *mut Request);
             request.waker.wake();
         }
+        let handle = self.get_handle()?;
+        let _permit = self.send_permit().await;
+        let slot = pool.alloc()?;
+        let mut param = MaybeUninit::<ucp_request_param_t>::uninit();
+        unsafe {
+            let param = &mut *param.as_mut_ptr();
+            param.op_attr_mask = ucp_op_attr_t::UCP_OP_ATTR_FIELD_CALLBACK as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_DATATYPE as u32
+                | ucp_op_attr_t::UCP_OP_ATTR_FIELD_REQUEST as u32;
+            param.cb = ucp_request_param_t__bindgen_ty_1 {
+                send: Some(callback),
+            };
+            param.datatype = ucp_dt_make_contig(1);
+            param.request = slot.as_ptr();
+        }
         let status = unsafe {
-            ucp_tag_send_nb(
-                self.get_handle()?,
-                iov.as_ptr() as _,
-                iov.len() as _,
-                ucp_dt_type::UCP_DATATYPE_IOV as _,
+            ucp_tag_send_nbx(
+                handle,
+                buf.as_ptr() as _,
+                buf.len() as _,
                 tag,
-                Some(callback),
+                param.as_ptr(),
             )
         };
-        let total_len = iov.iter().map(|v| v.len()).sum();
         if status.is_null() {
-            trace!("tag_send_vectored: complete");
+            trace!("tag_send_pooled: complete");
         } else if UCS_PTR_IS_PTR(status) {
-            RequestHandle {
-                ptr: status,
-                poll_fn: poll_normal,
-            }
-            .await?;
+            PooledRequestHandle::new(self.worker(), slot, poll_normal).await?;
         } else {
             return Err(Error::from_ptr(status).unwrap_err());
         }
-        Ok(total_len)
+        Ok(buf.len())
+    }
+
+    /// Like `tag_send`, except that it reads into a slice of buffers.
+    pub async fn tag_send_vectored(&self, tag: u64, iov: &[IoSlice<'_>]) -> Result<usize, Error> {
+        trace!(
+            "tag_send_vectored: endpoint={:?} iov.len={}",
+            self.handle,
+            iov.len()
+        );
+        let _permit = self.send_permit().await;
+        tag_send(self.worker(), self.get_handle()?, tag, iov).await
     }
 }
 
-unsafe fn poll_tag(ptr: ucs_status_ptr_t) -> Poll<Result<(u64, usize), Error>> {
+// Send `iov` with `tag` on a raw endpoint, shared with `EndpointRef`.
+// A single buffer is sent as contiguous data.
+pub(super) async fn tag_send(
+    worker: &Worker,
+    endpoint: ucp_ep_h,
+    tag: u64,
+    iov: &[IoSlice<'_>],
+) -> Result<usize, Error> {
+    unsafe extern "C" fn callback(request: *mut c_void, status: ucs_status_t) {
+        trace!("tag_send: complete. req={:?}, status={:?}", request, status);
+        let request = &mut *(request as *mut Request);
+        request.waker.wake();
+    }
+    let (buffer, count, datatype) = if iov.len() == 1 {
+        (iov[0].as_ptr(), iov[0].len(), ucp_dt_make_contig(1))
+    } else {
+        let datatype = ucp_dt_type::UCP_DATATYPE_IOV as _;
+        (iov.as_ptr() as _, iov.len(), datatype)
+    };
+    let status = unsafe {
+        ucp_tag_send_nb(
+            endpoint,
+            buffer as _,
+            count as _,
+            datatype,
+            tag,
+            Some(callback),
+        )
+    };
+    let total_len = iov.iter().map(|v| v.len()).sum();
+    if status.is_null() {
+        trace!("tag_send: complete");
+    } else if UCS_PTR_IS_PTR(status) {
+        RequestHandle::new(worker, status, poll_normal).await?;
+    } else {
+        return Err(Error::from_ptr(status).unwrap_err());
+    }
+    Ok(total_len)
+}
+
+pub(super) unsafe fn poll_tag(ptr: ucs_status_ptr_t) -> Poll<Result<(u64, usize), Error>> {
     let mut info = MaybeUninit::<ucp_tag_recv_info>::uninit();
     let status = ucp_tag_recv_request_test(ptr as _, info.as_mut_ptr() as _);
     match status {
@@ -193,6 +627,32 @@ unsafe fn poll_tag(ptr: ucs_status_ptr_t) -> Poll<Result<(u64, usize), Error>> {
     }
 }
 
+// The buffer of an in-flight `tag_recv_alloc`.
+// UCX writes into it until the receive completes, so it is leaked if the
+// receive is dropped in flight, like the slot of `PooledRequestHandle`.
+struct AllocRecv {
+    request: RequestHandle<Result<(u64, usize), Error>>,
+    buf: Option<Vec<u8>>,
+}
+
+impl Drop for AllocRecv {
+    fn drop(&mut self) {
+        let buf = match self.buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        // checked before `request` is freed
+        let status = unsafe { ucp_request_check_status(self.request.ptr) };
+        if status == ucs_status_t::UCS_INPROGRESS {
+            warn!(
+                "tag_recv_alloc dropped in flight, {} bytes leaked",
+                buf.capacity()
+            );
+            std::mem::forget(buf);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -244,13 +704,110 @@ mod tests {
             }
         );
 
-        assert_eq!(endpoint1.get_rc(), (1, 1));
-        assert_eq!(endpoint2.get_rc(), (1, 1));
+        assert_eq!(endpoint1.get_rc(), (1, 2));
+        assert_eq!(endpoint2.get_rc(), (1, 2));
         assert_eq!(endpoint1.close(false).await, Ok(()));
         assert_eq!(endpoint2.close(false).await, Err(Error::ConnectionReset));
         assert_eq!(endpoint1.get_rc(), (1, 0));
-        assert_eq!(endpoint2.get_rc(), (1, 1));
+        assert_eq!(endpoint2.get_rc(), (1, 2));
         assert_eq!(endpoint2.close(true).await, Ok(()));
         assert_eq!(endpoint2.get_rc(), (1, 0));
     }
+
+    #[test_log::test]
+    fn tag_mem() {
+        spawn_thread!(_tag_mem()).join().unwrap();
+    }
+
+    async fn _tag_mem() {
+        const MSG_SIZE: usize = 4 << 20;
+        const ROUNDS: usize = 16;
+
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let msg: Vec<u8> = (0..MSG_SIZE).map(|x| x as u8).collect();
+        let (endpoint2, msg) = (&endpoint2, &msg);
+        let send = || async move {
+            for _ in 0..ROUNDS {
+                endpoint2.tag_send(1, msg).await.unwrap();
+            }
+        };
+
+        let mut region = vec![0_u8; MSG_SIZE + 8];
+        let mem = MemoryHandle::register(&worker1.context, &mut region);
+        // `region` is only accessed through `mem` until it is dropped
+        let result = unsafe { worker1.tag_recv_mem(1, !0, &mem, 16, MSG_SIZE).await };
+        assert_eq!(result, Err(Error::OutOfRange));
+        tokio::join!(send(), async {
+            for _ in 0..ROUNDS {
+                let info = unsafe { worker1.tag_recv_mem(1, !0, &mem, 8, MSG_SIZE).await };
+                assert_eq!(info, Ok((1, MSG_SIZE)));
+            }
+        });
+        drop(mem);
+        assert_eq!(&region[8..], &msg[..]);
+    }
+
+    #[test_log::test]
+    fn tag_recv_alloc() {
+        spawn_thread!(_tag_recv_alloc()).join().unwrap();
+    }
+
+    async fn _tag_recv_alloc() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        // the receive starts first and waits for the message
+        let sizes = [0, 1, 100, 8 << 10, 1 << 20];
+        tokio::join!(
+            async {
+                for (i, &size) in sizes.iter().enumerate() {
+                    let (data, tag) = worker1.tag_recv_alloc(0x100, !0xff).await.unwrap();
+                    assert_eq!(tag, 0x100 | i as u64);
+                    assert_eq!(data.len(), size);
+                    assert_eq!(data.capacity(), size);
+                    assert!(data.iter().all(|&x| x == i as u8));
+                }
+            },
+            async {
+                tokio::task::yield_now().await;
+                for (i, &size) in sizes.iter().enumerate() {
+                    let data = vec![i as u8; size];
+                    endpoint2.tag_send(0x100 | i as u64, &data).await.unwrap();
+                }
+            }
+        );
+    }
+
+    #[test_log::test]
+    fn tag_send_sync() {
+        spawn_thread!(_tag_send_sync()).join().unwrap();
+    }
+
+    async fn _tag_send_sync() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        // the receive is posted late
+        let delay = std::time::Duration::from_millis(200);
+        for sync in [false, true] {
+            let start = std::time::Instant::now();
+            let (sent, received) = tokio::join!(
+                async {
+                    let result = if sync {
+                        endpoint2.tag_send_sync(1, &[1, 2, 3]).await
+                    } else {
+                        endpoint2.tag_send(1, &[1, 2, 3]).await
+                    };
+                    (result, start.elapsed())
+                },
+                async {
+                    tokio::time::sleep(delay).await;
+                    let mut buf = [MaybeUninit::uninit(); 3];
+                    worker1.tag_recv(1, &mut buf).await
+                }
+            );
+            assert_eq!(sent.0, Ok(3));
+            assert_eq!(received, Ok(3));
+            assert_eq!(sent.1 >= delay, sync);
+        }
+    }
 }
@@ -0,0 +1,342 @@
+//! Passing file descriptors along with active messages between processes on one node.
+//!
+//! UCX can't carry file descriptors, so they travel through a unix datagram
+//! socket with `SCM_RIGHTS`, keyed by a token prefixed to the message header.
+
+use super::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+/// Length of the token prefixed to the header of a message carrying a descriptor.
+pub const FD_TOKEN_LEN: usize = std::mem::size_of::<u64>();
+
+/// Prefix `header` with the token of a descriptor.
+pub fn encode_fd_header(token: u64, header: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(FD_TOKEN_LEN + header.len());
+    buf.extend_from_slice(&token.to_le_bytes());
+    buf.extend_from_slice(header);
+    buf
+}
+
+/// Split a header made by [`encode_fd_header`] into the token and the rest.
+pub fn decode_fd_header(header: &[u8]) -> Option<(u64, &[u8])> {
+    if header.len() < FD_TOKEN_LEN {
+        return None;
+    }
+    let (token, rest) = header.split_at(FD_TOKEN_LEN);
+    Some((u64::from_le_bytes(token.try_into().unwrap()), rest))
+}
+
+// unique among the processes of a node
+fn next_token() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let seq = NEXT.fetch_add(1, Ordering::Relaxed) & 0xffff_ffff;
+    ((std::process::id() as u64) << 32) | seq
+}
+
+/// Node-local side channel passing file descriptors with `SCM_RIGHTS`.
+///
+/// Each receiving process binds a channel at a path known to its peers,
+/// senders may use an unbound one.
+#[derive(Debug)]
+pub struct FdChannel {
+    socket: UnixDatagram,
+    // descriptors that arrived while waiting for another token
+    received: RefCell<HashMap<u64, OwnedFd>>,
+}
+
+impl FdChannel {
+    /// Create a channel receiving descriptors sent to `path`.
+    pub fn bind(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(UnixDatagram::bind(path)?))
+    }
+
+    /// Create a channel that only sends descriptors.
+    pub fn unbound() -> io::Result<Self> {
+        Ok(Self::new(UnixDatagram::unbound()?))
+    }
+
+    fn new(socket: UnixDatagram) -> Self {
+        FdChannel {
+            socket,
+            received: RefCell::default(),
+        }
+    }
+
+    /// Send a duplicate of `fd` with `token` to the channel bound at `path`.
+    pub fn send_to(
+        &self,
+        path: impl AsRef<Path>,
+        token: u64,
+        fd: BorrowedFd<'_>,
+    ) -> io::Result<()> {
+        let path = path.as_ref().as_os_str().as_bytes();
+        let mut addr: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+        if path.len() >= addr.sun_path.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "path too long"));
+        }
+        addr.sun_family = libc::AF_UNIX as _;
+        for (dst, src) in addr.sun_path.iter_mut().zip(path) {
+            *dst = *src as _;
+        }
+        let addr_len = std::mem::size_of::<libc::sa_family_t>() + path.len() + 1;
+
+        let mut token = token.to_le_bytes();
+        let mut iov = libc::iovec {
+            iov_base: token.as_mut_ptr() as _,
+            iov_len: token.len(),
+        };
+        // u64 keeps the control buffer aligned for `cmsghdr`
+        let mut control = [0_u64; 8];
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_name = &mut addr as *mut _ as _;
+        msg.msg_namelen = addr_len as _;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as _;
+        msg.msg_controllen = unsafe { libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as _) } as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as _) as _;
+            (libc::CMSG_DATA(cmsg) as *mut RawFd).write_unaligned(fd.as_raw_fd());
+        }
+        let ret = unsafe { libc::sendmsg(self.socket.as_raw_fd(), &msg, 0) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Take the descriptor sent with `token`.
+    ///
+    /// It doesn't block, failing with [`io::ErrorKind::WouldBlock`] if the
+    /// descriptor hasn't arrived. Descriptors with other tokens received
+    /// meanwhile are kept for later calls.
+    pub fn recv(&self, token: u64) -> io::Result<OwnedFd> {
+        if let Some(fd) = self.received.borrow_mut().remove(&token) {
+            return Ok(fd);
+        }
+        loop {
+            let (received, fd) = self.recv_one()?;
+            if received == token {
+                return Ok(fd);
+            }
+            self.received.borrow_mut().insert(received, fd);
+        }
+    }
+
+    fn recv_one(&self) -> io::Result<(u64, OwnedFd)> {
+        let mut token = [0_u8; FD_TOKEN_LEN];
+        let mut iov = libc::iovec {
+            iov_base: token.as_mut_ptr() as _,
+            iov_len: token.len(),
+        };
+        let mut control = [0_u64; 8];
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as _;
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+        let flags = libc::MSG_DONTWAIT | libc::MSG_CMSG_CLOEXEC;
+        let ret = unsafe { libc::recvmsg(self.socket.as_raw_fd(), &mut msg, flags) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        if ret as usize != FD_TOKEN_LEN
+            || cmsg.is_null()
+            || unsafe {
+                (*cmsg).cmsg_level != libc::SOL_SOCKET || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+            }
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a descriptor message",
+            ));
+        }
+        let fd = unsafe { (libc::CMSG_DATA(cmsg) as *const RawFd).read_unaligned() };
+        Ok((u64::from_le_bytes(token), unsafe {
+            OwnedFd::from_raw_fd(fd)
+        }))
+    }
+}
+
+/// Memory shared between processes, backed by a memfd.
+#[derive(Debug)]
+pub struct SharedMemory {
+    fd: OwnedFd,
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl SharedMemory {
+    /// Create `len` bytes of zeroed shared memory, `name` shows up in `/proc`.
+    pub fn create(name: &str, len: usize) -> io::Result<Self> {
+        let name =
+            CString::new(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), len as _) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Self::map(fd, len)
+    }
+
+    /// Map the shared memory received as `fd`.
+    pub fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        let mut stat = MaybeUninit::<libc::stat>::uninit();
+        if unsafe { libc::fstat(fd.as_raw_fd(), stat.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let len = unsafe { stat.assume_init() }.st_size as usize;
+        Self::map(fd, len)
+    }
+
+    fn map(fd: OwnedFd, len: usize) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(SharedMemory {
+            fd,
+            ptr: ptr as _,
+            len,
+        })
+    }
+}
+
+impl AsFd for SharedMemory {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl Deref for SharedMemory {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for SharedMemory {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as _, self.len) };
+    }
+}
+
+impl Endpoint {
+    /// Send an active message carrying `fd`, for a peer on the same node.
+    ///
+    /// `fd` is sent through `channel` to the channel the peer bound at `peer`
+    /// before the message, whose header is prefixed with the token of `fd`.
+    /// The receiver takes it with [`AmMsg::recv_fd`].
+    pub async fn am_send_fd(
+        &self,
+        id: u32,
+        channel: &FdChannel,
+        peer: impl AsRef<Path>,
+        fd: BorrowedFd<'_>,
+        header: &[u8],
+        data: &[u8],
+    ) -> Result<usize, Error> {
+        let token = next_token();
+        channel.send_to(peer, token, fd).map_err(|err| {
+            warn!("am_send_fd: {}", err);
+            Error::IoError
+        })?;
+        trace!("am_send_fd: endpoint={:?}, token={:#x}", self.handle, token);
+        self.am_send(id, &encode_fd_header(token, header), data)
+            .await
+    }
+}
+
+impl AmMsg<'_> {
+    /// Take the descriptor sent with [`Endpoint::am_send_fd`] from `channel`.
+    ///
+    /// The header after the token is `decode_fd_header(msg.header())`.
+    /// Fails with [`Error::InvalidParam`] if the header has no token and with
+    /// [`Error::NoMessage`] if the descriptor hasn't arrived.
+    pub fn recv_fd(&self, channel: &FdChannel) -> Result<OwnedFd, Error> {
+        let (token, _) = decode_fd_header(self.header()).ok_or(Error::InvalidParam)?;
+        channel.recv(token).map_err(|err| match err.kind() {
+            io::ErrorKind::WouldBlock => Error::NoMessage,
+            _ => {
+                warn!("recv_fd: {}", err);
+                Error::IoError
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn fd_header() {
+        let header = encode_fd_header(0x1234, &[1, 2, 3]);
+        assert_eq!(decode_fd_header(&header), Some((0x1234, &[1, 2, 3][..])));
+        assert_eq!(decode_fd_header(&[0; FD_TOKEN_LEN - 1]), None);
+    }
+
+    #[test_log::test]
+    fn am_send_fd() {
+        spawn_thread!(_am_send_fd()).join().unwrap();
+    }
+
+    async fn _am_send_fd() {
+        const LEN: usize = 4096;
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let path = std::env::temp_dir().join(format!("async-ucx-fd-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let channel1 = FdChannel::bind(&path).unwrap();
+        let channel2 = FdChannel::unbound().unwrap();
+
+        let mut shm2 = SharedMemory::create("am_send_fd", LEN).unwrap();
+        shm2.fill(7);
+        let stream1 = worker1.am_stream(16).unwrap();
+        let (sent, msg) = tokio::join!(
+            endpoint2.am_send_fd(16, &channel2, &path, shm2.as_fd(), &[1, 2], &[3]),
+            async { stream1.wait_msg().await.expect("no msg") }
+        );
+        assert_eq!(sent, Ok(1));
+        assert_eq!(decode_fd_header(msg.header()).unwrap().1, &[1, 2]);
+        assert_eq!(msg.get_data(), Some(&[3][..]));
+
+        // the receiver maps the same memory
+        let mut shm1 = SharedMemory::from_fd(msg.recv_fd(&channel1).unwrap()).unwrap();
+        assert_eq!(shm1.len(), LEN);
+        assert!(shm1.iter().all(|&x| x == 7));
+        shm1[0] = 42;
+        assert_eq!(shm2[0], 42);
+        assert_eq!(msg.recv_fd(&channel1).err(), Some(Error::NoMessage));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -2,26 +2,83 @@ use crate::Error;
 
 use super::*;
 use futures::channel::mpsc;
-use futures::stream::StreamExt;
+use futures::stream::{Stream, StreamExt};
+use std::cell::Cell;
 use std::mem::MaybeUninit;
 use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
 
 /// Listening on a specific address and accepting connections from clients.
+///
+/// Incoming connection requests are buffered until taken by [`Listener::next`],
+/// or through the [`Stream`] implementation, which ends with the listener.
 #[derive(Debug)]
 pub struct Listener {
     handle: ucp_listener_h,
-    #[allow(unused)]
-    sender: Rc<mpsc::UnboundedSender<ConnectionRequest>>,
+    state: Rc<ListenerState>,
     recver: mpsc::UnboundedReceiver<ConnectionRequest>,
 }
 
+// State shared with the connection handler
+#[derive(Debug)]
+struct ListenerState {
+    sender: mpsc::UnboundedSender<ConnectionRequest>,
+    handle: Cell<ucp_listener_h>,
+    // number of requests buffered in the channel
+    pending: Cell<usize>,
+    backlog: usize,
+}
+
+/// Builder of a [`Listener`], created by [`Worker::listener_builder`].
+#[derive(Debug)]
+pub struct ListenerBuilder<'a> {
+    worker: &'a Rc<Worker>,
+    addr: SocketAddr,
+    backlog: usize,
+}
+
+impl<'a> ListenerBuilder<'a> {
+    pub(super) fn new(worker: &'a Rc<Worker>) -> Self {
+        ListenerBuilder {
+            worker,
+            addr: "0.0.0.0:0".parse().unwrap(),
+            backlog: usize::MAX,
+        }
+    }
+
+    /// Address to listen on, `0.0.0.0:0` by default.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.addr = addr;
+        self
+    }
+
+    /// Maximal number of connection requests waiting to be taken, unlimited by default.
+    ///
+    /// Further requests are rejected.
+    pub fn backlog(mut self, backlog: usize) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    /// Create the `Listener`.
+    ///
+    /// Fails with [`Error::Busy`] if the address is in use, or another error
+    /// if UCX can't listen on it, e.g. a privileged port.
+    pub fn build(self) -> Result<Listener, Error> {
+        Listener::new(self.worker, self.addr, self.backlog)
+    }
+}
+
 /// An incoming connection request.
 ///
 /// The request must be explicitly accepted by [Worker::accept] or rejected by [Listener::reject].
+/// Use [ConnectionRequest::remote_addr] to decide before accepting.
 #[derive(Debug)]
 #[must_use = "connection must be accepted or rejected"]
 pub struct ConnectionRequest {
     pub(super) handle: ucp_conn_request_h,
+    listener: ucp_listener_h,
 }
 
 // connection can be send to other thread and accepted on its worker
@@ -40,24 +97,58 @@ impl ConnectionRequest {
         let status = unsafe { ucp_conn_request_query(self.handle, &mut attr) };
         Error::from_status(status)?;
 
+        let len = std::mem::size_of_val(&attr.client_address) as _;
         let sockaddr =
-            unsafe { socket2::SockAddr::new(std::mem::transmute(attr.client_address), 8) };
-        Ok(sockaddr.as_socket().unwrap())
+            unsafe { socket2::SockAddr::new(std::mem::transmute(attr.client_address), len) };
+        sockaddr.as_socket().ok_or(Error::InvalidAddr)
+    }
+
+    /// Reject the connection, like [`Listener::reject`].
+    ///
+    /// # Safety
+    /// The listener that received the request must still be alive.
+    pub unsafe fn reject(self) -> Result<(), Error> {
+        let status = ucp_listener_reject(self.listener, self.handle);
+        Error::from_status(status)
     }
 }
 
 impl Listener {
-    pub(super) fn new(worker: &Rc<Worker>, addr: SocketAddr) -> Result<Self, Error> {
+    pub(super) fn new(
+        worker: &Rc<Worker>,
+        addr: SocketAddr,
+        backlog: usize,
+    ) -> Result<Self, Error> {
         unsafe extern "C" fn connect_handler(conn_request: ucp_conn_request_h, arg: *mut c_void) {
             trace!("connect request={:?}", conn_request);
-            let sender = &*(arg as *const mpsc::UnboundedSender<ConnectionRequest>);
+            let state = &*(arg as *const ListenerState);
+            if state.pending.get() >= state.backlog {
+                warn!("backlog is full, reject request={:?}", conn_request);
+                let status = ucp_listener_reject(state.handle.get(), conn_request);
+                let _ = Error::from_status(status)
+                    .map_err(|err| error!("Failed to reject connection, {}", err));
+                return;
+            }
             let connection = ConnectionRequest {
                 handle: conn_request,
+                listener: state.handle.get(),
             };
-            sender.unbounded_send(connection).unwrap();
+            if let Err(err) = state.sender.unbounded_send(connection) {
+                warn!("listener is gone, reject request={:?}", conn_request);
+                let status = ucp_listener_reject(state.handle.get(), err.into_inner().handle);
+                let _ = Error::from_status(status)
+                    .map_err(|err| error!("Failed to reject connection, {}", err));
+                return;
+            }
+            state.pending.set(state.pending.get() + 1);
         }
         let (sender, recver) = mpsc::unbounded();
-        let sender = Rc::new(sender);
+        let state = Rc::new(ListenerState {
+            sender,
+            handle: Cell::new(null_mut()),
+            pending: Cell::new(0),
+            backlog,
+        });
         let sockaddr = socket2::SockAddr::from(addr);
         let params = ucp_listener_params_t {
             field_mask: (ucp_listener_params_field::UCP_LISTENER_PARAM_FIELD_SOCK_ADDR
@@ -73,16 +164,18 @@ impl Listener {
             },
             conn_handler: ucp_listener_conn_handler_t {
                 cb: Some(connect_handler),
-                arg: &*sender as *const mpsc::UnboundedSender<ConnectionRequest> as _,
+                arg: Rc::as_ptr(&state) as _,
             },
         };
         let mut handle = MaybeUninit::uninit();
         let status = unsafe { ucp_listener_create(worker.handle, &params, handle.as_mut_ptr()) };
         Error::from_status(status)?;
+        let handle = unsafe { handle.assume_init() };
         trace!("create listener={:?}", handle);
+        state.handle.set(handle);
         Ok(Listener {
-            handle: unsafe { handle.assume_init() },
-            sender,
+            handle,
+            state,
             recver,
         })
     }
@@ -97,14 +190,17 @@ impl Listener {
         };
         let status = unsafe { ucp_listener_query(self.handle, &mut attr) };
         Error::from_status(status)?;
-        let sockaddr = unsafe { socket2::SockAddr::new(std::mem::transmute(attr.sockaddr), 8) };
+        let len = std::mem::size_of_val(&attr.sockaddr) as _;
+        let sockaddr = unsafe { socket2::SockAddr::new(std::mem::transmute(attr.sockaddr), len) };
 
-        Ok(sockaddr.as_socket().unwrap())
+        sockaddr.as_socket().ok_or(Error::InvalidAddr)
     }
 
     /// Waiting for the next connection request.
+    ///
+    /// This shadows [`StreamExt::next`], use [`StreamExt::by_ref`] to get the stream.
     pub async fn next(&mut self) -> ConnectionRequest {
-        self.recver.next().await.unwrap()
+        StreamExt::next(self).await.unwrap()
     }
 
     /// Reject a connection.
@@ -114,6 +210,18 @@ impl Listener {
     }
 }
 
+impl Stream for Listener {
+    type Item = ConnectionRequest;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.recver.poll_next_unpin(cx);
+        if let Poll::Ready(Some(_)) = poll {
+            self.state.pending.set(self.state.pending.get() - 1);
+        }
+        poll
+    }
+}
+
 impl Drop for Listener {
     fn drop(&mut self) {
         trace!("destroy listener={:?}", self.handle);
@@ -151,4 +259,103 @@ mod tests {
         });
         f1.join().unwrap();
     }
+
+    #[test_log::test]
+    fn reject() {
+        let (sender, recver) = tokio::sync::oneshot::channel();
+        let f1 = spawn_thread!(async move {
+            let context = Context::new().unwrap();
+            let worker = context.create_worker().unwrap();
+            tokio::task::spawn_local(worker.clone().polling());
+            let mut listener = worker
+                .create_listener("0.0.0.0:0".parse().unwrap())
+                .unwrap();
+            let listen_port = listener.socket_addr().unwrap().port();
+            sender.send(listen_port).unwrap();
+
+            // reject the first client, accept the second one
+            let conn = listener.next().await;
+            let client = conn.remote_addr().unwrap();
+            assert!(client.ip().is_loopback());
+            listener.reject(conn).unwrap();
+            let conn = listener.next().await;
+            let _endpoint = worker.accept(conn).await.unwrap();
+        });
+        spawn_thread!(async move {
+            let context = Context::new().unwrap();
+            let worker = context.create_worker().unwrap();
+            tokio::task::spawn_local(worker.clone().polling());
+            let mut addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+            let listen_port = recver.await.unwrap();
+            addr.set_port(listen_port);
+            assert!(worker.connect_socket(addr).await.is_err());
+            let _endpoint = worker.connect_socket(addr).await.unwrap();
+        });
+        f1.join().unwrap();
+    }
+
+    #[test_log::test]
+    fn stream() {
+        let (sender, recver) = tokio::sync::oneshot::channel();
+        let f1 = spawn_thread!(async move {
+            let context = Context::new().unwrap();
+            let worker = context.create_worker().unwrap();
+            tokio::task::spawn_local(worker.clone().polling());
+            let mut listener = worker
+                .create_listener("0.0.0.0:0".parse().unwrap())
+                .unwrap();
+            let listen_port = listener.socket_addr().unwrap().port();
+            sender.send(listen_port).unwrap();
+
+            let mut endpoints = Vec::new();
+            let mut conns = listener.by_ref().take(3);
+            while let Some(conn) = conns.next().await {
+                endpoints.push(worker.accept(conn).await.unwrap());
+            }
+            assert_eq!(endpoints.len(), 3);
+        });
+        spawn_thread!(async move {
+            let context = Context::new().unwrap();
+            let worker = context.create_worker().unwrap();
+            tokio::task::spawn_local(worker.clone().polling());
+            let mut addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+            let listen_port = recver.await.unwrap();
+            addr.set_port(listen_port);
+            let mut endpoints = Vec::new();
+            for _ in 0..3 {
+                endpoints.push(worker.connect_socket(addr).await.unwrap());
+            }
+        });
+        f1.join().unwrap();
+    }
+
+    #[test_log::test]
+    fn builder() {
+        spawn_thread!(async {
+            let context = Context::new().unwrap();
+            let worker = context.create_worker().unwrap();
+            let listener = worker.listener_builder().backlog(16).build().unwrap();
+            let addr = listener.socket_addr().unwrap();
+
+            // the port is taken by the first listener
+            let mut bind_addr: SocketAddr = "0.0.0.0:0".parse().unwrap();
+            bind_addr.set_port(addr.port());
+            let result = worker.listener_builder().bind(bind_addr).build();
+            assert_eq!(result.unwrap_err(), Error::Busy);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test_log::test]
+    fn privileged_port() {
+        // root may bind privileged ports
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let context = Context::new().unwrap();
+        let worker = context.create_worker().unwrap();
+        let result = worker.create_listener("0.0.0.0:1".parse().unwrap());
+        assert!(result.is_err(), "{:?}", result);
+    }
 }
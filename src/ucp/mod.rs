@@ -2,22 +2,35 @@
 
 use futures::task::AtomicWaker;
 use std::ffi::CString;
+use std::io::Write;
 use std::mem::MaybeUninit;
-use std::os::raw::c_void;
+use std::os::raw::{c_char, c_int, c_void};
 use std::ptr::{null, null_mut};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use ucx1_sys::*;
 
 mod endpoint;
+#[cfg(feature = "fd")]
+mod fd;
 mod listener;
+#[cfg(feature = "am")]
+mod rpc;
 mod worker;
+mod worker_pool;
 
 use crate::Error;
 
 pub use self::endpoint::*;
+#[cfg(feature = "fd")]
+pub use self::fd::*;
 pub use self::listener::*;
+#[cfg(feature = "am")]
+pub use self::rpc::*;
 pub use self::worker::*;
+pub use self::worker_pool::*;
 
 /// The configuration for UCP application context.
 #[derive(Debug)]
@@ -26,29 +39,100 @@ pub struct Config {
 }
 
 impl Default for Config {
+    /// Like `Config::new`.
+    ///
+    /// # Panics
+    /// Panics if the configuration from the environment is invalid.
     fn default() -> Self {
+        Self::new().expect("Failed to read UCP configuration")
+    }
+}
+
+impl Config {
+    /// Reads the UCP configuration from the environment.
+    ///
+    /// Fails if a `UCX_*` environment variable holds an invalid value.
+    pub fn new() -> Result<Self, Error> {
         let mut handle = MaybeUninit::uninit();
         let status = unsafe { ucp_config_read(null(), null(), handle.as_mut_ptr()) };
-        Error::from_status(status).unwrap();
+        Error::from_status(status)?;
 
-        Config {
+        Ok(Config {
             handle: unsafe { handle.assume_init() },
-        }
+        })
+    }
+
+    /// Sets the configuration `name` to `value`, overriding the environment.
+    ///
+    /// `name` is given without the `UCX_` prefix, e.g. `RNDV_THRESH`.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        let name = CString::new(name).map_err(|_| Error::InvalidParam)?;
+        let value = CString::new(value).map_err(|_| Error::InvalidParam)?;
+        let status = unsafe { ucp_config_modify(self.handle, name.as_ptr(), value.as_ptr()) };
+        Error::from_status(status)
+    }
+
+    /// Sets the message size from which the rendezvous protocol is used.
+    ///
+    /// Active messages can still force a protocol per send
+    /// with [`AmSendFlags::EAGER`] or [`AmSendFlags::RNDV`].
+    pub fn set_rndv_thresh(&mut self, bytes: usize) -> Result<(), Error> {
+        self.set("RNDV_THRESH", &bytes.to_string())
+    }
+
+    /// Sets the interval of keepalive probes on idle endpoints.
+    ///
+    /// A peer that silently died is detected within a few intervals,
+    /// after which [`Endpoint::is_alive`] returns `false`.
+    pub fn set_keepalive_interval(&mut self, interval: Duration) -> Result<(), Error> {
+        self.set("KEEPALIVE_INTERVAL", &format!("{}us", interval.as_micros()))
+    }
+
+    /// Restricts network traffic to `devices`, e.g. `["mlx5_0:1"]`.
+    ///
+    /// UCX selects devices per context, so workers bound to different
+    /// devices need contexts created from different configs.
+    pub fn set_net_devices(&mut self, devices: &[&str]) -> Result<(), Error> {
+        self.set("NET_DEVICES", &devices.join(","))
+    }
+
+    /// Restricts transports to shared memory and loopback (`UCX_TLS=shm,self`).
+    ///
+    /// Only for deployments where all peers are on the same node, as
+    /// connections to other nodes fail. Connect with worker addresses then,
+    /// since connecting to a socket address needs a network transport.
+    pub fn set_shared_memory_only(&mut self) -> Result<(), Error> {
+        self.set("TLS", "shm,self")
     }
-}
 
-impl Config {
     /// Prints information about the context configuration.
     ///
     /// Including memory domains, transport resources, and other useful
     /// information associated with the context.
     pub fn print_to_stderr(&self) {
+        unsafe { self.print_to_file(stderr) };
+    }
+
+    /// Like `print_to_stderr`, except that it writes to `out`.
+    pub fn print(&self, out: &mut impl Write) -> std::io::Result<()> {
+        print_to_writer(out, |file| unsafe { self.print_to_file(file) })
+    }
+
+    unsafe fn print_to_file(&self, file: *mut FILE) {
         let flags = ucs_config_print_flags_t::UCS_CONFIG_PRINT_CONFIG
             | ucs_config_print_flags_t::UCS_CONFIG_PRINT_DOC
             | ucs_config_print_flags_t::UCS_CONFIG_PRINT_HEADER
             | ucs_config_print_flags_t::UCS_CONFIG_PRINT_HIDDEN;
         let title = CString::new("UCP Configuration").expect("Not a valid CStr");
-        unsafe { ucp_config_print(self.handle, stderr, title.as_ptr(), flags) };
+        ucp_config_print(self.handle, file, title.as_ptr(), flags);
+    }
+}
+
+impl std::fmt::Display for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut buf = Vec::new();
+        self.print(&mut buf).map_err(|_| std::fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&buf))
     }
 }
 
@@ -62,6 +146,7 @@ impl Drop for Config {
 #[derive(Debug)]
 pub struct Context {
     handle: ucp_context_h,
+    owned: bool,
 }
 
 // Context is thread safe.
@@ -70,8 +155,11 @@ unsafe impl Sync for Context {}
 
 impl Context {
     /// Creates and initializes a UCP application context with default configuration.
+    ///
+    /// Fails instead of panicking if the configuration is invalid
+    /// or no transport is available.
     pub fn new() -> Result<Arc<Self>, Error> {
-        Self::new_with_config(&Config::default())
+        Self::new_with_config(&Config::new()?)
     }
 
     /// Creates and initializes a UCP application context with specified configuration.
@@ -94,6 +182,7 @@ impl Context {
                 | ucp_params_field::UCP_PARAM_FIELD_MT_WORKERS_SHARED)
                 .0 as u64,
             features: features.0 as u64,
+            // every request UCX allocates carries a `Request` initialized by `request_init`
             request_size: std::mem::size_of::<Request>(),
             request_init: Some(Request::init),
             request_cleanup: Some(Request::cleanup),
@@ -114,12 +203,42 @@ impl Context {
 
         Ok(Arc::new(Context {
             handle: unsafe { handle.assume_init() },
+            owned: true,
         }))
     }
 
+    /// Wraps a UCP context created outside of this crate, e.g. by an MPI
+    /// implementation in the same process, so both can share it.
+    ///
+    /// The context is cleaned up on drop only if `owned` is `true`.
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be a valid context that outlives the returned one and
+    /// every object created from it. It must have been initialized like
+    /// [`Context::new_with_config`] does: the features this crate uses
+    /// (`RMA`, `TAG`, `STREAM`, `WAKEUP`, and `AM` with the `am` feature)
+    /// enabled, and `request_size`, `request_init` and `request_cleanup` set
+    /// so that every request carries the crate's request data.
+    pub unsafe fn from_raw(handle: ucp_context_h, owned: bool) -> Arc<Self> {
+        Arc::new(Context { handle, owned })
+    }
+
+    /// Returns the underlying UCX context handle.
+    ///
+    /// The handle is valid only while this context is alive.
+    pub fn as_raw(&self) -> ucp_context_h {
+        self.handle
+    }
+
     /// Create a `Worker` object.
     pub fn create_worker(self: &Arc<Self>) -> Result<Rc<Worker>, Error> {
-        Worker::new(self)
+        self.worker_builder().build()
+    }
+
+    /// Create a `Worker` with non-default parameters.
+    pub fn worker_builder(self: &Arc<Self>) -> WorkerBuilder {
+        WorkerBuilder::new(self)
     }
 
     /// Prints information about the context configuration.
@@ -131,43 +250,93 @@ impl Context {
     }
 
     /// Fetches information about the context.
-    pub fn query(&self) -> Result<ucp_context_attr, Error> {
+    pub fn query(&self) -> Result<ContextAttr, Error> {
         #[allow(invalid_value)]
         #[allow(clippy::uninit_assumed_init)]
         let mut attr = ucp_context_attr {
             field_mask: (ucp_context_attr_field::UCP_ATTR_FIELD_REQUEST_SIZE
-                | ucp_context_attr_field::UCP_ATTR_FIELD_THREAD_MODE)
+                | ucp_context_attr_field::UCP_ATTR_FIELD_THREAD_MODE
+                | ucp_context_attr_field::UCP_ATTR_FIELD_MEMORY_TYPES)
                 .0 as u64,
             ..unsafe { MaybeUninit::uninit().assume_init() }
         };
         let status = unsafe { ucp_context_query(self.handle, &mut attr) };
         Error::from_status(status)?;
 
-        Ok(attr)
+        Ok(ContextAttr {
+            request_size: attr.request_size as usize,
+            thread_mode: ThreadMode::from_raw(attr.thread_mode),
+            memory_types: attr.memory_types,
+        })
+    }
+
+    /// Size of the UCP data stored before a request allocated by the application.
+    ///
+    /// This is used to size the slots of a [`RequestPool`].
+    pub fn request_size(&self) -> Result<usize, Error> {
+        Ok(self.query()?.request_size)
     }
 }
 
+/// Attributes of a [`Context`] negotiated by UCX.
+#[derive(Debug, Clone)]
+pub struct ContextAttr {
+    /// Size of the UCP data stored before a request allocated by the application.
+    pub request_size: usize,
+    /// Thread safe level of the context.
+    pub thread_mode: ThreadMode,
+    /// Bitmap of the memory types supported by the context, indexed by `ucs_memory_type_t`.
+    pub memory_types: u64,
+}
+
 impl Drop for Context {
     fn drop(&mut self) {
-        unsafe { ucp_cleanup(self.handle) };
+        if self.owned {
+            unsafe { ucp_cleanup(self.handle) };
+        }
     }
 }
 
 extern "C" {
     static stderr: *mut FILE;
+    fn open_memstream(ptr: *mut *mut c_char, sizeloc: *mut usize) -> *mut FILE;
+    fn fclose(stream: *mut FILE) -> c_int;
+    fn free(ptr: *mut c_void);
+}
+
+/// Call `print` with an in-memory `FILE` stream, then write what it printed to `out`.
+fn print_to_writer(out: &mut impl Write, print: impl FnOnce(*mut FILE)) -> std::io::Result<()> {
+    let mut buf = null_mut();
+    let mut len = 0;
+    let file = unsafe { open_memstream(&mut buf, &mut len) };
+    if file.is_null() {
+        return Err(std::io::Error::last_os_error());
+    }
+    print(file);
+    // closing the stream flushes it and updates `buf` and `len`
+    let ret = unsafe { fclose(file) };
+    let result = if ret == 0 {
+        out.write_all(unsafe { std::slice::from_raw_parts(buf as *const u8, len) })
+    } else {
+        Err(std::io::Error::last_os_error())
+    };
+    unsafe { free(buf as _) };
+    result
 }
 
 /// Our defined request structure stored at `ucs_status_ptr_t`.
 ///
-/// To enable this, set the following fields in `ucp_params_t` when initializing
-/// UCP context:
+/// `Context::new_with_config` sets the following fields in `ucp_params_t`,
+/// so that every request returned by UCX points to an initialized `Request`:
 /// ```ignore
 /// ucp_params_t {
-///     request_size: std::mem::size_of::<Request>() as u64,
+///     request_size: std::mem::size_of::<Request>(),
 ///     request_init: Some(Request::init),
 ///     request_cleanup: Some(Request::cleanup),
 /// }
 /// ```
+/// Requests allocated by the application, see [`RequestPool`], are
+/// initialized the same way.
 #[derive(Default)]
 struct Request {
     waker: AtomicWaker,
@@ -189,3 +358,189 @@ impl Request {
         std::ptr::drop_in_place(request as *mut Self)
     }
 }
+
+// Id correlating the events of one request in the trace spans.
+fn next_request_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Verbosity of UCX logging, see [`set_log_level`].
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Fatal,
+    Error,
+    Warn,
+    Diag,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    // value of the `LOG_LEVEL` option
+    fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Fatal => "fatal",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Diag => "diag",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+/// Set the UCX log level of the process, overriding `UCX_LOG_LEVEL`.
+///
+/// Takes effect right away, e.g. to get debug output around a failing operation.
+pub fn set_log_level(level: LogLevel) -> Result<(), Error> {
+    trace!("set_log_level: {:?}", level);
+    let name = CString::new("LOG_LEVEL").unwrap();
+    let value = CString::new(level.as_str()).unwrap();
+    let status = unsafe { ucs_global_opts_set_value(name.as_ptr(), value.as_ptr()) };
+    Error::from_status(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    #[ignore = "changes the log level of the whole process"]
+    fn set_log_level() {
+        let log_level = || {
+            let name = CString::new("LOG_LEVEL").unwrap();
+            let mut value = [0 as c_char; 32];
+            let status = unsafe {
+                ucs_global_opts_get_value(name.as_ptr(), value.as_mut_ptr(), value.len())
+            };
+            assert_eq!(status, ucs_status_t::UCS_OK);
+            let value = unsafe { std::ffi::CStr::from_ptr(value.as_ptr()) };
+            value.to_str().unwrap().to_string()
+        };
+        let orig = log_level();
+        super::set_log_level(LogLevel::Debug).unwrap();
+        assert_eq!(log_level(), "debug");
+        // UCX keeps working while logging
+        let context = Context::new().unwrap();
+        let _worker = context.create_worker().unwrap();
+        super::set_log_level(LogLevel::Warn).unwrap();
+        assert_eq!(log_level(), "warn");
+
+        let name = CString::new("LOG_LEVEL").unwrap();
+        let orig = CString::new(orig).unwrap();
+        unsafe { ucs_global_opts_set_value(name.as_ptr(), orig.as_ptr()) };
+    }
+
+    #[test_log::test]
+    fn request_size() {
+        let context = Context::new().unwrap();
+        let size = context.request_size().unwrap();
+        assert!(size > 0);
+
+        let pool = RequestPool::new(&context, 1).unwrap();
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[test_log::test]
+    fn print_config() {
+        let config = Config::new().unwrap();
+        let mut out = Vec::new();
+        config.print(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("UCP Configuration"));
+        assert_eq!(config.to_string(), out);
+    }
+
+    #[test_log::test]
+    fn query() {
+        let context = Context::new().unwrap();
+        let attr = context.query().unwrap();
+        assert!(attr.request_size > 0);
+        assert_ne!(
+            attr.memory_types & (1 << ucs_memory_type::UCS_MEMORY_TYPE_HOST as u64),
+            0
+        );
+
+        let worker = context.create_worker().unwrap();
+        let attr = worker.query().unwrap();
+        assert_eq!(attr.thread_mode, worker.thread_mode());
+        assert!(attr.max_info_string > 0);
+        #[cfg(feature = "am")]
+        assert!(worker.max_am_header_len() > 0);
+        #[cfg(feature = "am")]
+        assert_eq!(attr.max_am_header, worker.max_am_header_len());
+    }
+
+    #[test_log::test]
+    fn from_raw() {
+        let context = Context::new().unwrap();
+        let shared = unsafe { Context::from_raw(context.as_raw(), false) };
+        assert_eq!(shared.as_raw(), context.as_raw());
+        let worker = shared.create_worker().unwrap();
+        assert_eq!(worker.query().unwrap().thread_mode, worker.thread_mode());
+        drop(worker);
+        drop(shared);
+
+        // still usable, as the borrowed one didn't clean it up
+        let worker = context.create_worker().unwrap();
+        assert!(worker.address().is_ok());
+    }
+
+    #[test_log::test]
+    fn worker_thread_mode() {
+        let context = Context::new().unwrap();
+        let worker = context.create_worker().unwrap();
+        assert_eq!(worker.thread_mode(), ThreadMode::Single);
+
+        let worker = context
+            .worker_builder()
+            .thread_mode(ThreadMode::Multi)
+            .build()
+            .unwrap();
+        assert_eq!(worker.thread_mode(), ThreadMode::Multi);
+    }
+
+    #[test_log::test]
+    fn concurrent_requests() {
+        spawn_thread!(_concurrent_requests()).join().unwrap();
+    }
+
+    async fn _concurrent_requests() {
+        const COUNT: u64 = 1000;
+        let (_endpoint1, worker1, endpoint2, worker2) = crate::test_util::loopback().await.unwrap();
+
+        // every request wakes only its own future with its own result
+        let sends = (0..COUNT).map(|tag| {
+            let endpoint2 = &endpoint2;
+            async move {
+                let data = vec![tag as u8; 1 + (tag as usize % 7) * 4096];
+                endpoint2.tag_send(tag, &data).await.unwrap()
+            }
+        });
+        let recvs = (0..COUNT).rev().map(|tag| {
+            let worker1 = &worker1;
+            async move {
+                let mut buf = vec![MaybeUninit::uninit(); 32 * 1024];
+                let len = worker1.tag_recv(tag, &mut buf).await.unwrap();
+                let buf: Vec<u8> = buf[..len]
+                    .iter()
+                    .map(|x| unsafe { x.assume_init() })
+                    .collect();
+                assert_eq!(buf, vec![tag as u8; 1 + (tag as usize % 7) * 4096]);
+                len
+            }
+        });
+        let (sent, received) = tokio::join!(
+            futures::future::join_all(sends),
+            futures::future::join_all(recvs)
+        );
+        let received: Vec<_> = received.into_iter().rev().collect();
+        assert_eq!(sent, received);
+        assert_eq!(worker1.inflight_requests(), 0);
+        assert_eq!(worker2.inflight_requests(), 0);
+    }
+}
@@ -0,0 +1,356 @@
+use super::*;
+use futures::channel::oneshot;
+use futures::future::Either;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+// every request and reply header starts with the correlation id
+const CALL_ID_LEN: usize = std::mem::size_of::<u64>();
+
+fn frame(call_id: u64, header: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(CALL_ID_LEN + header.len());
+    framed.extend_from_slice(&call_id.to_le_bytes());
+    framed.extend_from_slice(header);
+    framed
+}
+
+fn call_id(header: &[u8]) -> Option<u64> {
+    let id = header.get(..CALL_ID_LEN)?;
+    Some(u64::from_le_bytes(id.try_into().unwrap()))
+}
+
+/// Reply to an [`RpcClient::call`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcReply {
+    /// Header given by the server.
+    pub header: Vec<u8>,
+    /// Data given by the server.
+    pub data: Vec<u8>,
+}
+
+/// Client side of request/response calls over active messages.
+///
+/// Requests are sent as active messages of `request_id` and the server
+/// replies with `reply_id`. Replies are matched to calls by a correlation id
+/// carried in the header, so calls can be issued concurrently.
+/// The client owns the stream of `reply_id` on its worker,
+/// so each client needs its own `reply_id`.
+pub struct RpcClient<'a> {
+    endpoint: &'a Endpoint,
+    stream: AmStream<'a>,
+    request_id: u16,
+    next_call: Cell<u64>,
+    // a reply is handed over before its data is received, so dropping
+    // another call's future never loses it
+    pending: RefCell<HashMap<u64, oneshot::Sender<AmMsg<'a>>>>,
+}
+
+impl<'a> RpcClient<'a> {
+    /// Create a client calling the server on the other side of `endpoint`.
+    pub fn new(endpoint: &'a Endpoint, request_id: u16, reply_id: u16) -> Result<Self, Error> {
+        Ok(RpcClient {
+            endpoint,
+            stream: endpoint.worker().am_stream(reply_id)?,
+            request_id,
+            next_call: Cell::new(0),
+            pending: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Number of calls waiting for their reply.
+    pub fn pending_calls(&self) -> usize {
+        self.pending.borrow().len()
+    }
+
+    /// Send a request and wait for the reply.
+    ///
+    /// Dropping the future abandons the call, a late reply is discarded.
+    /// Fails with [`Error::Canceled`] if the reply stream is unregistered.
+    pub async fn call(&self, header: &[u8], data: &[u8]) -> Result<RpcReply, Error> {
+        let call_id = self.next_call.get();
+        self.next_call.set(call_id.wrapping_add(1));
+        let (sender, mut reply) = oneshot::channel();
+        self.pending.borrow_mut().insert(call_id, sender);
+        let _pending = PendingCall {
+            client: self,
+            call_id,
+        };
+
+        let header = frame(call_id, header);
+        self.endpoint
+            .am_send_flags(self.request_id as u32, &header, data, AmSendFlags::REPLY)
+            .await?;
+
+        // whichever call is waiting takes the next reply and hands it to its caller
+        let mut msg = loop {
+            let msg =
+                match futures::future::select(&mut reply, Box::pin(self.stream.wait_msg())).await {
+                    Either::Left((reply, _)) => break reply.map_err(|_| Error::Canceled)?,
+                    Either::Right((Some(msg), _)) => msg,
+                    Either::Right((None, _)) => return Err(Error::Canceled),
+                };
+            self.dispatch(msg);
+        };
+        let header = msg.header()[CALL_ID_LEN..].to_vec();
+        let data = msg.recv_data().await?;
+        Ok(RpcReply { header, data })
+    }
+
+    /// Like `call`, except that it fails with [`Error::Timeout`]
+    /// if no reply arrives within `timeout`.
+    #[cfg(feature = "time")]
+    pub async fn call_timeout(
+        &self,
+        header: &[u8],
+        data: &[u8],
+        timeout: Duration,
+    ) -> Result<RpcReply, Error> {
+        tokio::time::timeout(timeout, self.call(header, data))
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    fn dispatch(&self, msg: AmMsg<'a>) {
+        let call_id = match call_id(msg.header()) {
+            Some(call_id) => call_id,
+            None => {
+                warn!("rpc: reply without call id, id={}", msg.id());
+                return;
+            }
+        };
+        match self.pending.borrow_mut().remove(&call_id) {
+            Some(sender) => {
+                let _ = sender.send(msg);
+            }
+            None => trace!("rpc: discard reply of abandoned call {}", call_id),
+        }
+    }
+}
+
+// Removes the pending entry of a finished or dropped call
+struct PendingCall<'a, 'b> {
+    client: &'b RpcClient<'a>,
+    call_id: u64,
+}
+
+impl<'a, 'b> Drop for PendingCall<'a, 'b> {
+    fn drop(&mut self) {
+        self.client.pending.borrow_mut().remove(&self.call_id);
+    }
+}
+
+/// Server side of request/response calls, see [`RpcClient`].
+pub struct RpcServer<'a> {
+    worker: &'a Rc<Worker>,
+    stream: AmStream<'a>,
+    reply_id: u16,
+}
+
+impl<'a> RpcServer<'a> {
+    /// Serve requests of `request_id` on `worker`, replying with `reply_id`.
+    pub fn new(worker: &'a Rc<Worker>, request_id: u16, reply_id: u16) -> Result<Self, Error> {
+        Ok(RpcServer {
+            worker,
+            stream: worker.am_stream(request_id)?,
+            reply_id,
+        })
+    }
+
+    /// Limit the replies sent to one client without a flush,
+    /// see [`Worker::set_am_reply_window`].
+    ///
+    /// This sets the window of the worker, so it applies to every reply sent
+    /// on it, including those of other servers and of [`AmMsg::reply`].
+    pub fn set_reply_window(&self, window: Option<usize>) {
+        self.worker.set_am_reply_window(window);
+    }
+
+    /// Wait for the next request.
+    ///
+    /// Messages that are not requests of an `RpcClient` are dropped.
+    /// Returns `None` if the request stream is unregistered.
+    pub async fn recv(&self) -> Option<RpcRequest<'_>> {
+        loop {
+            let msg = self.stream.wait_msg().await?;
+            match call_id(msg.header()) {
+                Some(call_id) if msg.need_reply() => {
+                    return Some(RpcRequest {
+                        msg,
+                        call_id,
+                        reply_id: self.reply_id,
+                    })
+                }
+                _ => warn!("rpc: drop malformed request, id={}", msg.id()),
+            }
+        }
+    }
+}
+
+/// A request received by [`RpcServer::recv`].
+pub struct RpcRequest<'a> {
+    msg: AmMsg<'a>,
+    call_id: u64,
+    reply_id: u16,
+}
+
+impl<'a> RpcRequest<'a> {
+    /// Header given by the client.
+    pub fn header(&self) -> &[u8] {
+        &self.msg.header()[CALL_ID_LEN..]
+    }
+
+    /// Receive the data given by the client.
+    pub async fn recv_data(&mut self) -> Result<Vec<u8>, Error> {
+        self.msg.recv_data().await
+    }
+
+    /// Send the reply.
+    /// # Safety
+    /// User needs to ensure that the endpoint isn't closed, see [`AmMsg::reply`].
+    pub async unsafe fn reply(&self, header: &[u8], data: &[u8]) -> Result<usize, Error> {
+        let header = frame(self.call_id, header);
+        self.msg
+            .reply(self.reply_id as u32, &header, data, AmSendFlags::empty())
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn concurrent_calls() {
+        spawn_thread!(_concurrent_calls()).join().unwrap();
+    }
+
+    async fn _concurrent_calls() {
+        const N: u64 = 32;
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let server = RpcServer::new(&worker1, 16, 17).unwrap();
+        let client = RpcClient::new(&endpoint2, 16, 17).unwrap();
+
+        // echo requests in reverse order, so replies arrive out of order
+        let serve = async {
+            let mut requests = Vec::new();
+            for _ in 0..N {
+                requests.push(server.recv().await.expect("no request"));
+            }
+            for mut request in requests.into_iter().rev() {
+                let data = request.recv_data().await.unwrap();
+                let header = request.header().to_vec();
+                unsafe { request.reply(&header, &data) }.await.unwrap();
+            }
+        };
+        let calls = (0..N).map(|i| {
+            let client = &client;
+            async move {
+                let header = i.to_le_bytes();
+                let data = vec![i as u8; i as usize * 100];
+                let reply = client.call(&header, &data).await.unwrap();
+                assert_eq!(reply.header, header);
+                assert_eq!(reply.data, data);
+            }
+        });
+        tokio::join!(serve, futures::future::join_all(calls));
+        assert_eq!(client.pending_calls(), 0);
+    }
+
+    #[test_log::test]
+    fn reply_window() {
+        spawn_thread!(_reply_window()).join().unwrap();
+    }
+
+    async fn _reply_window() {
+        const WINDOW: usize = 2;
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let server = RpcServer::new(&worker1, 16, 17).unwrap();
+        server.set_reply_window(Some(WINDOW));
+        let client = RpcClient::new(&endpoint2, 16, 17).unwrap();
+
+        // the client takes replies slowly
+        let serve = async {
+            for _ in 0..5 {
+                let request = server.recv().await.expect("no request");
+                unsafe { request.reply(&[], &[1; 1024]) }.await.unwrap();
+                assert!(worker1.stats().am_unflushed_replies <= WINDOW);
+            }
+        };
+        let calls = async {
+            for _ in 0..5 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                client.call(&[], &[]).await.unwrap();
+            }
+        };
+        tokio::join!(serve, calls);
+        let stats = worker1.stats();
+        assert_eq!(stats.am_reply_flushes, 2);
+        assert_eq!(stats.am_unflushed_replies, 1);
+    }
+
+    #[cfg(feature = "time")]
+    #[test_log::test]
+    fn call_timeout() {
+        spawn_thread!(_call_timeout()).join().unwrap();
+    }
+
+    #[cfg(feature = "time")]
+    async fn _call_timeout() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        // nobody serves the request
+        let _requests = worker1.am_stream(16).unwrap();
+        let client = RpcClient::new(&endpoint2, 16, 17).unwrap();
+        let reply = client
+            .call_timeout(&[], &[], Duration::from_millis(100))
+            .await;
+        assert_eq!(reply, Err(Error::Timeout));
+        assert_eq!(client.pending_calls(), 0);
+    }
+
+    #[cfg(feature = "time")]
+    #[test_log::test]
+    fn abandoned_call() {
+        spawn_thread!(_abandoned_call()).join().unwrap();
+    }
+
+    #[cfg(feature = "time")]
+    async fn _abandoned_call() {
+        let (_endpoint1, worker1, endpoint2, _) = crate::test_util::loopback().await.unwrap();
+
+        let server = RpcServer::new(&worker1, 16, 17).unwrap();
+        let client = RpcClient::new(&endpoint2, 16, 17).unwrap();
+        let mut call1 = Box::pin(client.call(&[1], &[]));
+        let mut call2 = Box::pin(client.call(&[2], &[]));
+        let requests = async { [server.recv().await, server.recv().await] };
+        let requests = match futures::future::select(
+            Box::pin(requests),
+            futures::future::join(&mut call1, &mut call2),
+        )
+        .await
+        {
+            Either::Left((requests, _)) => requests,
+            Either::Right(_) => panic!("calls completed without replies"),
+        };
+
+        // only the second call gets a reply, a rendezvous one
+        let data = vec![2_u8; 1 << 20];
+        let request2 = requests
+            .iter()
+            .flatten()
+            .find(|request| request.header() == [2])
+            .unwrap();
+        unsafe { request2.reply(&[2], &data) }.await.unwrap();
+
+        // the first call takes the reply for the second one, then is dropped
+        let waited = tokio::time::timeout(Duration::from_millis(100), &mut call1).await;
+        assert!(waited.is_err());
+        drop(call1);
+        let reply = call2.await.unwrap();
+        assert_eq!(reply.header, [2]);
+        assert_eq!(reply.data, data);
+        assert_eq!(client.pending_calls(), 0);
+    }
+}
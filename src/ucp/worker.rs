@@ -1,11 +1,20 @@
 use super::*;
 use derivative::*;
+use futures::channel::mpsc;
+#[cfg(feature = "event")]
+use futures::future::Either;
+use futures::Stream;
+use std::cell::{Cell, RefCell};
 #[cfg(feature = "am")]
 use std::collections::HashMap;
+use std::ffi::CStr;
 use std::net::SocketAddr;
 use std::os::unix::io::AsRawFd;
+#[cfg(feature = "event")]
+use std::os::unix::io::BorrowedFd;
 #[cfg(feature = "am")]
 use std::sync::RwLock;
+use std::task::{Poll, Waker};
 #[cfg(feature = "event")]
 use tokio::io::unix::AsyncFd;
 
@@ -14,43 +23,163 @@ use tokio::io::unix::AsyncFd;
 #[derivative(Debug)]
 pub struct Worker {
     pub(super) handle: ucp_worker_h,
-    context: Arc<Context>,
+    pub(super) context: Arc<Context>,
+    pub(crate) inflight: Rc<Inflight>,
+    // bumped by each `progress` call that made progress
+    progress_epoch: Cell<u64>,
+    progress_wakers: RefCell<Vec<Waker>>,
+    #[cfg(feature = "event")]
+    poll_stop: Rc<PollStop>,
+    next_endpoint_id: Cell<u64>,
+    #[derivative(Debug = "ignore")]
+    pub(super) endpoints: EndpointRegistry,
+    #[derivative(Debug = "ignore")]
+    error_subscribers: RefCell<Vec<mpsc::UnboundedSender<(EndpointId, Error)>>>,
     #[cfg(feature = "am")]
     #[derivative(Debug = "ignore")]
     pub(crate) am_streams: RwLock<HashMap<u16, Rc<AmStreamInner>>>,
+    // boxed so UCX can keep a pointer to the handler
+    #[cfg(feature = "am")]
+    #[derivative(Debug = "ignore")]
+    pub(crate) am_inline_handlers: RwLock<HashMap<u16, Rc<AmInlineHandler>>>,
+    // id of the stream last served by `am_recv_any`
+    #[cfg(feature = "am")]
+    pub(crate) am_any_last: Cell<Option<u16>>,
+    #[cfg(feature = "am")]
+    pub(crate) am_counters: Rc<AmCounters>,
+    #[cfg(feature = "am")]
+    pub(crate) am_reply_window: Cell<Option<usize>>,
+    // replies sent to each reply endpoint since it was last flushed
+    #[cfg(feature = "am")]
+    pub(crate) am_unflushed_replies: RefCell<HashMap<usize, usize>>,
+    thread_mode: ThreadMode,
+    #[cfg(feature = "am")]
+    pub(crate) max_am_header: usize,
+}
+
+// Requests posted on a worker and not yet completed or dropped
+#[derive(Debug, Default)]
+pub(crate) struct Inflight {
+    count: Cell<usize>,
+    idle_wakers: RefCell<Vec<Waker>>,
+}
+
+impl Inflight {
+    pub(crate) fn track(self: &Rc<Self>) -> InflightGuard {
+        self.count.set(self.count.get() + 1);
+        InflightGuard(self.clone())
+    }
+}
+
+// Counts a request as in flight until dropped
+#[derive(Debug)]
+pub(crate) struct InflightGuard(Rc<Inflight>);
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        let inflight = &self.0;
+        inflight.count.set(inflight.count.get() - 1);
+        if inflight.count.get() == 0 {
+            for waker in inflight.idle_wakers.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+// Wakes `event_poll` parked on the event fd once the worker is dropped
+#[cfg(feature = "event")]
+#[derive(Debug, Default)]
+struct PollStop {
+    stopped: Cell<bool>,
+    waker: AtomicWaker,
 }
 
 impl Drop for Worker {
     fn drop(&mut self) {
+        // detach handlers before their state is freed
+        #[cfg(feature = "am")]
+        self.am_unregister_all();
+        #[cfg(feature = "event")]
+        {
+            self.poll_stop.stopped.set(true);
+            self.poll_stop.waker.wake();
+        }
         unsafe { ucp_worker_destroy(self.handle) }
     }
 }
 
 impl Worker {
-    pub(super) fn new(context: &Arc<Context>) -> Result<Rc<Self>, Error> {
+    pub(super) fn new(
+        context: &Arc<Context>,
+        thread_mode: ThreadMode,
+        name: Option<&CStr>,
+    ) -> Result<Rc<Self>, Error> {
         let mut params = MaybeUninit::<ucp_worker_params_t>::uninit();
         unsafe {
             (*params.as_mut_ptr()).field_mask =
                 ucp_worker_params_field::UCP_WORKER_PARAM_FIELD_THREAD_MODE.0 as _;
-            (*params.as_mut_ptr()).thread_mode = ucs_thread_mode_t::UCS_THREAD_MODE_SINGLE;
+            (*params.as_mut_ptr()).thread_mode = thread_mode.into();
+            if let Some(name) = name {
+                (*params.as_mut_ptr()).field_mask |=
+                    ucp_worker_params_field::UCP_WORKER_PARAM_FIELD_NAME.0 as u64;
+                (*params.as_mut_ptr()).name = name.as_ptr();
+            }
         };
         let mut handle = MaybeUninit::uninit();
         let status =
             unsafe { ucp_worker_create(context.handle, params.as_ptr(), handle.as_mut_ptr()) };
         Error::from_status(status)?;
+        let handle = unsafe { handle.assume_init() };
+
+        // cached, e.g. to check headers before sending
+        let attr = match Self::query_handle(handle) {
+            Ok(attr) => attr,
+            Err(err) => {
+                unsafe { ucp_worker_destroy(handle) };
+                return Err(err);
+            }
+        };
 
         Ok(Rc::new(Worker {
-            handle: unsafe { handle.assume_init() },
+            handle,
             context: context.clone(),
+            inflight: Rc::default(),
+            progress_epoch: Cell::new(0),
+            progress_wakers: RefCell::default(),
+            #[cfg(feature = "event")]
+            poll_stop: Rc::default(),
+            next_endpoint_id: Cell::new(0),
+            endpoints: EndpointRegistry::default(),
+            error_subscribers: RefCell::default(),
             #[cfg(feature = "am")]
             am_streams: RwLock::new(HashMap::new()),
+            #[cfg(feature = "am")]
+            am_inline_handlers: RwLock::new(HashMap::new()),
+            #[cfg(feature = "am")]
+            am_any_last: Cell::new(None),
+            #[cfg(feature = "am")]
+            am_counters: Rc::default(),
+            #[cfg(feature = "am")]
+            am_reply_window: Cell::new(None),
+            #[cfg(feature = "am")]
+            am_unflushed_replies: RefCell::default(),
+            thread_mode: attr.thread_mode,
+            #[cfg(feature = "am")]
+            max_am_header: attr.max_am_header,
         }))
     }
 
     /// Make progress on the worker.
+    ///
+    /// Only a weak reference is kept while yielding, so the loop ends
+    /// once all other clones of the worker are dropped.
     pub async fn polling(self: Rc<Self>) {
-        while Rc::strong_count(&self) > 1 {
-            while self.progress() != 0 {}
+        let weak = Rc::downgrade(&self);
+        drop(self);
+        while let Some(worker) = weak.upgrade() {
+            while worker.progress() != 0 {}
+            drop(worker);
             futures_lite::future::yield_now().await;
         }
     }
@@ -60,14 +189,42 @@ impl Worker {
     /// This function register `event_fd` on tokio's event loop and wait `event_fd` become readable,
     ////  then call progress function.
     #[cfg(feature = "event")]
+    ///
+    /// Each round drains the worker with `progress` until it returns 0, then
+    /// arms it and only sleeps on the fd if arming succeeded; if events
+    /// arrived meanwhile it progresses again instead. So no wakeup is missed
+    /// and an idle worker doesn't spin.
+    ///
+    /// Like `polling`, it ends once all other clones of the worker are dropped.
+    /// Fails if the worker can't be armed.
     pub async fn event_poll(self: Rc<Self>) -> Result<(), Error> {
-        let fd = self.event_fd()?;
+        // a duplicate stays valid after `ucp_worker_destroy` closes the original
+        let fd = unsafe { BorrowedFd::borrow_raw(self.event_fd()?) }
+            .try_clone_to_owned()
+            .map_err(|_| Error::IoError)?;
         let wait_fd = AsyncFd::new(fd).unwrap();
-        while Rc::strong_count(&self) > 1 {
-            while self.progress() != 0 {}
-            if self.arm().unwrap() {
-                let mut ready = wait_fd.readable().await.unwrap();
-                ready.clear_ready();
+        let stop = self.poll_stop.clone();
+        let weak = Rc::downgrade(&self);
+        drop(self);
+        while let Some(worker) = weak.upgrade() {
+            while worker.progress() != 0 {}
+            let armed = worker.arm()?;
+            drop(worker);
+            if armed {
+                let stopped = futures::future::poll_fn(|cx| {
+                    stop.waker.register(cx.waker());
+                    if stop.stopped.get() {
+                        Poll::Ready(())
+                    } else {
+                        Poll::Pending
+                    }
+                });
+                let readable = wait_fd.readable();
+                futures::pin_mut!(stopped, readable);
+                if let Either::Right((ready, _)) = futures::future::select(stopped, readable).await
+                {
+                    ready.unwrap().clear_ready();
+                }
             }
         }
 
@@ -82,15 +239,76 @@ impl Worker {
         unsafe { ucp_worker_print_info(self.handle, stderr) };
     }
 
+    /// Like `print_to_stderr`, except that it writes to `out`.
+    pub fn print_info(&self, out: &mut impl Write) -> std::io::Result<()> {
+        print_to_writer(out, |file| unsafe {
+            ucp_worker_print_info(self.handle, file)
+        })
+    }
+
     /// Thread safe level of the context.
-    pub fn thread_mode(&self) -> ucs_thread_mode_t {
+    pub fn thread_mode(&self) -> ThreadMode {
+        self.thread_mode
+    }
+
+    /// Number of requests posted on this worker and its endpoints
+    /// that are not completed yet.
+    ///
+    /// Requests are counted until their future resolves or is dropped.
+    pub fn inflight_requests(&self) -> usize {
+        self.inflight.count.get()
+    }
+
+    /// Wait until no request is in flight, e.g. before shutting down.
+    ///
+    /// The worker must be progressed meanwhile.
+    pub async fn wait_idle(&self) {
+        futures::future::poll_fn(|cx| {
+            if self.inflight.count.get() == 0 {
+                return Poll::Ready(());
+            }
+            let mut wakers = self.inflight.idle_wakers.borrow_mut();
+            if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Fetches information about the worker.
+    pub fn query(&self) -> Result<WorkerAttr, Error> {
+        Self::query_handle(self.handle)
+    }
+
+    fn query_handle(handle: ucp_worker_h) -> Result<WorkerAttr, Error> {
         let mut attr = MaybeUninit::<ucp_worker_attr>::uninit();
         unsafe { &mut *attr.as_mut_ptr() }.field_mask =
-            ucp_worker_attr_field::UCP_WORKER_ATTR_FIELD_THREAD_MODE.0 as u64;
-        let status = unsafe { ucp_worker_query(self.handle, attr.as_mut_ptr()) };
-        assert_eq!(status, ucs_status_t::UCS_OK);
+            (ucp_worker_attr_field::UCP_WORKER_ATTR_FIELD_THREAD_MODE
+                | ucp_worker_attr_field::UCP_WORKER_ATTR_FIELD_MAX_AM_HEADER
+                | ucp_worker_attr_field::UCP_WORKER_ATTR_FIELD_MAX_INFO_STRING
+                | ucp_worker_attr_field::UCP_WORKER_ATTR_FIELD_NAME)
+                .0 as u64;
+        let status = unsafe { ucp_worker_query(handle, attr.as_mut_ptr()) };
+        Error::from_status(status)?;
         let attr = unsafe { attr.assume_init() };
-        attr.thread_mode
+
+        Ok(WorkerAttr {
+            thread_mode: ThreadMode::from_raw(attr.thread_mode),
+            max_am_header: attr.max_am_header as usize,
+            max_info_string: attr.max_debug_string as usize,
+            name: unsafe { CStr::from_ptr(attr.name.as_ptr()) }
+                .to_string_lossy()
+                .into_owned(),
+        })
+    }
+
+    /// Maximal header length of an active message.
+    ///
+    /// Sending a longer header fails with [`Error::HeaderTooLarge`].
+    #[cfg(feature = "am")]
+    pub fn max_am_header_len(&self) -> usize {
+        self.max_am_header
     }
 
     /// Get the address of the worker object.
@@ -114,7 +332,12 @@ impl Worker {
 
     /// Create a new [`Listener`].
     pub fn create_listener(self: &Rc<Self>, addr: SocketAddr) -> Result<Listener, Error> {
-        Listener::new(self, addr)
+        self.listener_builder().bind(addr).build()
+    }
+
+    /// Create a `Listener` with non-default parameters.
+    pub fn listener_builder(self: &Rc<Self>) -> ListenerBuilder<'_> {
+        ListenerBuilder::new(self)
     }
 
     /// Connect to a remote worker by address.
@@ -124,14 +347,98 @@ impl Worker {
 
     /// Connect to a remote listener.
     pub async fn connect_socket(self: &Rc<Self>, addr: SocketAddr) -> Result<Endpoint, Error> {
-        Endpoint::connect_socket(self, addr).await
+        self.endpoint_builder().connect(addr).build().await
+    }
+
+    /// Create an `Endpoint` with non-default parameters.
+    pub fn endpoint_builder(self: &Rc<Self>) -> EndpointBuilder<'_> {
+        EndpointBuilder::new(self)
+    }
+
+    /// Connect to a remote listener, failing with [`Error::Timeout`]
+    /// if the connection isn't established within `timeout`.
+    ///
+    /// The half-open endpoint is force closed on timeout.
+    #[cfg(feature = "time")]
+    pub async fn connect_timeout(
+        self: &Rc<Self>,
+        addr: SocketAddr,
+        timeout: std::time::Duration,
+    ) -> Result<Endpoint, Error> {
+        tokio::time::timeout(timeout, self.connect_socket(addr))
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Connect to a remote listener, retrying up to `attempts` times,
+    /// e.g. while the peer is still starting up.
+    ///
+    /// The delay between attempts starts at `backoff` and doubles after each
+    /// failure. Each attempt is given as long as the delay before the next
+    /// one, see [`connect_timeout`](Self::connect_timeout).
+    /// Returns the error of the last attempt if all of them fail.
+    #[cfg(feature = "time")]
+    pub async fn connect_retry(
+        self: &Rc<Self>,
+        addr: SocketAddr,
+        attempts: u32,
+        backoff: std::time::Duration,
+    ) -> Result<Endpoint, Error> {
+        let mut delay = backoff;
+        let mut result = Err(Error::InvalidParam);
+        for attempt in 1..=attempts {
+            let start = tokio::time::Instant::now();
+            result = self.connect_timeout(addr, delay).await;
+            match &result {
+                Ok(_) => break,
+                Err(err) => trace!(
+                    "connect_retry: addr={}, attempt={}, err={:?}",
+                    addr,
+                    attempt,
+                    err
+                ),
+            }
+            if attempt < attempts {
+                tokio::time::sleep_until(start + delay).await;
+                delay *= 2;
+            }
+        }
+        result
     }
 
     /// Accept a connection request.
+    ///
+    /// On failure the request is rejected, so connecting fails on the client.
     pub async fn accept(self: &Rc<Self>, connection: ConnectionRequest) -> Result<Endpoint, Error> {
         Endpoint::accept(self, connection).await
     }
 
+    /// Returns a stream of the errors happening on the endpoints of this worker,
+    /// with the id of the failed endpoint, e.g. when a peer dies.
+    ///
+    /// Only errors after the call are yielded. Closing an endpoint locally
+    /// is not reported.
+    pub fn error_events(&self) -> impl Stream<Item = (EndpointId, Error)> {
+        let (sender, recver) = mpsc::unbounded();
+        self.error_subscribers.borrow_mut().push(sender);
+        recver
+    }
+
+    pub(super) fn next_endpoint_id(&self) -> EndpointId {
+        let id = self.next_endpoint_id.get();
+        self.next_endpoint_id.set(id + 1);
+        EndpointId(id)
+    }
+
+    // called from the error handler of the endpoint `id`
+    pub(super) fn report_error(&self, id: EndpointId, status: ucs_status_t) {
+        self.error_subscribers.borrow_mut().retain(|sender| {
+            sender
+                .unbounded_send((id, Error::from_error(status)))
+                .is_ok()
+        });
+    }
+
     /// Waits (blocking) until an event has happened.
     pub fn wait(&self) -> Result<(), Error> {
         let status = unsafe { ucp_worker_wait(self.handle) };
@@ -150,9 +457,66 @@ impl Worker {
         }
     }
 
+    /// Returns the underlying UCX worker handle, for calling `ucx1-sys`
+    /// functions this crate doesn't wrap.
+    ///
+    /// The handle is valid only while this worker is alive. Keeping raw calls
+    /// consistent with the crate's own, e.g. not completing or releasing
+    /// requests the crate is waiting on, is up to the caller.
+    pub fn as_raw(&self) -> ucp_worker_h {
+        self.handle
+    }
+
     /// Explicitly progresses all communication operations on a worker.
     pub fn progress(&self) -> u32 {
-        unsafe { ucp_worker_progress(self.handle) }
+        let count = unsafe { ucp_worker_progress(self.handle) };
+        if count != 0 {
+            self.progress_epoch.set(self.progress_epoch.get() + 1);
+            for waker in self.progress_wakers.take() {
+                waker.wake();
+            }
+        }
+        count
+    }
+
+    // Wait until a later `progress` call makes progress, so that operations
+    // without a completion callback, like probes, can be retried.
+    pub(crate) async fn wait_progress(&self) {
+        let epoch = self.progress_epoch.get();
+        futures::future::poll_fn(|cx| {
+            if self.progress_epoch.get() != epoch {
+                return Poll::Ready(());
+            }
+            let mut wakers = self.progress_wakers.borrow_mut();
+            if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Progresses the worker until `cond` holds, at most `max_spins` times.
+    ///
+    /// `cond` is checked before each progress. Returns whether it was met.
+    /// Meant for manual progress loops and synchronous harnesses without a
+    /// [`polling`](Self::polling) task.
+    pub fn progress_until<F: Fn() -> bool>(&self, cond: F, max_spins: usize) -> bool {
+        for _ in 0..max_spins {
+            if cond() {
+                return true;
+            }
+            self.progress();
+        }
+        cond()
+    }
+
+    /// Wakes the worker from [`wait`](Self::wait), or makes the event fd
+    /// readable so that [`event_poll`](Self::event_poll) wakes up,
+    /// e.g. to shut it down.
+    pub fn signal(&self) -> Result<(), Error> {
+        let status = unsafe { ucp_worker_signal(self.handle) };
+        Error::from_status(status)
     }
 
     /// Returns a valid file descriptor for polling functions.
@@ -169,6 +533,17 @@ impl Worker {
         let status = unsafe { ucp_worker_flush(self.handle) };
         assert_eq!(status, ucs_status_t::UCS_OK);
     }
+
+    /// Order the AMO and RMA operations issued on the worker.
+    ///
+    /// Operations issued after the fence are applied at each target only after
+    /// those issued before it, e.g. a `get` following a `put` to the same
+    /// address reads the put data. Unlike `flush`, it doesn't wait for anything
+    /// to complete, nor make the earlier operations visible remotely by itself.
+    pub fn fence(&self) -> Result<(), Error> {
+        let status = unsafe { ucp_worker_fence(self.handle) };
+        Error::from_status(status)
+    }
 }
 
 impl AsRawFd for Worker {
@@ -177,6 +552,102 @@ impl AsRawFd for Worker {
     }
 }
 
+/// Thread safe level of a [`Worker`] or [`Context`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadMode {
+    /// Only the thread that created it may access it.
+    Single,
+    /// Any thread may access it, one at a time.
+    Serialized,
+    /// Any thread may access it concurrently.
+    Multi,
+}
+
+impl From<ThreadMode> for ucs_thread_mode_t {
+    fn from(mode: ThreadMode) -> Self {
+        match mode {
+            ThreadMode::Single => ucs_thread_mode_t::UCS_THREAD_MODE_SINGLE,
+            ThreadMode::Serialized => ucs_thread_mode_t::UCS_THREAD_MODE_SERIALIZED,
+            ThreadMode::Multi => ucs_thread_mode_t::UCS_THREAD_MODE_MULTI,
+        }
+    }
+}
+
+impl ThreadMode {
+    pub(super) fn from_raw(mode: ucs_thread_mode_t) -> Self {
+        match mode {
+            ucs_thread_mode_t::UCS_THREAD_MODE_SINGLE => ThreadMode::Single,
+            ucs_thread_mode_t::UCS_THREAD_MODE_SERIALIZED => ThreadMode::Serialized,
+            // UCX never reports `UCS_THREAD_MODE_LAST`
+            _ => ThreadMode::Multi,
+        }
+    }
+}
+
+/// Builder of a [`Worker`], created by [`Context::worker_builder`].
+#[derive(Debug)]
+pub struct WorkerBuilder {
+    context: Arc<Context>,
+    thread_mode: ThreadMode,
+    name: Option<String>,
+}
+
+impl WorkerBuilder {
+    pub(super) fn new(context: &Arc<Context>) -> Self {
+        WorkerBuilder {
+            context: context.clone(),
+            thread_mode: ThreadMode::Single,
+            name: None,
+        }
+    }
+
+    /// Thread safe level of the worker, [`ThreadMode::Single`] by default.
+    ///
+    /// UCX may grant a lower level than requested, check it with [`Worker::thread_mode`].
+    /// `Worker` stays `!Send` and `!Sync` whatever the mode, as its requests and
+    /// endpoints are tracked with `Rc` and `RefCell`, so this only matters to
+    /// code sharing the UCX worker outside of this crate. Multi-threaded
+    /// runtimes should run one worker per thread instead, e.g. on a `LocalSet`.
+    pub fn thread_mode(mut self, thread_mode: ThreadMode) -> Self {
+        self.thread_mode = thread_mode;
+        self
+    }
+
+    /// Name of the worker shown in UCX logs, `print_to_stderr` and [`Worker::query`].
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Create the `Worker`.
+    ///
+    /// Fails with [`Error::InvalidParam`] if the name contains a nul byte.
+    pub fn build(self) -> Result<Rc<Worker>, Error> {
+        let name = match self.name {
+            Some(name) => Some(CString::new(name).map_err(|_| Error::InvalidParam)?),
+            None => None,
+        };
+        Worker::new(&self.context, self.thread_mode, name.as_deref())
+    }
+}
+
+/// Attributes of a [`Worker`], including its size limits.
+///
+/// UCP doesn't expose the short and bcopy thresholds of the underlying
+/// transports, they are chosen per protocol and can be inspected with
+/// [`Worker::print_to_stderr`].
+#[derive(Debug, Clone)]
+pub struct WorkerAttr {
+    /// Thread safe level of the worker.
+    pub thread_mode: ThreadMode,
+    /// Maximal header length of an active message.
+    pub max_am_header: usize,
+    /// Maximal length of the string printed for a request.
+    pub max_info_string: usize,
+    /// Name of the worker, generated by UCX unless given to [`WorkerBuilder::name`].
+    pub name: String,
+}
+
 /// The address of the worker object.
 #[derive(Debug)]
 pub struct WorkerAddress<'a> {
@@ -220,3 +691,215 @@ impl WorkerAddressRef for WorkerAddress<'_> {
         self.handle
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn polling_stops() {
+        spawn_thread!(_polling_stops()).join().unwrap();
+    }
+
+    async fn _polling_stops() {
+        let context = Context::new().unwrap();
+        let worker = context.create_worker().unwrap();
+        let polling = tokio::task::spawn_local(worker.clone().polling());
+        tokio::task::yield_now().await;
+        assert!(!polling.is_finished());
+
+        drop(worker);
+        polling.await.unwrap();
+    }
+
+    #[test]
+    fn name() {
+        let context = Context::new().unwrap();
+        let worker = context
+            .worker_builder()
+            .name("test-worker")
+            .build()
+            .unwrap();
+        assert_eq!(worker.query().unwrap().name, "test-worker");
+
+        let worker = context.create_worker().unwrap();
+        assert!(!worker.query().unwrap().name.is_empty());
+        assert_eq!(
+            context.worker_builder().name("a\0b").build().err(),
+            Some(Error::InvalidParam)
+        );
+    }
+
+    #[test]
+    fn print_info() {
+        let context = Context::new().unwrap();
+        let worker = context.worker_builder().name("print-info").build().unwrap();
+        let mut out = Vec::new();
+        worker.print_info(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("UCP worker"), "{}", out);
+        assert!(out.contains("print-info"), "{}", out);
+    }
+
+    #[test_log::test]
+    fn progress_until() {
+        use futures::task::noop_waker;
+        use std::cell::RefCell;
+        use std::future::Future;
+        use std::task::{Context as TaskContext, Poll};
+
+        let context = Context::new().unwrap();
+        let worker = context.create_worker().unwrap();
+        let endpoint = worker.connect_addr(&worker.address().unwrap()).unwrap();
+
+        // drive both futures by hand, nothing else polls the worker
+        let mut buf = [MaybeUninit::uninit(); 5];
+        let send = RefCell::new(Box::pin(endpoint.tag_send(1, b"hello")));
+        let recv = RefCell::new(Box::pin(worker.tag_recv(1, &mut buf)));
+        let (sent, received) = (RefCell::new(None), RefCell::new(None));
+        let waker = noop_waker();
+        let done = worker.progress_until(
+            || {
+                let mut cx = TaskContext::from_waker(&waker);
+                if sent.borrow().is_none() {
+                    if let Poll::Ready(ret) = send.borrow_mut().as_mut().poll(&mut cx) {
+                        *sent.borrow_mut() = Some(ret);
+                    }
+                }
+                if received.borrow().is_none() {
+                    if let Poll::Ready(ret) = recv.borrow_mut().as_mut().poll(&mut cx) {
+                        *received.borrow_mut() = Some(ret);
+                    }
+                }
+                sent.borrow().is_some() && received.borrow().is_some()
+            },
+            1_000_000,
+        );
+        assert!(done);
+        assert_eq!(sent.into_inner(), Some(Ok(5)));
+        assert_eq!(received.into_inner(), Some(Ok(5)));
+        drop(recv);
+        let buf: Vec<u8> = buf.iter().map(|x| unsafe { x.assume_init() }).collect();
+        assert_eq!(buf, b"hello");
+
+        assert!(!worker.progress_until(|| false, 10));
+    }
+
+    #[test]
+    fn as_raw() {
+        let context = Context::new().unwrap();
+        let worker = context.worker_builder().name("raw").build().unwrap();
+        let mut attr = MaybeUninit::<ucp_worker_attr>::uninit();
+        unsafe { &mut *attr.as_mut_ptr() }.field_mask =
+            ucp_worker_attr_field::UCP_WORKER_ATTR_FIELD_NAME.0 as u64;
+        let status = unsafe { ucp_worker_query(worker.as_raw(), attr.as_mut_ptr()) };
+        assert_eq!(Error::from_status(status), Ok(()));
+        let name = unsafe { CStr::from_ptr(attr.assume_init().name.as_ptr()) };
+        assert_eq!(name.to_str(), Ok("raw"));
+
+        let endpoint = worker.connect_addr(&worker.address().unwrap()).unwrap();
+        assert!(!endpoint.as_raw().is_null());
+        let status = unsafe { ucp_ep_flush(endpoint.as_raw()) };
+        assert_eq!(Error::from_status(status), Ok(()));
+    }
+
+    #[cfg(feature = "event")]
+    #[test_log::test]
+    fn event_poll_stops() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let local = tokio::task::LocalSet::new();
+        local.block_on(&rt, async {
+            let context = Context::new().unwrap();
+            let worker = context.create_worker().unwrap();
+            let polling = tokio::task::spawn_local(worker.clone().event_poll());
+            tokio::task::yield_now().await;
+            assert!(!polling.is_finished());
+
+            // nothing to progress, so it is parked on the event fd
+            drop(worker);
+            polling.await.unwrap().unwrap();
+        });
+    }
+
+    // CPU time used by the calling thread
+    #[cfg(feature = "event")]
+    fn thread_cpu_time() -> Duration {
+        let stat = std::fs::read_to_string("/proc/thread-self/stat").unwrap();
+        // fields after the parenthesized command, utime and stime are 14th and 15th
+        let fields: Vec<&str> = stat
+            .rsplit(')')
+            .next()
+            .unwrap()
+            .split_whitespace()
+            .collect();
+        let ticks: u64 = fields[11].parse::<u64>().unwrap() + fields[12].parse::<u64>().unwrap();
+        // USER_HZ is 100 on Linux
+        Duration::from_millis(ticks * 10)
+    }
+
+    #[cfg(feature = "event")]
+    #[test_log::test]
+    fn event_poll_idle() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let local = tokio::task::LocalSet::new();
+        local.block_on(&rt, async {
+            let context = Context::new().unwrap();
+            let worker = context.create_worker().unwrap();
+            tokio::task::spawn_local(worker.clone().event_poll());
+            tokio::task::yield_now().await;
+
+            // sleeping on the fd, not spinning
+            let start = thread_cpu_time();
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            let used = thread_cpu_time() - start;
+            assert!(
+                used < Duration::from_millis(100),
+                "{:?} used while idle",
+                used
+            );
+
+            // still woken up by events
+            let endpoint = worker.connect_addr(&worker.address().unwrap()).unwrap();
+            let mut buf = [MaybeUninit::uninit(); 1];
+            let (sent, received) =
+                tokio::join!(endpoint.tag_send(1, &[1]), worker.tag_recv(1, &mut buf));
+            assert_eq!(sent, Ok(1));
+            assert_eq!(received, Ok(1));
+        });
+    }
+
+    #[cfg(feature = "event")]
+    #[test_log::test]
+    fn signal() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let local = tokio::task::LocalSet::new();
+        local.block_on(&rt, async {
+            let context = Context::new().unwrap();
+            let worker = context.create_worker().unwrap();
+            let fd = unsafe { BorrowedFd::borrow_raw(worker.event_fd().unwrap()) }
+                .try_clone_to_owned()
+                .unwrap();
+            let wait_fd = AsyncFd::new(fd).unwrap();
+            while worker.progress() != 0 {}
+            assert!(worker.arm().unwrap());
+
+            // nothing happens on its own
+            let readable = tokio::time::timeout(Duration::from_millis(100), wait_fd.readable());
+            assert!(readable.await.is_err());
+
+            let signaler = worker.clone();
+            tokio::task::spawn_local(async move { signaler.signal().unwrap() });
+            let readable = tokio::time::timeout(Duration::from_secs(5), wait_fd.readable());
+            readable.await.expect("not woken").unwrap().clear_ready();
+        });
+    }
+}
@@ -0,0 +1,150 @@
+use super::*;
+use std::cell::Cell;
+use std::net::SocketAddr;
+
+/// A set of workers created from one [`Context`].
+///
+/// New connections are spread over the workers round-robin, so progress
+/// of the connections is spread too. Each worker still needs its own
+/// [`Worker::polling`] task, see [`WorkerPool::workers`].
+#[derive(Debug)]
+pub struct WorkerPool {
+    workers: Vec<Rc<Worker>>,
+    next: Cell<usize>,
+}
+
+impl WorkerPool {
+    /// Create a pool of `size` workers.
+    pub fn new(context: &Arc<Context>, size: usize) -> Result<Self, Error> {
+        if size == 0 {
+            return Err(Error::InvalidParam);
+        }
+        let workers = (0..size)
+            .map(|_| context.create_worker())
+            .collect::<Result<_, _>>()?;
+        Ok(WorkerPool {
+            workers,
+            next: Cell::new(0),
+        })
+    }
+
+    /// Workers of the pool.
+    pub fn workers(&self) -> &[Rc<Worker>] {
+        &self.workers
+    }
+
+    /// Number of workers in the pool.
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Whether the pool has no worker, which never holds.
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+
+    /// Pick the worker for the next connection.
+    pub fn next_worker(&self) -> &Rc<Worker> {
+        let next = self.next.get();
+        self.next.set((next + 1) % self.workers.len());
+        &self.workers[next]
+    }
+
+    /// Connect to a remote listener from the next worker.
+    pub async fn connect_socket(&self, addr: SocketAddr) -> Result<Endpoint, Error> {
+        self.next_worker().connect_socket(addr).await
+    }
+
+    /// Accept a connection request on the next worker.
+    pub async fn accept(&self, connection: ConnectionRequest) -> Result<Endpoint, Error> {
+        self.next_worker().accept(connection).await
+    }
+
+    /// Register active message stream for `id` on all workers.
+    #[cfg(feature = "am")]
+    pub fn am_stream(&self, id: u16) -> Result<Vec<AmStream<'_>>, Error> {
+        self.workers
+            .iter()
+            .map(|worker| worker.am_stream(id))
+            .collect()
+    }
+
+    /// Wait active message of any registered stream on any worker.
+    ///
+    /// Returns `None` once no worker has a registered stream.
+    #[cfg(feature = "am")]
+    pub async fn am_recv_any(&self) -> Option<AmMsg<'_>> {
+        let mut pending: Vec<_> = self
+            .workers
+            .iter()
+            .map(|worker| Box::pin(worker.am_recv_any()))
+            .collect();
+        while !pending.is_empty() {
+            let (msg, _, rest) = futures::future::select_all(pending).await;
+            if msg.is_some() {
+                return msg;
+            }
+            pending = rest;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_log::test]
+    fn worker_pool() {
+        spawn_thread!(_worker_pool()).join().unwrap();
+    }
+
+    async fn _worker_pool() {
+        let context1 = Context::new().unwrap();
+        let pool = WorkerPool::new(&context1, 2).unwrap();
+        for worker in pool.workers() {
+            tokio::task::spawn_local(worker.clone().polling());
+        }
+        let context2 = Context::new().unwrap();
+        let worker2 = context2.create_worker().unwrap();
+        tokio::task::spawn_local(worker2.clone().polling());
+
+        let mut listener = pool.workers()[0]
+            .create_listener("0.0.0.0:0".parse().unwrap())
+            .unwrap();
+        let listen_port = listener.socket_addr().unwrap().port();
+        let mut addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        addr.set_port(listen_port);
+
+        // 4 connections spread over 2 workers
+        let mut endpoints = Vec::new();
+        for _ in 0..4 {
+            let (server, client) = tokio::join!(
+                async {
+                    let conn = listener.next().await;
+                    pool.accept(conn).await.unwrap()
+                },
+                async { worker2.connect_socket(addr).await.unwrap() },
+            );
+            endpoints.push((server, client));
+        }
+        for (i, (server, _)) in endpoints.iter().enumerate() {
+            assert!(Rc::ptr_eq(server.worker(), &pool.workers()[i % 2]));
+        }
+
+        #[cfg(feature = "am")]
+        {
+            let _streams = pool.am_stream(16).unwrap();
+            for (i, (_, client)) in endpoints.iter().enumerate() {
+                client.am_send(16, &[i as u8], &[]).await.unwrap();
+            }
+            let mut received = Vec::new();
+            for _ in 0..4 {
+                let msg = pool.am_recv_any().await.expect("no msg");
+                received.push(msg.header()[0]);
+            }
+            received.sort_unstable();
+            assert_eq!(received, [0, 1, 2, 3]);
+        }
+    }
+}